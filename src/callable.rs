@@ -1,11 +1,23 @@
 use std::fmt::Debug;
 
-use crate::error::Error;
+use crate::error::LoxError;
+use crate::gc::Tracer;
 use crate::interpreter::Interpreter;
 use crate::lox_value::LoxValue;
 
 pub trait Callable: Debug {
     fn name(&self) -> String;
     fn arity(&self) -> usize;
-    fn call(&self, interpreter: &mut Interpreter, args: Vec<LoxValue>) -> Result<LoxValue, Error>;
+    // Class the callable is a method of, used to label stack frames.
+    fn class_name(&self) -> Option<String> {
+        None
+    }
+    // Reports the objects the callable holds on to so that the collector
+    // can reclaim cycles going through it.
+    fn trace(&self, _tracer: &mut Tracer) {}
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        args: Vec<LoxValue>,
+    ) -> Result<LoxValue, LoxError>;
 }
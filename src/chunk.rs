@@ -0,0 +1,147 @@
+use std::fmt::Write;
+use std::rc::Rc;
+
+use crate::lox_value::LoxValue;
+use crate::token::Span;
+
+// One VM instruction. Operands are indexes into the chunk's pools, stack
+// slots relative to the current frame, or jump distances in instructions.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OpCode {
+    Constant(u16),
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal(u8),
+    SetLocal(u8),
+    GetGlobal(u16),
+    DefineGlobal(u16),
+    SetGlobal(u16),
+    GetUpvalue(u8),
+    SetUpvalue(u8),
+    GetProperty(u16),
+    SetProperty(u16),
+    GetSuper(u16),
+    GetIndex,
+    SetIndex,
+    // Builds a list from that many values on top of the stack.
+    List(u16),
+    // Builds a map from that many key and value pairs on top of the stack.
+    Map(u16),
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    Jump(u16),
+    JumpIfFalse(u16),
+    Loop(u16),
+    Call(u8),
+    Closure(u16),
+    CloseUpvalue,
+    Return,
+    // Starts a `try`: an error raised before the matching `EndTry` unwinds
+    // the stack to its height here, pushes the value `catch` receives and
+    // jumps ahead by the operand.
+    Try(u16),
+    // Like `Try`, for a `try` with only a `finally` block. The error is set
+    // aside and a handle to it pushed instead, for `Rethrow` to raise again.
+    TryFinally(u16),
+    EndTry,
+    // Raises the value on top of the stack as an exception.
+    Throw,
+    Rethrow,
+    // Builds a class from the method name and closure pairs on top of the
+    // stack. `Subclass` also reads the superclass sitting below them.
+    Class(u16, u16),
+    Subclass(u16, u16),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<OpCode>,
+    // Source location of each instruction, used for errors and backtraces.
+    pub spans: Vec<Span>,
+    pub constants: Vec<LoxValue>,
+    pub functions: Vec<Rc<FunctionProto>>,
+}
+
+impl Chunk {
+    pub fn write(&mut self, op: OpCode, span: Span) -> usize {
+        self.code.push(op);
+        self.spans.push(span);
+        self.code.len() - 1
+    }
+
+    // Lists the instructions of the chunk and of the functions nested in it,
+    // one per line, e.g. `0003    2 GetGlobal 1 'a'`.
+    pub fn disassemble(&self, name: &str) -> String {
+        let mut out = format!("== {} ==\n", name);
+        for (offset, op) in self.code.iter().enumerate() {
+            let _ = write!(out, "{:04} {:>4} {:?}", offset, self.spans[offset].line, op);
+            match op {
+                OpCode::Constant(index)
+                | OpCode::GetGlobal(index)
+                | OpCode::DefineGlobal(index)
+                | OpCode::SetGlobal(index)
+                | OpCode::GetProperty(index)
+                | OpCode::SetProperty(index)
+                | OpCode::GetSuper(index)
+                | OpCode::Class(index, _)
+                | OpCode::Subclass(index, _) => {
+                    let _ = write!(out, " '{}'", self.constants[*index as usize]);
+                }
+                OpCode::Closure(index) => {
+                    let _ = write!(out, " <fn {}>", self.functions[*index as usize].name);
+                }
+                _ => {}
+            }
+            out.push('\n');
+        }
+        for function in self.functions.iter() {
+            out.push('\n');
+            out.push_str(&function.chunk.disassemble(&function.name));
+        }
+        out
+    }
+}
+
+// A function as the compiler leaves it: code plus what the VM needs to build
+// a closure from it.
+#[derive(Debug, Default)]
+pub struct FunctionProto {
+    pub name: String,
+    pub arity: usize,
+    pub chunk: Chunk,
+    // Where each captured variable comes from: a local slot of the enclosing
+    // function, or one of the enclosing function's own upvalues.
+    pub upvalues: Vec<UpvalueRef>,
+    pub class: Option<String>,
+    // Module whose globals the function uses.
+    pub module: usize,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct UpvalueRef {
+    pub index: u8,
+    pub is_local: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opcode_is_compact() {
+        assert!(std::mem::size_of::<OpCode>() <= 6);
+    }
+}
@@ -0,0 +1,1043 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::chunk::{FunctionProto, OpCode, UpvalueRef};
+use crate::error::{CompileError, CompileErrorKind, LoxError};
+use crate::expr::{walk_expr, Expr, Slot};
+use crate::lox_value::LoxValue;
+use crate::stmt::{walk_stmt, Flow, Stmt};
+use crate::token::{Literal, Span, Token};
+use crate::visitor::Visitor;
+
+// Compiles a resolved AST to bytecode for the VM. Static errors have already
+// been reported by the resolver, so the only failures left are limits of the
+// bytecode format. Locals are assigned stack slots here rather than using the
+// resolver's slots, which describe the tree-walker's environments.
+pub struct Compiler {
+    // Functions being compiled, the innermost last.
+    functions: Vec<FunctionState>,
+    // Location of the code being compiled, for instructions that have no
+    // token of their own.
+    span: Span,
+    // Module whose globals the compiled code uses.
+    module: usize,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+struct Local {
+    name: String,
+    depth: usize,
+    captured: bool,
+}
+
+// A loop being compiled, with the jumps of its `break`s and `continue`s left
+// to patch once their targets are known.
+struct Loop {
+    scope_depth: usize,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+// A `try` whose handler is active in the code being compiled. Leaving it
+// early with `break`, `continue` or `return` ends the handler and runs the
+// `finally` block first.
+struct TryBlock {
+    // How many loops enclosed the `try`.
+    loops: usize,
+    finally: Option<Stmt>,
+}
+
+struct FunctionState {
+    proto: FunctionProto,
+    kind: FunctionKind,
+    locals: Vec<Local>,
+    scope_depth: usize,
+    names: HashMap<String, u16>,
+    loops: Vec<Loop>,
+    tries: Vec<TryBlock>,
+}
+
+impl FunctionState {
+    fn new(
+        name: String,
+        kind: FunctionKind,
+        class: Option<String>,
+        module: usize,
+    ) -> FunctionState {
+        // Slot 0 holds the function being called, or the receiver of a method.
+        let slot_zero = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            _ => "",
+        };
+        FunctionState {
+            proto: FunctionProto {
+                name,
+                class,
+                module,
+                ..Default::default()
+            },
+            kind,
+            locals: vec![Local {
+                name: slot_zero.to_string(),
+                depth: 0,
+                captured: false,
+            }],
+            scope_depth: 0,
+            names: HashMap::new(),
+            loops: Vec::new(),
+            tries: Vec::new(),
+        }
+    }
+}
+
+impl Default for Compiler {
+    fn default() -> Compiler {
+        Compiler::new()
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Compiler {
+        Compiler::for_module(0)
+    }
+
+    // A compiler for the top level of `module`.
+    pub fn for_module(module: usize) -> Compiler {
+        Compiler {
+            functions: vec![FunctionState::new(
+                "<script>".to_string(),
+                FunctionKind::Script,
+                None,
+                module,
+            )],
+            span: Span::default(),
+            module,
+        }
+    }
+
+    // Compiles a whole program. Like `Interpreter::interpret`, the script
+    // returns the value of a trailing expression statement, or nil.
+    pub fn compile(mut self, stmts: &[Stmt]) -> Result<Rc<FunctionProto>, LoxError> {
+        match stmts.split_last() {
+            Some((Stmt::Expr(expr), init)) => {
+                for stmt in init {
+                    walk_stmt(&mut self, stmt)?;
+                }
+                walk_expr(&mut self, expr)?;
+                let span = self.last_span();
+                self.emit(OpCode::Return, span);
+            }
+            _ => {
+                for stmt in stmts {
+                    walk_stmt(&mut self, stmt)?;
+                }
+                self.emit_return(self.last_span());
+            }
+        }
+        let state = self.functions.pop().expect("script is being compiled");
+        Ok(Rc::new(state.proto))
+    }
+
+    fn current(&self) -> &FunctionState {
+        self.functions.last().expect("a function is being compiled")
+    }
+
+    fn current_mut(&mut self) -> &mut FunctionState {
+        self.functions
+            .last_mut()
+            .expect("a function is being compiled")
+    }
+
+    fn emit(&mut self, op: OpCode, span: Span) -> usize {
+        self.span = span;
+        self.current_mut().proto.chunk.write(op, span)
+    }
+
+    fn last_span(&self) -> Span {
+        self.span
+    }
+
+    fn emit_return(&mut self, span: Span) {
+        self.emit_default_return_value(span);
+        self.emit(OpCode::Return, span);
+    }
+
+    fn emit_default_return_value(&mut self, span: Span) {
+        if self.current().kind == FunctionKind::Initializer {
+            self.emit(OpCode::GetLocal(0), span);
+        } else {
+            self.emit(OpCode::Nil, span);
+        }
+    }
+
+    fn error(kind: CompileErrorKind, span: Span) -> LoxError {
+        LoxError::Compile(CompileError { kind, span })
+    }
+
+    fn make_constant(&mut self, value: LoxValue, span: Span) -> Result<u16, LoxError> {
+        let constants = &mut self.current_mut().proto.chunk.constants;
+        if constants.len() > u16::MAX as usize {
+            return Err(Compiler::error(CompileErrorKind::TooManyConstants, span));
+        }
+        constants.push(value);
+        Ok((constants.len() - 1) as u16)
+    }
+
+    // Constant holding an identifier, shared by every use of the same name.
+    fn name_constant(&mut self, name: &str, span: Span) -> Result<u16, LoxError> {
+        if let Some(index) = self.current().names.get(name) {
+            return Ok(*index);
+        }
+        let index = self.make_constant(LoxValue::Str(name.to_string()), span)?;
+        self.current_mut().names.insert(name.to_string(), index);
+        Ok(index)
+    }
+
+    fn emit_jump(&mut self, op: fn(u16) -> OpCode, span: Span) -> usize {
+        self.emit(op(0), span)
+    }
+
+    fn patch_jump(&mut self, jump: usize) -> Result<(), LoxError> {
+        let chunk = &mut self.current_mut().proto.chunk;
+        let offset = chunk.code.len() - jump - 1;
+        if offset > u16::MAX as usize {
+            return Err(Compiler::error(
+                CompileErrorKind::JumpTooLarge,
+                chunk.spans[jump],
+            ));
+        }
+        chunk.code[jump] = match chunk.code[jump] {
+            OpCode::Jump(_) => OpCode::Jump(offset as u16),
+            OpCode::JumpIfFalse(_) => OpCode::JumpIfFalse(offset as u16),
+            OpCode::Try(_) => OpCode::Try(offset as u16),
+            OpCode::TryFinally(_) => OpCode::TryFinally(offset as u16),
+            op => unreachable!("patching {:?} as a jump", op),
+        };
+        Ok(())
+    }
+
+    fn emit_loop(&mut self, start: usize, span: Span) -> Result<(), LoxError> {
+        let offset = self.current().proto.chunk.code.len() - start + 1;
+        if offset > u16::MAX as usize {
+            return Err(Compiler::error(CompileErrorKind::JumpTooLarge, span));
+        }
+        self.emit(OpCode::Loop(offset as u16), span);
+        Ok(())
+    }
+
+    fn begin_scope(&mut self) {
+        self.current_mut().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        let span = self.last_span();
+        let state = self.current_mut();
+        state.scope_depth -= 1;
+        let mut ops = Vec::new();
+        while let Some(local) = state.locals.last() {
+            if local.depth <= state.scope_depth {
+                break;
+            }
+            ops.push(if local.captured {
+                OpCode::CloseUpvalue
+            } else {
+                OpCode::Pop
+            });
+            state.locals.pop();
+        }
+        for op in ops {
+            self.emit(op, span);
+        }
+    }
+
+    // Jumps out of the innermost loop's body, discarding the locals declared
+    // inside it. Returns the jump to patch.
+    fn exit_loop_body(&mut self, span: Span) -> usize {
+        let state = self.current();
+        let depth = state.loops.last().expect("resolver checked").scope_depth;
+        let ops: Vec<OpCode> = state
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth > depth)
+            .map(|local| {
+                if local.captured {
+                    OpCode::CloseUpvalue
+                } else {
+                    OpCode::Pop
+                }
+            })
+            .collect();
+        for op in ops {
+            self.emit(op, span);
+        }
+        self.emit_jump(OpCode::Jump, span)
+    }
+
+    // Ends the handlers of the innermost `count` active `try`s, running their
+    // `finally` blocks, as code jumps out of them.
+    fn leave_tries(&mut self, count: usize, span: Span) -> Result<(), LoxError> {
+        let len = self.current().tries.len();
+        for i in (len - count..len).rev() {
+            self.emit(OpCode::EndTry, span);
+            if let Some(finally) = self.current().tries[i].finally.clone() {
+                // Code in the block only leaves the `try`s around this one.
+                let inner = self.current_mut().tries.split_off(i);
+                let compiled = walk_stmt(self, &finally);
+                self.current_mut().tries.extend(inner);
+                compiled?;
+            }
+        }
+        Ok(())
+    }
+
+    // Active `try`s inside the innermost loop, which `break` and `continue`
+    // leave.
+    fn tries_in_loop(&self) -> usize {
+        let state = self.current();
+        state
+            .tries
+            .iter()
+            .rev()
+            .take_while(|block| block.loops == state.loops.len())
+            .count()
+    }
+
+    // Compiles the `finally` block run when an error unwinds out of a
+    // `TryFinally`. The handle to the error sits on top of the stack, held as
+    // a nameless local while the block runs, and is raised again afterwards.
+    fn finally_on_error(&mut self, finally: &Stmt, span: Span) -> Result<(), LoxError> {
+        self.add_local("", span)?;
+        walk_stmt(self, finally)?;
+        self.current_mut().locals.pop();
+        self.emit(OpCode::Rethrow, span);
+        Ok(())
+    }
+
+    // Compiles `body` with a handler, `Try` or `TryFinally`, that jumps to
+    // the returned instruction, still to be patched, if an error escapes it.
+    fn guarded(
+        &mut self,
+        op: fn(u16) -> OpCode,
+        body: &Stmt,
+        finally: Option<&Stmt>,
+        span: Span,
+    ) -> Result<usize, LoxError> {
+        let handler = self.emit_jump(op, span);
+        let loops = self.current().loops.len();
+        self.current_mut().tries.push(TryBlock {
+            loops,
+            finally: finally.cloned(),
+        });
+        let compiled = walk_stmt(self, body);
+        self.current_mut().tries.pop();
+        compiled?;
+        self.emit(OpCode::EndTry, self.last_span());
+        Ok(handler)
+    }
+
+    fn add_local(&mut self, name: &str, span: Span) -> Result<(), LoxError> {
+        let state = self.current_mut();
+        if state.locals.len() > u8::MAX as usize {
+            return Err(Compiler::error(CompileErrorKind::TooManyLocals, span));
+        }
+        state.locals.push(Local {
+            name: name.to_string(),
+            depth: state.scope_depth,
+            captured: false,
+        });
+        Ok(())
+    }
+
+    fn resolve_local(&self, level: usize, name: &str) -> Option<u8> {
+        self.functions[level]
+            .locals
+            .iter()
+            .rposition(|local| local.name == name)
+            .map(|slot| slot as u8)
+    }
+
+    // Finds `name` in the functions enclosing `level`, threading an upvalue
+    // through every function in between.
+    fn resolve_upvalue(
+        &mut self,
+        level: usize,
+        name: &str,
+        span: Span,
+    ) -> Result<Option<u8>, LoxError> {
+        if level == 0 {
+            return Ok(None);
+        }
+        if let Some(slot) = self.resolve_local(level - 1, name) {
+            self.functions[level - 1].locals[slot as usize].captured = true;
+            return self.add_upvalue(level, slot, true, span).map(Some);
+        }
+        match self.resolve_upvalue(level - 1, name, span)? {
+            Some(index) => self.add_upvalue(level, index, false, span).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn add_upvalue(
+        &mut self,
+        level: usize,
+        index: u8,
+        is_local: bool,
+        span: Span,
+    ) -> Result<u8, LoxError> {
+        let upvalue = UpvalueRef { index, is_local };
+        let upvalues = &mut self.functions[level].proto.upvalues;
+        if let Some(existing) = upvalues.iter().position(|u| *u == upvalue) {
+            return Ok(existing as u8);
+        }
+        if upvalues.len() > u8::MAX as usize {
+            return Err(Compiler::error(CompileErrorKind::TooManyUpvalues, span));
+        }
+        upvalues.push(upvalue);
+        Ok((upvalues.len() - 1) as u8)
+    }
+
+    fn get_variable(&mut self, name: &str, span: Span) -> Result<(), LoxError> {
+        let level = self.functions.len() - 1;
+        let op = if let Some(slot) = self.resolve_local(level, name) {
+            OpCode::GetLocal(slot)
+        } else if let Some(index) = self.resolve_upvalue(level, name, span)? {
+            OpCode::GetUpvalue(index)
+        } else {
+            OpCode::GetGlobal(self.name_constant(name, span)?)
+        };
+        self.emit(op, span);
+        Ok(())
+    }
+
+    fn set_variable(&mut self, name: &str, span: Span) -> Result<(), LoxError> {
+        let level = self.functions.len() - 1;
+        let op = if let Some(slot) = self.resolve_local(level, name) {
+            OpCode::SetLocal(slot)
+        } else if let Some(index) = self.resolve_upvalue(level, name, span)? {
+            OpCode::SetUpvalue(index)
+        } else {
+            OpCode::SetGlobal(self.name_constant(name, span)?)
+        };
+        self.emit(op, span);
+        Ok(())
+    }
+
+    // Binds the value on top of the stack to a newly declared variable.
+    fn define_variable(&mut self, name: &Token) -> Result<(), LoxError> {
+        if self.current().scope_depth > 0 {
+            return self.add_local(&name.lexeme, name.span);
+        }
+        let index = self.name_constant(&name.lexeme, name.span)?;
+        self.emit(OpCode::DefineGlobal(index), name.span);
+        Ok(())
+    }
+
+    fn function(
+        &mut self,
+        name: &Token,
+        args: &[Token],
+        body: &Stmt,
+        kind: FunctionKind,
+        class: Option<String>,
+    ) -> Result<(), LoxError> {
+        self.span = name.span;
+        self.functions.push(FunctionState::new(
+            name.lexeme.clone(),
+            kind,
+            class,
+            self.module,
+        ));
+        self.current_mut().proto.arity = args.len();
+        self.begin_scope();
+        for arg in args {
+            self.add_local(&arg.lexeme, arg.span)?;
+        }
+        match body {
+            Stmt::Block(stmts) => {
+                for stmt in stmts {
+                    walk_stmt(self, stmt)?;
+                }
+            }
+            body => {
+                walk_stmt(self, body)?;
+            }
+        }
+        self.emit_return(self.last_span());
+
+        let state = self.functions.pop().expect("function is being compiled");
+        let functions = &mut self.current_mut().proto.chunk.functions;
+        if functions.len() > u16::MAX as usize {
+            return Err(Compiler::error(
+                CompileErrorKind::TooManyConstants,
+                name.span,
+            ));
+        }
+        functions.push(Rc::new(state.proto));
+        let index = (functions.len() - 1) as u16;
+        self.emit(OpCode::Closure(index), name.span);
+        Ok(())
+    }
+}
+
+impl Visitor for Compiler {
+    fn visit_assign(
+        &mut self,
+        left: &Token,
+        right: &Expr,
+        _slot: Rc<Cell<Slot>>,
+    ) -> Result<LoxValue, LoxError> {
+        walk_expr(self, right)?;
+        self.set_variable(&left.lexeme, left.span)?;
+        Ok(LoxValue::Nil)
+    }
+
+    fn visit_binary(
+        &mut self,
+        left: &Expr,
+        op: &Token,
+        right: &Expr,
+    ) -> Result<LoxValue, LoxError> {
+        walk_expr(self, left)?;
+        walk_expr(self, right)?;
+        let code = match op.lexeme.as_str() {
+            "-" => OpCode::Subtract,
+            "+" => OpCode::Add,
+            "*" => OpCode::Multiply,
+            "/" => OpCode::Divide,
+            ">" => OpCode::Greater,
+            ">=" => OpCode::GreaterEqual,
+            "<" => OpCode::Less,
+            "<=" => OpCode::LessEqual,
+            "==" => OpCode::Equal,
+            "!=" => OpCode::NotEqual,
+            _ => unreachable!("invalid operator in binary: {}", op.lexeme),
+        };
+        self.emit(code, op.span);
+        Ok(LoxValue::Nil)
+    }
+
+    fn visit_grouping(&mut self, expr: &Expr) -> Result<LoxValue, LoxError> {
+        walk_expr(self, expr)
+    }
+
+    fn visit_literal(&mut self, lit: &Literal) -> Result<LoxValue, LoxError> {
+        let span = self.last_span();
+        let op = match lit {
+            Literal::Nil => OpCode::Nil,
+            Literal::Bool(true) => OpCode::True,
+            Literal::Bool(false) => OpCode::False,
+            lit => OpCode::Constant(self.make_constant(lit.value(), span)?),
+        };
+        self.emit(op, span);
+        Ok(LoxValue::Nil)
+    }
+
+    fn visit_logical(
+        &mut self,
+        left: &Expr,
+        op: &Token,
+        right: &Expr,
+    ) -> Result<LoxValue, LoxError> {
+        walk_expr(self, left)?;
+        if op.lexeme == "and" {
+            let end = self.emit_jump(OpCode::JumpIfFalse, op.span);
+            self.emit(OpCode::Pop, op.span);
+            walk_expr(self, right)?;
+            self.patch_jump(end)?;
+        } else {
+            let otherwise = self.emit_jump(OpCode::JumpIfFalse, op.span);
+            let end = self.emit_jump(OpCode::Jump, op.span);
+            self.patch_jump(otherwise)?;
+            self.emit(OpCode::Pop, op.span);
+            walk_expr(self, right)?;
+            self.patch_jump(end)?;
+        }
+        Ok(LoxValue::Nil)
+    }
+
+    fn visit_unary(&mut self, token: &Token, expr: &Expr) -> Result<LoxValue, LoxError> {
+        walk_expr(self, expr)?;
+        let op = match token.lexeme.as_str() {
+            "-" => OpCode::Negate,
+            "!" => OpCode::Not,
+            _ => unreachable!("invalid operator in unary: {}", token.lexeme),
+        };
+        self.emit(op, token.span);
+        Ok(LoxValue::Nil)
+    }
+
+    fn visit_var_expr(
+        &mut self,
+        token: &Token,
+        _slot: Rc<Cell<Slot>>,
+    ) -> Result<LoxValue, LoxError> {
+        self.get_variable(&token.lexeme, token.span)?;
+        Ok(LoxValue::Nil)
+    }
+
+    fn visit_call(
+        &mut self,
+        callee: &Expr,
+        paren: &Token,
+        args: Vec<Expr>,
+    ) -> Result<LoxValue, LoxError> {
+        walk_expr(self, callee)?;
+        for arg in args.iter() {
+            walk_expr(self, arg)?;
+        }
+        // The parser already rejects calls with more than 255 arguments.
+        self.emit(OpCode::Call(args.len() as u8), paren.span);
+        Ok(LoxValue::Nil)
+    }
+
+    fn visit_get(&mut self, expr: &Expr, name: &Token) -> Result<LoxValue, LoxError> {
+        walk_expr(self, expr)?;
+        let index = self.name_constant(&name.lexeme, name.span)?;
+        self.emit(OpCode::GetProperty(index), name.span);
+        Ok(LoxValue::Nil)
+    }
+
+    fn visit_set(&mut self, expr: &Expr, name: &Token, value: &Expr) -> Result<LoxValue, LoxError> {
+        walk_expr(self, expr)?;
+        walk_expr(self, value)?;
+        let index = self.name_constant(&name.lexeme, name.span)?;
+        self.emit(OpCode::SetProperty(index), name.span);
+        Ok(LoxValue::Nil)
+    }
+
+    fn visit_lambda(
+        &mut self,
+        name: &Token,
+        args: &[Token],
+        body: &Stmt,
+    ) -> Result<LoxValue, LoxError> {
+        self.function(name, args, body, FunctionKind::Function, None)?;
+        Ok(LoxValue::Nil)
+    }
+
+    fn visit_list(&mut self, bracket: &Token, items: &[Expr]) -> Result<LoxValue, LoxError> {
+        if items.len() > u16::MAX as usize {
+            return Err(Compiler::error(
+                CompileErrorKind::TooManyElements,
+                bracket.span,
+            ));
+        }
+        for item in items.iter() {
+            walk_expr(self, item)?;
+        }
+        self.emit(OpCode::List(items.len() as u16), bracket.span);
+        Ok(LoxValue::Nil)
+    }
+
+    fn visit_map(&mut self, brace: &Token, entries: &[(Expr, Expr)]) -> Result<LoxValue, LoxError> {
+        if entries.len() > u16::MAX as usize {
+            return Err(Compiler::error(
+                CompileErrorKind::TooManyElements,
+                brace.span,
+            ));
+        }
+        for (key, value) in entries.iter() {
+            walk_expr(self, key)?;
+            walk_expr(self, value)?;
+        }
+        self.emit(OpCode::Map(entries.len() as u16), brace.span);
+        Ok(LoxValue::Nil)
+    }
+
+    fn visit_index(
+        &mut self,
+        expr: &Expr,
+        bracket: &Token,
+        index: &Expr,
+    ) -> Result<LoxValue, LoxError> {
+        walk_expr(self, expr)?;
+        walk_expr(self, index)?;
+        self.emit(OpCode::GetIndex, bracket.span);
+        Ok(LoxValue::Nil)
+    }
+
+    fn visit_set_index(
+        &mut self,
+        expr: &Expr,
+        bracket: &Token,
+        index: &Expr,
+        value: &Expr,
+    ) -> Result<LoxValue, LoxError> {
+        walk_expr(self, expr)?;
+        walk_expr(self, index)?;
+        walk_expr(self, value)?;
+        self.emit(OpCode::SetIndex, bracket.span);
+        Ok(LoxValue::Nil)
+    }
+
+    fn visit_this(&mut self, token: &Token, _slot: Rc<Cell<Slot>>) -> Result<LoxValue, LoxError> {
+        self.get_variable("this", token.span)?;
+        Ok(LoxValue::Nil)
+    }
+
+    fn visit_super(
+        &mut self,
+        keyword: &Token,
+        method: &Token,
+        _slot: Rc<Cell<Slot>>,
+    ) -> Result<LoxValue, LoxError> {
+        self.get_variable("this", keyword.span)?;
+        self.get_variable("super", keyword.span)?;
+        let index = self.name_constant(&method.lexeme, method.span)?;
+        self.emit(OpCode::GetSuper(index), method.span);
+        Ok(LoxValue::Nil)
+    }
+
+    fn visit_class(
+        &mut self,
+        name: &Token,
+        superclass: Option<Expr>,
+        methods: Vec<Stmt>,
+    ) -> Result<Flow, LoxError> {
+        // Like the tree-walker, the class variable exists before the class
+        // does so that methods can refer to it.
+        let local = self.current().scope_depth > 0;
+        if local {
+            self.emit(OpCode::Nil, name.span);
+            self.add_local(&name.lexeme, name.span)?;
+        }
+        let slot = self.current().locals.len() - 1;
+
+        let superclass_span = match superclass {
+            Some(ref superclass) => {
+                let span = match superclass {
+                    Expr::Variable(token, _) => token.span,
+                    _ => name.span,
+                };
+                self.begin_scope();
+                walk_expr(self, superclass)?;
+                self.add_local("super", span)?;
+                Some(span)
+            }
+            None => None,
+        };
+
+        for method in methods.iter() {
+            match method {
+                Stmt::Func(method_name, args, body) => {
+                    let index = self.name_constant(&method_name.lexeme, method_name.span)?;
+                    self.emit(OpCode::Constant(index), method_name.span);
+                    let kind = if method_name.lexeme == "init" {
+                        FunctionKind::Initializer
+                    } else {
+                        FunctionKind::Method
+                    };
+                    self.function(method_name, args, body, kind, Some(name.lexeme.clone()))?;
+                }
+                _ => unreachable!("function only in class'es methods"),
+            }
+        }
+
+        let index = self.name_constant(&name.lexeme, name.span)?;
+        let count = methods.len() as u16;
+        match superclass_span {
+            Some(span) => self.emit(OpCode::Subclass(index, count), span),
+            None => self.emit(OpCode::Class(index, count), name.span),
+        };
+        if local {
+            self.emit(OpCode::SetLocal(slot as u8), name.span);
+            self.emit(OpCode::Pop, name.span);
+        } else {
+            self.emit(OpCode::DefineGlobal(index), name.span);
+        }
+
+        if superclass_span.is_some() {
+            self.end_scope();
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn visit_expr_stmt(&mut self, expr: &Expr) -> Result<Flow, LoxError> {
+        walk_expr(self, expr)?;
+        self.emit(OpCode::Pop, self.last_span());
+        Ok(Flow::Normal)
+    }
+
+    fn visit_print(&mut self, expr: &Expr) -> Result<Flow, LoxError> {
+        walk_expr(self, expr)?;
+        self.emit(OpCode::Print, self.last_span());
+        Ok(Flow::Normal)
+    }
+
+    fn visit_block(&mut self, stmts: Vec<Stmt>) -> Result<Flow, LoxError> {
+        self.begin_scope();
+        for stmt in stmts.iter() {
+            walk_stmt(self, stmt)?;
+        }
+        self.end_scope();
+        Ok(Flow::Normal)
+    }
+
+    fn visit_func(
+        &mut self,
+        name: &Token,
+        args: Vec<Token>,
+        body: &Stmt,
+    ) -> Result<Flow, LoxError> {
+        if self.current().scope_depth > 0 {
+            // Declared before the body is compiled so the function can
+            // recurse through its own slot.
+            self.add_local(&name.lexeme, name.span)?;
+            self.function(name, &args, body, FunctionKind::Function, None)?;
+        } else {
+            self.function(name, &args, body, FunctionKind::Function, None)?;
+            self.define_variable(name)?;
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn visit_if(
+        &mut self,
+        cond: &Expr,
+        then_branch: &Stmt,
+        else_branch: Option<&Stmt>,
+    ) -> Result<Flow, LoxError> {
+        walk_expr(self, cond)?;
+        let span = self.last_span();
+        let then_jump = self.emit_jump(OpCode::JumpIfFalse, span);
+        self.emit(OpCode::Pop, span);
+        walk_stmt(self, then_branch)?;
+        let else_jump = self.emit_jump(OpCode::Jump, self.last_span());
+        self.patch_jump(then_jump)?;
+        self.emit(OpCode::Pop, span);
+        if let Some(else_branch) = else_branch {
+            walk_stmt(self, else_branch)?;
+        }
+        self.patch_jump(else_jump)?;
+        Ok(Flow::Normal)
+    }
+
+    fn visit_return(&mut self, keyword: &Token, value: Option<&Expr>) -> Result<Flow, LoxError> {
+        self.span = keyword.span;
+        match value {
+            Some(value) => {
+                walk_expr(self, value)?;
+            }
+            None => self.emit_default_return_value(keyword.span),
+        }
+        let tries = self.current().tries.len();
+        if self
+            .current()
+            .tries
+            .iter()
+            .any(|block| block.finally.is_some())
+        {
+            // The value waits in a nameless local while `finally` blocks run.
+            self.add_local("", keyword.span)?;
+            let slot = (self.current().locals.len() - 1) as u8;
+            self.leave_tries(tries, keyword.span)?;
+            self.current_mut().locals.pop();
+            self.emit(OpCode::GetLocal(slot), keyword.span);
+        }
+        // Returning drops the frame's handlers, so plain `try`s need no
+        // `EndTry`.
+        self.emit(OpCode::Return, keyword.span);
+        Ok(Flow::Normal)
+    }
+
+    // Imports are bound before the module runs; see `Lox::link`.
+    fn visit_import(&mut self, _path: &Token, _names: Option<&[Token]>) -> Result<Flow, LoxError> {
+        Ok(Flow::Normal)
+    }
+
+    fn visit_throw(&mut self, keyword: &Token, value: &Expr) -> Result<Flow, LoxError> {
+        walk_expr(self, value)?;
+        self.emit(OpCode::Throw, keyword.span);
+        Ok(Flow::Normal)
+    }
+
+    fn visit_try(
+        &mut self,
+        body: &Stmt,
+        catch: Option<(&Token, &Stmt)>,
+        finally: Option<&Stmt>,
+    ) -> Result<Flow, LoxError> {
+        let span = self.last_span();
+        let op = if catch.is_some() {
+            OpCode::Try
+        } else {
+            OpCode::TryFinally
+        };
+        let handler = self.guarded(op, body, finally, span)?;
+        let done = self.emit_jump(OpCode::Jump, self.last_span());
+        self.patch_jump(handler)?;
+        match (catch, finally) {
+            (Some((name, handler)), finally) => {
+                // The caught value is on the stack, where the variable's
+                // slot is.
+                self.begin_scope();
+                self.add_local(&name.lexeme, name.span)?;
+                match finally {
+                    Some(finally) => {
+                        let failed =
+                            self.guarded(OpCode::TryFinally, handler, Some(finally), name.span)?;
+                        let caught = self.emit_jump(OpCode::Jump, self.last_span());
+                        self.patch_jump(failed)?;
+                        self.finally_on_error(finally, name.span)?;
+                        self.patch_jump(caught)?;
+                    }
+                    None => {
+                        walk_stmt(self, handler)?;
+                    }
+                }
+                self.end_scope();
+            }
+            (None, Some(finally)) => self.finally_on_error(finally, span)?,
+            (None, None) => unreachable!("the parser requires `catch` or `finally`"),
+        }
+        self.patch_jump(done)?;
+        if let Some(finally) = finally {
+            walk_stmt(self, finally)?;
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn visit_while(
+        &mut self,
+        cond: &Expr,
+        body: &Stmt,
+        inc: Option<&Expr>,
+    ) -> Result<Flow, LoxError> {
+        let start = self.current().proto.chunk.code.len();
+        walk_expr(self, cond)?;
+        let span = self.last_span();
+        let exit = self.emit_jump(OpCode::JumpIfFalse, span);
+        self.emit(OpCode::Pop, span);
+        let depth = self.current().scope_depth;
+        self.current_mut().loops.push(Loop {
+            scope_depth: depth,
+            breaks: Vec::new(),
+            continues: Vec::new(),
+        });
+        let compiled = walk_stmt(self, body);
+        let Loop {
+            breaks, continues, ..
+        } = self.current_mut().loops.pop().expect("pushed above");
+        compiled?;
+        for jump in continues {
+            self.patch_jump(jump)?;
+        }
+        if let Some(inc) = inc {
+            walk_expr(self, inc)?;
+            self.emit(OpCode::Pop, span);
+        }
+        self.emit_loop(start, span)?;
+        self.patch_jump(exit)?;
+        self.emit(OpCode::Pop, span);
+        for jump in breaks {
+            self.patch_jump(jump)?;
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn visit_break(&mut self, keyword: &Token) -> Result<Flow, LoxError> {
+        self.leave_tries(self.tries_in_loop(), keyword.span)?;
+        let jump = self.exit_loop_body(keyword.span);
+        let state = self.current_mut();
+        state
+            .loops
+            .last_mut()
+            .expect("resolver checked")
+            .breaks
+            .push(jump);
+        Ok(Flow::Normal)
+    }
+
+    fn visit_continue(&mut self, keyword: &Token) -> Result<Flow, LoxError> {
+        self.leave_tries(self.tries_in_loop(), keyword.span)?;
+        let jump = self.exit_loop_body(keyword.span);
+        let state = self.current_mut();
+        state
+            .loops
+            .last_mut()
+            .expect("resolver checked")
+            .continues
+            .push(jump);
+        Ok(Flow::Normal)
+    }
+
+    fn visit_var_stmt(&mut self, name: &Token, init: Option<&Expr>) -> Result<Flow, LoxError> {
+        self.span = name.span;
+        match init {
+            Some(init) => {
+                walk_expr(self, init)?;
+            }
+            None => {
+                self.emit(OpCode::Nil, name.span);
+            }
+        }
+        self.define_variable(name)?;
+        Ok(Flow::Normal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn compile(source: &str) -> Rc<FunctionProto> {
+        let mut scanner = Scanner {
+            source: source.chars().collect(),
+            ..Default::default()
+        };
+        let tokens = scanner.scan_tokens().unwrap();
+        let stmts = Parser::new(tokens).parse().unwrap();
+        Compiler::new().compile(&stmts).unwrap()
+    }
+
+    #[test]
+    fn test_locals_use_slots_and_captures_use_upvalues() {
+        let script = compile(
+            "var g = 1;\nfun f(a) {\n  var b = a;\n  fun h() { return b; }\n  return h;\n}",
+        );
+        assert_eq!(
+            script.chunk.disassemble("<script>"),
+            "== <script> ==
+0000    1 Constant(0) '1'
+0001    1 DefineGlobal(1) 'g'
+0002    2 Closure(0) <fn f>
+0003    2 DefineGlobal(2) 'f'
+0004    2 Nil
+0005    2 Return
+
+== f ==
+0000    3 GetLocal(1)
+0001    4 Closure(0) <fn h>
+0002    5 GetLocal(3)
+0003    5 Return
+0004    5 Nil
+0005    5 Return
+
+== h ==
+0000    4 GetUpvalue(0)
+0001    4 Return
+0002    4 Nil
+0003    4 Return
+"
+        );
+        let h = &script.chunk.functions[0].chunk.functions[0];
+        assert_eq!(
+            h.upvalues,
+            vec![UpvalueRef {
+                index: 2,
+                is_local: true
+            }]
+        );
+    }
+}
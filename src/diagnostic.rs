@@ -0,0 +1,216 @@
+use std::fmt::Write;
+
+use crate::token::Span;
+
+pub struct SourceFile {
+    pub name: String,
+    pub source: String,
+}
+
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        SourceMap { files: Vec::new() }
+    }
+
+    pub fn add(&mut self, name: String, source: String) -> usize {
+        self.files.push(SourceFile { name, source });
+        self.files.len() - 1
+    }
+
+    pub fn get(&self, file_id: usize) -> Option<&SourceFile> {
+        self.files.get(file_id)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub msg: String,
+}
+
+impl Label {
+    pub fn new(span: Span, msg: String) -> Label {
+        Label { span, msg }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub msg: String,
+    pub code: Option<&'static str>,
+    pub labels: Vec<Label>,
+    // Call stack of a runtime error, rendered as `at <msg> (<file>:<line>)`.
+    pub backtrace: Vec<Label>,
+}
+
+impl Diagnostic {
+    pub fn new(msg: String) -> Diagnostic {
+        Diagnostic {
+            msg,
+            code: None,
+            labels: Vec::new(),
+            backtrace: Vec::new(),
+        }
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Diagnostic {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn with_label(mut self, span: Span, msg: String) -> Diagnostic {
+        self.labels.push(Label::new(span, msg));
+        self
+    }
+
+    // Renders the diagnostic the way rustc does:
+    //
+    //   error[E0401]: operands of `-` must be numbers, got number and string
+    //    --> main.lox:3:9
+    //     |
+    //   3 | print 1 - "a";
+    //     |         ^
+    //     = backtrace:
+    //         at sub (main.lox:3)
+    //         at <script> (main.lox:6)
+    pub fn render(&self, sources: &SourceMap) -> String {
+        let mut out = match self.code {
+            Some(code) => format!("error[{}]: {}\n", code, self.msg),
+            None => format!("error: {}\n", self.msg),
+        };
+
+        let mut labels: Vec<&Label> = self.labels.iter().collect();
+        labels.sort_by_key(|label| (label.span.file_id, label.span.start));
+
+        let width = labels
+            .iter()
+            .map(|label| label.span.line.to_string().len())
+            .max()
+            .unwrap_or(0);
+        let gutter = " ".repeat(width);
+
+        let mut last: Option<(usize, usize)> = None;
+        for label in labels {
+            let span = label.span;
+            let file = match sources.get(span.file_id) {
+                Some(file) => file,
+                None => continue,
+            };
+
+            if last.map(|(file_id, _)| file_id) != Some(span.file_id) {
+                let _ = writeln!(
+                    out,
+                    "{}--> {}:{}:{}",
+                    gutter, file.name, span.line, span.column
+                );
+                let _ = writeln!(out, "{} |", gutter);
+            }
+            let line = file.source.lines().nth(span.line - 1).unwrap_or("");
+            if last != Some((span.file_id, span.line)) {
+                let _ = writeln!(out, "{:>width$} | {}", span.line, line, width = width);
+            }
+
+            let underline = underline(file, span, line);
+            let _ = writeln!(
+                out,
+                "{} | {}{} {}",
+                gutter,
+                " ".repeat(span.column - 1),
+                "^".repeat(underline),
+                label.msg
+            );
+            last = Some((span.file_id, span.line));
+        }
+
+        if !self.backtrace.is_empty() {
+            let _ = writeln!(out, "{} = backtrace:", gutter);
+            for frame in &self.backtrace {
+                let file = sources
+                    .get(frame.span.file_id)
+                    .map_or("?", |file| &file.name);
+                let _ = writeln!(
+                    out,
+                    "{}     at {} ({}:{})",
+                    gutter, frame.msg, file, frame.span.line
+                );
+            }
+        }
+
+        out.lines()
+            .map(|line| line.trim_end())
+            .collect::<Vec<&str>>()
+            .join("\n")
+    }
+}
+
+// Number of characters of `span` that fall on its first line, never less
+// than one so that zero-width spans such as end of file stay visible.
+fn underline(file: &SourceFile, span: Span, line: &str) -> usize {
+    let text = file.source.get(span.start..span.end).unwrap_or("");
+    let text = text.lines().next().unwrap_or("");
+    let available = line.chars().count().saturating_sub(span.column - 1);
+    text.chars().count().min(available).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(start: usize, end: usize, line: usize, column: usize) -> Span {
+        Span {
+            file_id: 0,
+            start,
+            end,
+            line,
+            column,
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let mut sources = SourceMap::new();
+        sources.add(
+            "main.lox".to_string(),
+            "var a = 1;\nprint a - \"b\";\n".to_string(),
+        );
+        let diagnostic = Diagnostic::new("operands must be numbers".to_string())
+            .with_label(span(19, 20, 2, 9), "".to_string())
+            .with_label(span(21, 24, 2, 11), "this is a string".to_string());
+        assert_eq!(
+            diagnostic.render(&sources),
+            "error: operands must be numbers
+ --> main.lox:2:9
+  |
+2 | print a - \"b\";
+  |         ^
+  |           ^^^ this is a string"
+        );
+    }
+
+    #[test]
+    fn test_render_lines_and_end_of_file() {
+        let mut sources = SourceMap::new();
+        sources.add(
+            "<input>".to_string(),
+            "print 1;\n\n\n\n\n\n\n\n\nprint".to_string(),
+        );
+        let diagnostic = Diagnostic::new("syntax errors".to_string())
+            .with_label(span(0, 5, 1, 1), "first".to_string())
+            .with_label(span(23, 23, 10, 6), "expect expression".to_string());
+        assert_eq!(
+            diagnostic.render(&sources),
+            "error: syntax errors
+  --> <input>:1:1
+   |
+ 1 | print 1;
+   | ^^^^^ first
+10 | print
+   |      ^ expect expression"
+        );
+    }
+}
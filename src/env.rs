@@ -1,85 +1,65 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
 use std::rc::Rc;
 
+use crate::expr::Slot;
 use crate::lox_value::LoxValue;
 
+// The variables of one local scope, stored in the order they are declared so
+// that they can be read at the slot index the resolver assigned. Globals live
+// in the interpreter instead.
 #[derive(Clone, Debug)]
 pub struct Env {
-    pub values: HashMap<String, LoxValue>,
+    pub values: Vec<LoxValue>,
     pub enclosing: Option<Rc<RefCell<Env>>>,
 }
 
+impl Default for Env {
+    fn default() -> Env {
+        Env::new()
+    }
+}
+
 impl Env {
     pub fn new() -> Env {
         Env {
-            values: HashMap::new(),
+            values: Vec::new(),
             enclosing: None,
         }
     }
 
-    pub fn define(&mut self, k: String, v: LoxValue) -> Option<LoxValue> {
-        self.values.insert(k, v)
-    }
-
-    pub fn assign(&mut self, k: String, v: LoxValue) -> Option<LoxValue> {
-        if self.values.contains_key(&k) {
-            self.values.insert(k, v)
-        } else {
-            match self.enclosing {
-                Some(ref mut parent) => parent.borrow_mut().assign(k, v),
-                None => None,
-            }
+    pub fn with_enclosing(enclosing: Rc<RefCell<Env>>) -> Env {
+        Env {
+            values: Vec::new(),
+            enclosing: Some(enclosing),
         }
     }
 
-    pub fn assign_at(&mut self, distance: i32, k: String, v: LoxValue) -> Option<LoxValue> {
-        if distance == 0 {
-            return self.assign(k, v);
-        }
-
-        let mut ret_env = match self.enclosing.clone() {
-            Some(parent_env) => parent_env.clone(),
-            _ => return None,
-        };
-        for _ in 1..distance {
-            let new_env = match ret_env.borrow().enclosing {
-                Some(ref parent_env) => parent_env.clone(),
-                None => return None,
-            };
-            ret_env = new_env;
-        }
-        let ret = ret_env.borrow_mut().assign(k, v);
-        ret
+    // Declares the next variable of the scope and returns its index.
+    pub fn define(&mut self, v: LoxValue) -> usize {
+        self.values.push(v);
+        self.values.len() - 1
     }
 
-    pub fn get(&self, k: &String) -> Option<LoxValue> {
-        match self.values.get(k) {
-            Some(v) => Some(v.clone()),
-            None => match &self.enclosing {
-                Some(parent) => parent.borrow().get(k),
-                None => None,
-            },
+    pub fn get_at(&self, slot: Slot) -> Option<LoxValue> {
+        if slot.depth == 0 {
+            return self.values.get(slot.index).cloned();
         }
+        let parent = Slot {
+            depth: slot.depth - 1,
+            ..slot
+        };
+        self.enclosing.as_ref()?.borrow().get_at(parent)
     }
 
-    pub fn get_at(&self, name: String, distance: i32) -> Option<LoxValue> {
-        if distance == 0 {
-            return self.get(&name);
+    pub fn assign_at(&mut self, slot: Slot, v: LoxValue) -> Option<LoxValue> {
+        if slot.depth == 0 {
+            let value = self.values.get_mut(slot.index)?;
+            return Some(std::mem::replace(value, v));
         }
-
-        let mut ret_env = match self.enclosing.clone() {
-            Some(parent_env) => parent_env.clone(),
-            _ => return None,
+        let parent = Slot {
+            depth: slot.depth - 1,
+            ..slot
         };
-        for _ in 1..distance {
-            let new_env = match ret_env.borrow().enclosing {
-                Some(ref parent_env) => parent_env.clone(),
-                None => return None,
-            };
-            ret_env = new_env;
-        }
-        let ret = ret_env.borrow().get(&name);
-        ret
+        self.enclosing.as_ref()?.borrow_mut().assign_at(parent, v)
     }
 }
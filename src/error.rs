@@ -1,13 +1,766 @@
+use std::error;
 use std::fmt;
 
-#[derive(Debug)]
-pub struct Error {
-    pub kind: String,
-    pub msg: String,
+use crate::diagnostic::{Diagnostic, Label};
+use crate::limits::Limit;
+use crate::lox_value::LoxValue;
+use crate::token::{Span, Token, TokenType};
+
+// The larger errors are boxed to keep `Result<_, LoxError>` small.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LoxError {
+    Lex(Vec<LexError>),
+    Parse(Vec<ParseError>),
+    Resolve(Box<ResolveError>),
+    Compile(CompileError),
+    Module(ModuleError),
+    Runtime(Box<RuntimeError>),
+}
+
+impl LoxError {
+    pub fn runtime(kind: RuntimeErrorKind) -> LoxError {
+        LoxError::Runtime(Box::new(RuntimeError {
+            kind,
+            span: None,
+            backtrace: Vec::new(),
+        }))
+    }
+
+    // Points a runtime error raised somewhere without source information, such
+    // as `LoxValue` arithmetic, at the token that triggered it.
+    pub fn at(mut self, span: Span) -> LoxError {
+        if let LoxError::Runtime(ref mut err) = self {
+            err.span.get_or_insert(span);
+        }
+        self
+    }
+
+    // Whether the error ends the run outright, letting `try` through.
+    pub fn is_uncatchable(&self) -> bool {
+        match self {
+            LoxError::Runtime(err) => matches!(
+                err.kind,
+                RuntimeErrorKind::LimitExceeded(_) | RuntimeErrorKind::Cancelled
+            ),
+            _ => false,
+        }
+    }
+
+    // Code of the first error, e.g. `E0402`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            LoxError::Lex(errors) => errors.first().map_or("E0100", |err| err.kind.code()),
+            LoxError::Parse(errors) => errors.first().map_or("E0200", |err| err.kind.code()),
+            LoxError::Resolve(err) => err.kind.code(),
+            LoxError::Compile(err) => err.kind.code(),
+            LoxError::Module(err) => err.kind.code(),
+            LoxError::Runtime(err) => err.kind.code(),
+        }
+    }
+
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        match self {
+            LoxError::Lex(errors) => errors.iter().map(|err| err.diagnostic()).collect(),
+            LoxError::Parse(errors) => errors.iter().map(|err| err.diagnostic()).collect(),
+            LoxError::Resolve(err) => vec![err.diagnostic()],
+            LoxError::Compile(err) => vec![err.diagnostic()],
+            LoxError::Module(err) => vec![err.diagnostic()],
+            LoxError::Runtime(err) => vec![err.diagnostic()],
+        }
+    }
+}
+
+impl fmt::Display for LoxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoxError::Lex(errors) => write_all(f, errors),
+            LoxError::Parse(errors) => write_all(f, errors),
+            LoxError::Resolve(err) => write!(f, "{}", err),
+            LoxError::Compile(err) => write!(f, "{}", err),
+            LoxError::Module(err) => write!(f, "{}", err),
+            LoxError::Runtime(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for LoxError {}
+
+fn write_all<T: fmt::Display>(f: &mut fmt::Formatter<'_>, errors: &[T]) -> fmt::Result {
+    for (i, err) in errors.iter().enumerate() {
+        if i > 0 {
+            writeln!(f)?;
+        }
+        write!(f, "{}", err)?;
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LexErrorKind {
+    UnexpectedCharacter(char),
+    UnterminatedString,
+    InvalidNumber(String),
+}
+
+impl LexErrorKind {
+    pub fn code(&self) -> &'static str {
+        match self {
+            LexErrorKind::UnexpectedCharacter(_) => "E0101",
+            LexErrorKind::UnterminatedString => "E0102",
+            LexErrorKind::InvalidNumber(_) => "E0103",
+        }
+    }
+}
+
+impl fmt::Display for LexErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexErrorKind::UnexpectedCharacter(c) => write!(f, "unexpected character '{}'", c),
+            LexErrorKind::UnterminatedString => write!(f, "unterminated string"),
+            LexErrorKind::InvalidNumber(text) => write!(f, "invalid number literal '{}'", text),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub span: Span,
+}
+
+impl LexError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::new(self.kind.to_string())
+            .with_code(self.kind.code())
+            .with_label(self.span, "".to_string())
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[line {}:{}] error: {}",
+            self.span.line, self.span.column, self.kind
+        )
+    }
+}
+
+impl error::Error for LexError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseErrorKind {
+    // `msg` says what the parser was looking for, e.g. "expect ')' after arguments".
+    Expected { expected: TokenType, msg: String },
+    ExpectedExpression,
+    InvalidAssignmentTarget,
+    TooManyParameters,
+    TooManyArguments,
+    ImportNotAtTopLevel,
+    // Code nested deeper than the given limit.
+    TooDeep(usize),
+}
+
+impl ParseErrorKind {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ParseErrorKind::Expected { .. } => "E0201",
+            ParseErrorKind::ExpectedExpression => "E0202",
+            ParseErrorKind::InvalidAssignmentTarget => "E0203",
+            ParseErrorKind::TooManyParameters => "E0204",
+            ParseErrorKind::TooManyArguments => "E0205",
+            ParseErrorKind::ImportNotAtTopLevel => "E0206",
+            ParseErrorKind::TooDeep(_) => "E0207",
+        }
+    }
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseErrorKind::Expected { msg, .. } => write!(f, "{}", msg),
+            ParseErrorKind::ExpectedExpression => write!(f, "expect expression"),
+            ParseErrorKind::InvalidAssignmentTarget => write!(f, "invalid assignment target"),
+            ParseErrorKind::TooManyParameters => write!(f, "can't have more than 255 parameters"),
+            ParseErrorKind::TooManyArguments => write!(f, "can't have more than 255 arguments"),
+            ParseErrorKind::ImportNotAtTopLevel => {
+                write!(f, "imports must be at the top level of a file")
+            }
+            ParseErrorKind::TooDeep(limit) => {
+                write!(f, "code nests more than {} levels deep", limit)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    // Boxed so the parser's `Result<_, ParseError>` stays small.
+    pub token: Box<Token>,
+}
+
+impl ParseError {
+    pub fn new(token: Token, kind: ParseErrorKind) -> ParseError {
+        ParseError {
+            kind,
+            token: Box::new(token),
+        }
+    }
+
+    pub fn line(&self) -> usize {
+        self.token.span.line
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::new(self.kind.to_string())
+            .with_code(self.kind.code())
+            .with_label(self.token.span, "".to_string())
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.token.token_type == TokenType::Eof {
+            write!(f, "[line {}] error at end: {}", self.line(), self.kind)
+        } else {
+            write!(
+                f,
+                "[line {}] error at '{}': {}",
+                self.line(),
+                self.token.lexeme,
+                self.kind
+            )
+        }
+    }
+}
+
+impl error::Error for ParseError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ResolveErrorKind {
+    AlreadyDeclared(String),
+    ReadInOwnInitializer(String),
+    InheritFromSelf(String),
+    ReturnAtTopLevel,
+    ReturnFromInitializer,
+    ThisOutsideClass,
+    SuperOutsideClass,
+    SuperWithoutSuperclass,
+    // The keyword, `break` or `continue`.
+    OutsideLoop(String),
+}
+
+impl ResolveErrorKind {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ResolveErrorKind::AlreadyDeclared(_) => "E0301",
+            ResolveErrorKind::ReadInOwnInitializer(_) => "E0302",
+            ResolveErrorKind::InheritFromSelf(_) => "E0303",
+            ResolveErrorKind::ReturnAtTopLevel => "E0304",
+            ResolveErrorKind::ReturnFromInitializer => "E0305",
+            ResolveErrorKind::ThisOutsideClass => "E0306",
+            ResolveErrorKind::SuperOutsideClass => "E0307",
+            ResolveErrorKind::SuperWithoutSuperclass => "E0308",
+            ResolveErrorKind::OutsideLoop(_) => "E0309",
+        }
+    }
+}
+
+impl fmt::Display for ResolveErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveErrorKind::AlreadyDeclared(name) => write!(
+                f,
+                "variable `{}` that has the name already exists in this scope",
+                name
+            ),
+            ResolveErrorKind::ReadInOwnInitializer(name) => write!(
+                f,
+                "can't read local variable `{}` in its own initializer",
+                name
+            ),
+            ResolveErrorKind::InheritFromSelf(name) => {
+                write!(f, "class `{}` can't inherit from itself", name)
+            }
+            ResolveErrorKind::ReturnAtTopLevel => write!(f, "can't return from top-level code"),
+            ResolveErrorKind::ReturnFromInitializer => {
+                write!(f, "can't return a value from an initializer")
+            }
+            ResolveErrorKind::ThisOutsideClass => write!(f, "can't use 'this' outside of a class"),
+            ResolveErrorKind::SuperOutsideClass => {
+                write!(f, "can't use 'super' outside of a class")
+            }
+            ResolveErrorKind::SuperWithoutSuperclass => {
+                write!(f, "can't use 'super' in a class with no superclass")
+            }
+            ResolveErrorKind::OutsideLoop(keyword) => {
+                write!(f, "can't use '{}' outside of a loop", keyword)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResolveError {
+    pub kind: ResolveErrorKind,
+    pub token: Token,
+}
+
+impl ResolveError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::new(self.kind.to_string())
+            .with_code(self.kind.code())
+            .with_label(self.token.span, "".to_string())
+    }
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[line {}] error: {}", self.token.span.line, self.kind)
+    }
+}
+
+impl error::Error for ResolveError {}
+
+impl From<ResolveError> for LoxError {
+    fn from(err: ResolveError) -> LoxError {
+        LoxError::Resolve(Box::new(err))
+    }
+}
+
+// Limits of the bytecode format that a valid program can still run into.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CompileErrorKind {
+    TooManyConstants,
+    TooManyLocals,
+    TooManyUpvalues,
+    JumpTooLarge,
+    TooManyElements,
+}
+
+impl CompileErrorKind {
+    pub fn code(&self) -> &'static str {
+        match self {
+            CompileErrorKind::TooManyConstants => "E0501",
+            CompileErrorKind::TooManyLocals => "E0502",
+            CompileErrorKind::TooManyUpvalues => "E0503",
+            CompileErrorKind::JumpTooLarge => "E0504",
+            CompileErrorKind::TooManyElements => "E0505",
+        }
+    }
+}
+
+impl fmt::Display for CompileErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileErrorKind::TooManyConstants => write!(f, "too many constants in one chunk"),
+            CompileErrorKind::TooManyLocals => {
+                write!(f, "too many local variables in function")
+            }
+            CompileErrorKind::TooManyUpvalues => {
+                write!(f, "too many closure variables in function")
+            }
+            CompileErrorKind::JumpTooLarge => write!(f, "too much code to jump over"),
+            CompileErrorKind::TooManyElements => {
+                write!(f, "too many elements in collection literal")
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompileError {
+    pub kind: CompileErrorKind,
+    pub span: Span,
+}
+
+impl CompileError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::new(self.kind.to_string())
+            .with_code(self.kind.code())
+            .with_label(self.span, "".to_string())
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[line {}] error: {}", self.span.line, self.kind)
+    }
+}
+
+impl error::Error for CompileError {}
+
+impl From<CompileError> for LoxError {
+    fn from(err: CompileError) -> LoxError {
+        LoxError::Compile(err)
+    }
+}
+
+// Problems finding and loading the files a program imports. Paths are as
+// written in the `import`, or as found for cycles.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ModuleErrorKind {
+    NotFound { path: String },
+    Unreadable { path: String, reason: String },
+    // Each file in the cycle, starting and ending with the same one.
+    Cycle { files: Vec<String> },
+    NotExported { path: String, name: String },
+}
+
+impl ModuleErrorKind {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ModuleErrorKind::NotFound { .. } => "E0601",
+            ModuleErrorKind::Unreadable { .. } => "E0602",
+            ModuleErrorKind::Cycle { .. } => "E0603",
+            ModuleErrorKind::NotExported { .. } => "E0604",
+        }
+    }
 }
 
-impl fmt::Display for Error {
+impl fmt::Display for ModuleErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.msg)
+        match self {
+            ModuleErrorKind::NotFound { path } => write!(f, "can't find module \"{}\"", path),
+            ModuleErrorKind::Unreadable { path, reason } => {
+                write!(f, "couldn't read module \"{}\": {}", path, reason)
+            }
+            ModuleErrorKind::Cycle { files } => write!(f, "import cycle: {}", files.join(" -> ")),
+            ModuleErrorKind::NotExported { path, name } => {
+                write!(f, "module \"{}\" doesn't export `{}`", path, name)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModuleError {
+    pub kind: ModuleErrorKind,
+    // The path or name in the `import` that failed.
+    pub span: Span,
+}
+
+impl ModuleError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::new(self.kind.to_string())
+            .with_code(self.kind.code())
+            .with_label(self.span, "".to_string())
+    }
+}
+
+impl fmt::Display for ModuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[line {}] error: {}", self.span.line, self.kind)
+    }
+}
+
+impl error::Error for ModuleError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RuntimeErrorKind {
+    // `op` is the operator lexeme, `actual` the type names of its operands.
+    OperandType {
+        op: String,
+        expected: &'static str,
+        actual: Vec<&'static str>,
+    },
+    DivisionByZero,
+    UndefinedVariable(String),
+    UndefinedProperty(String),
+    NotAnInstance {
+        actual: &'static str,
+    },
+    NotCallable {
+        actual: &'static str,
+    },
+    Arity {
+        callee: String,
+        expected: usize,
+        got: usize,
+    },
+    SuperclassNotClass {
+        actual: &'static str,
+    },
+    NotIndexable {
+        actual: &'static str,
+    },
+    // `actual` is the offending number, or the type name of a non-number.
+    InvalidIndex {
+        actual: String,
+    },
+    IndexOutOfBounds {
+        index: f64,
+        len: usize,
+    },
+    UnhashableKey {
+        actual: &'static str,
+    },
+    KeyNotFound {
+        key: String,
+    },
+    // A value thrown with `throw`, described as in "uncaught exception: 1".
+    // The backend raising it holds on to the value itself, which can't
+    // leave the thread.
+    Thrown(String),
+    // `index` counts from 1; `expected` reads as in "must be a number".
+    ArgumentType {
+        callee: String,
+        index: usize,
+        expected: String,
+        actual: &'static str,
+    },
+    // A method of a host class was called on an instance that its `init`
+    // didn't set up, e.g. from a subclass that skipped `super.init`.
+    NoHostValue {
+        class: String,
+        method: String,
+    },
+    // An error returned by a host function, with its message.
+    Host(String),
+    // Writing to the output sink failed, e.g. because a pipe was closed.
+    Output(String),
+    // Ends the run outright: neither `catch` nor `finally` blocks see it.
+    LimitExceeded(Limit),
+    // The host interrupted the run. Uncatchable too.
+    Cancelled,
+}
+
+impl RuntimeErrorKind {
+    // An instance with a `message` field, like the ones `catch` receives for
+    // runtime errors, is described by its class and message.
+    pub fn thrown(value: &LoxValue) -> RuntimeErrorKind {
+        if let LoxValue::Instance(instance) = value {
+            let instance = instance.borrow();
+            if let Some(message) = instance.field("message") {
+                return RuntimeErrorKind::Thrown(format!("{}: {}", instance.class().name, message));
+            }
+        }
+        RuntimeErrorKind::Thrown(format!("exception: {}", value))
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            RuntimeErrorKind::OperandType { .. } => "E0401",
+            RuntimeErrorKind::DivisionByZero => "E0402",
+            RuntimeErrorKind::UndefinedVariable(_) => "E0403",
+            RuntimeErrorKind::UndefinedProperty(_) => "E0404",
+            RuntimeErrorKind::NotAnInstance { .. } => "E0405",
+            RuntimeErrorKind::NotCallable { .. } => "E0406",
+            RuntimeErrorKind::Arity { .. } => "E0407",
+            RuntimeErrorKind::SuperclassNotClass { .. } => "E0408",
+            RuntimeErrorKind::NotIndexable { .. } => "E0409",
+            RuntimeErrorKind::InvalidIndex { .. } => "E0410",
+            RuntimeErrorKind::IndexOutOfBounds { .. } => "E0411",
+            RuntimeErrorKind::UnhashableKey { .. } => "E0412",
+            RuntimeErrorKind::KeyNotFound { .. } => "E0413",
+            RuntimeErrorKind::Thrown(_) => "E0414",
+            RuntimeErrorKind::ArgumentType { .. } => "E0415",
+            RuntimeErrorKind::NoHostValue { .. } => "E0416",
+            RuntimeErrorKind::Host(_) => "E0417",
+            RuntimeErrorKind::Output(_) => "E0418",
+            RuntimeErrorKind::LimitExceeded(_) => "E0419",
+            RuntimeErrorKind::Cancelled => "E0420",
+        }
+    }
+}
+
+impl fmt::Display for RuntimeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeErrorKind::OperandType {
+                op,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "operands of `{}` must be {}, got {}",
+                op,
+                expected,
+                actual.join(" and ")
+            ),
+            RuntimeErrorKind::DivisionByZero => write!(f, "divided by zero"),
+            RuntimeErrorKind::UndefinedVariable(name) => {
+                write!(f, "undefined variable `{}`", name)
+            }
+            RuntimeErrorKind::UndefinedProperty(name) => {
+                write!(f, "undefined property `{}`", name)
+            }
+            RuntimeErrorKind::NotAnInstance { actual } => {
+                write!(f, "only instances have properties, got {}", actual)
+            }
+            RuntimeErrorKind::NotCallable { actual } => {
+                write!(f, "can only call functions and classes, got {}", actual)
+            }
+            RuntimeErrorKind::Arity {
+                callee,
+                expected,
+                got,
+            } => write!(
+                f,
+                "wrong number of arguments in `{}`: expected {}, got {}",
+                callee, expected, got
+            ),
+            RuntimeErrorKind::SuperclassNotClass { actual } => {
+                write!(f, "superclass must be a class, got {}", actual)
+            }
+            RuntimeErrorKind::NotIndexable { actual } => {
+                write!(f, "only lists and maps can be indexed, got {}", actual)
+            }
+            RuntimeErrorKind::InvalidIndex { actual } => {
+                write!(f, "list index must be an integer, got {}", actual)
+            }
+            RuntimeErrorKind::IndexOutOfBounds { index, len } => write!(
+                f,
+                "index {} out of bounds for list of length {}",
+                index, len
+            ),
+            RuntimeErrorKind::UnhashableKey { actual } => write!(
+                f,
+                "map keys must be numbers, strings, bools, nil or instances, got {}",
+                actual
+            ),
+            RuntimeErrorKind::KeyNotFound { key } => write!(f, "key {} not found in map", key),
+            RuntimeErrorKind::Thrown(description) => write!(f, "uncaught {}", description),
+            RuntimeErrorKind::ArgumentType {
+                callee,
+                index,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "argument {} of `{}` must be {}, got {}",
+                index, callee, expected, actual
+            ),
+            RuntimeErrorKind::NoHostValue { class, method } => write!(
+                f,
+                "`{}.{}` called on an instance that `{}.init` didn't set up",
+                class, method, class
+            ),
+            RuntimeErrorKind::Host(message) => write!(f, "{}", message),
+            RuntimeErrorKind::Output(reason) => write!(f, "couldn't write output: {}", reason),
+            RuntimeErrorKind::LimitExceeded(limit) => {
+                write!(f, "execution limit exceeded: {}", limit)
+            }
+            RuntimeErrorKind::Cancelled => write!(f, "cancelled by the host"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RuntimeError {
+    pub kind: RuntimeErrorKind,
+    pub span: Option<Span>,
+    // Innermost frame first; each label names a function and the point
+    // execution had reached inside it.
+    pub backtrace: Vec<Label>,
+}
+
+impl RuntimeError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        let mut diagnostic = Diagnostic::new(self.kind.to_string()).with_code(self.kind.code());
+        if let Some(span) = self.span {
+            diagnostic = diagnostic.with_label(span, "".to_string());
+        }
+        diagnostic.backtrace = self.backtrace.clone();
+        diagnostic
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "[line {}] error: {}", span.line, self.kind)?,
+            None => write!(f, "error: {}", self.kind)?,
+        }
+        for frame in &self.backtrace {
+            write!(f, "\n    at {} (line {})", frame.msg, frame.span.line)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for RuntimeError {}
+
+// Long-form description of an error code, in the spirit of `rustc --explain`.
+pub fn explain(code: &str) -> Option<&'static str> {
+    let text = match code {
+        "E0100" => "The scanner rejected the source.",
+        "E0101" => "The scanner found a character that doesn't start any Lox token.",
+        "E0102" => "A string literal is missing its closing '\"'.",
+        "E0103" => "A number literal couldn't be converted to a number.",
+        "E0200" => "The parser rejected the source.",
+        "E0201" => "The parser expected a specific token, such as ';' or ')', and found another.",
+        "E0202" => "The parser expected the start of an expression.",
+        "E0203" => "Only variables and properties can be assigned to.",
+        "E0204" => "A function can't declare more than 255 parameters.",
+        "E0205" => "A call can't pass more than 255 arguments.",
+        "E0206" => "`import` was used inside a block or function rather than at the top of a file.",
+        "E0207" => "Statements or expressions are nested too deeply, for example in dozens of nested parentheses.",
+        "E0301" => "A local variable was declared twice in the same scope.",
+        "E0302" => "A local variable was read inside its own initializer.",
+        "E0303" => "A class names itself as its superclass.",
+        "E0304" => "`return` was used outside of any function.",
+        "E0305" => "An `init` method returns a value; initializers always return `this`.",
+        "E0306" => "`this` was used outside of a method.",
+        "E0307" => "`super` was used outside of a method.",
+        "E0308" => "`super` was used in a class that has no superclass.",
+        "E0309" => "`break` or `continue` was used outside of a loop.",
+        "E0401" => "An operator was applied to operands of the wrong type.",
+        "E0402" => "A number was divided by zero.",
+        "E0403" => "A variable was read before it was defined.",
+        "E0404" => "An instance has neither a field nor a method with that name.",
+        "E0405" => "A property was accessed on a value that isn't an instance.",
+        "E0406" => "A value that is neither a function nor a class was called.",
+        "E0407" => "A function was called with the wrong number of arguments.",
+        "E0408" => "A class tried to inherit from a value that isn't a class.",
+        "E0409" => "A value that is neither a list nor a map was indexed.",
+        "E0410" => "A list was indexed with something other than a whole number.",
+        "E0411" => "A list index was negative or past the end of the list.",
+        "E0412" => "A map key was a value that can't be hashed, such as a list or a function.",
+        "E0413" => "A map was indexed with a key it doesn't contain.",
+        "E0414" => "A value was thrown with `throw` and no `catch` clause caught it.",
+        "E0415" => "A native function such as `sqrt` was passed an argument of the wrong type.",
+        "E0416" => "A method of a class defined in Rust was called on an instance whose Rust value was never created. A subclass's `init` has to call `super.init`.",
+        "E0417" => "A function or method defined in Rust returned an error.",
+        "E0418" => "`print` couldn't write to the output, for example because it is a pipe that was closed.",
+        "E0419" => "The program used up the fuel, time or call depth its host allowed it. Scripts can't catch this error.",
+        "E0420" => "The host stopped the program through an `InterruptHandle`. Scripts can't catch this error.",
+        "E0501" => "A function uses more than 65536 distinct constants.",
+        "E0502" => "A function has more than 256 local variables in scope at once.",
+        "E0503" => "A function captures more than 256 variables from enclosing functions.",
+        "E0504" => "A jump in the bytecode would span more than 65535 instructions.",
+        "E0505" => "A collection literal has more than 65535 elements.",
+        "E0601" => {
+            "No file matches an imported path, next to the importing file or on the search path."
+        }
+        "E0602" => "An imported file exists but couldn't be read.",
+        "E0603" => "A module imports itself, directly or through other modules.",
+        "E0604" => {
+            "An `import { ... }` names something the module doesn't declare at its top level."
+        }
+        _ => return None,
+    };
+    Some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_code_is_explained() {
+        // Every code literal in this file, which covers the `code` tables.
+        let source = include_str!("error.rs");
+        let codes: Vec<&str> = source
+            .match_indices("\"E0")
+            .map(|(i, _)| &source[i + 1..i + 6])
+            .filter(|code| code[1..].chars().all(|c| c.is_ascii_digit()))
+            .collect();
+        assert!(codes.len() > 20);
+        for code in codes {
+            assert!(explain(code).is_some(), "{} has no explanation", code);
+        }
+        assert_eq!(explain("E9999"), None);
     }
 }
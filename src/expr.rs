@@ -1,5 +1,6 @@
-use crate::error::Error;
+use crate::error::LoxError;
 use crate::lox_value::LoxValue;
+use crate::stmt::Stmt;
 use crate::token::{Literal, Token};
 use crate::visitor::Visitor;
 
@@ -7,21 +8,55 @@ use std::cell::Cell;
 use std::fmt;
 use std::rc::Rc;
 
+// Where the resolver found a local variable: `depth` scopes out from the one
+// in use, at position `index` in that scope. Globals keep the default, whose
+// depth is negative.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Slot {
+    pub depth: i32,
+    pub index: usize,
+}
+
+impl Default for Slot {
+    fn default() -> Slot {
+        Slot {
+            depth: -1,
+            index: 0,
+        }
+    }
+}
+
+impl Slot {
+    pub fn is_global(&self) -> bool {
+        self.depth < 0
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Clone, Debug, PartialEq)]
 pub enum Expr {
-    Assign(Token, Box<Expr>, Rc<Cell<i32>>),
+    Assign(Token, Box<Expr>, Rc<Cell<Slot>>),
     Binary(Box<Expr>, Token, Box<Expr>),
     Call(Box<Expr>, Token, Vec<Expr>),
     Get(Box<Expr>, Token),
     Grouping(Box<Expr>),
+    // `xs[index]`; the token is the closing bracket.
+    Index(Box<Expr>, Token, Box<Expr>),
+    // `fun (a) { ... }` or `(a) => ...`; the token names the function in
+    // backtraces and points at where it starts.
+    Lambda(Token, Vec<Token>, Box<Stmt>),
+    // `[a, b]`; the token is the opening bracket.
+    List(Token, Vec<Expr>),
     Literal(Literal),
     Logical(Box<Expr>, Token, Box<Expr>),
+    // `{key: value}`; the token is the opening brace.
+    Map(Token, Vec<(Expr, Expr)>),
     Set(Box<Expr>, Token, Box<Expr>),
-    Super(Token, Token, Rc<Cell<i32>>),
-    This(Token, Rc<Cell<i32>>),
+    SetIndex(Box<Expr>, Token, Box<Expr>, Box<Expr>),
+    Super(Token, Token, Rc<Cell<Slot>>),
+    This(Token, Rc<Cell<Slot>>),
     Unary(Token, Box<Expr>),
-    Variable(Token, Rc<Cell<i32>>),
+    Variable(Token, Rc<Cell<Slot>>),
 }
 
 impl fmt::Display for Expr {
@@ -36,7 +71,14 @@ impl fmt::Display for Expr {
             }
             Expr::Get(expr, token) => write!(f, "get: ({} {})", expr, token.lexeme),
             Expr::Grouping(expr) => write!(f, "grouping: ({})", expr),
+            Expr::Index(expr, _, index) => write!(f, "index: ({} {})", expr, index),
+            Expr::Lambda(_, args, _) => {
+                let args: Vec<&str> = args.iter().map(|arg| arg.lexeme.as_str()).collect();
+                write!(f, "lambda: ({})", args.join(" "))
+            }
+            Expr::List(_, items) => write!(f, "list: ({:?})", items),
             Expr::Literal(s) => write!(f, "literal: ({})", s),
+            Expr::Map(_, entries) => write!(f, "map: ({:?})", entries),
             Expr::Logical(left_expr, token, right_expr) => write!(
                 f,
                 "logical: ({} {} {})",
@@ -45,6 +87,9 @@ impl fmt::Display for Expr {
             Expr::Set(left_expr, token, right_expr) => {
                 write!(f, "set: ({} {} {})", left_expr, token.lexeme, right_expr)
             }
+            Expr::SetIndex(expr, _, index, value) => {
+                write!(f, "set index: ({} {} {})", expr, index, value)
+            }
             Expr::Super(keyword, method, _) => {
                 write!(f, "super: ({} {})", keyword.lexeme, method.lexeme)
             }
@@ -55,21 +100,26 @@ impl fmt::Display for Expr {
     }
 }
 
-pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expr) -> Result<LoxValue, Error> {
+pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expr) -> Result<LoxValue, LoxError> {
     match expr {
-        Expr::Assign(left, right, distance) => visitor.visit_assign(left, right, distance.clone()),
+        Expr::Assign(left, right, slot) => visitor.visit_assign(left, right, slot.clone()),
         Expr::Binary(left, op, right) => visitor.visit_binary(left, op, right),
         Expr::Call(callee, paren, args) => visitor.visit_call(callee, paren, args.to_vec()),
         Expr::Get(expr, name) => visitor.visit_get(expr, name),
         Expr::Grouping(expr) => visitor.visit_grouping(expr),
+        Expr::Index(expr, bracket, index) => visitor.visit_index(expr, bracket, index),
+        Expr::Lambda(name, args, body) => visitor.visit_lambda(name, args, body),
+        Expr::List(bracket, items) => visitor.visit_list(bracket, items),
         Expr::Literal(lit) => visitor.visit_literal(lit),
         Expr::Logical(left, op, right) => visitor.visit_logical(left, op, right),
+        Expr::Map(brace, entries) => visitor.visit_map(brace, entries),
         Expr::Set(expr, name, value) => visitor.visit_set(expr, name, value),
-        Expr::Super(keyword, method, distance) => {
-            visitor.visit_super(keyword, method, distance.clone())
+        Expr::SetIndex(expr, bracket, index, value) => {
+            visitor.visit_set_index(expr, bracket, index, value)
         }
-        Expr::This(token, distance) => visitor.visit_this(token, distance.clone()),
+        Expr::Super(keyword, method, slot) => visitor.visit_super(keyword, method, slot.clone()),
+        Expr::This(token, slot) => visitor.visit_this(token, slot.clone()),
         Expr::Unary(token, expr) => visitor.visit_unary(token, expr),
-        Expr::Variable(name, distance) => visitor.visit_var_expr(name, distance.clone()),
+        Expr::Variable(name, slot) => visitor.visit_var_expr(name, slot.clone()),
     }
 }
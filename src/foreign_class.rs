@@ -0,0 +1,313 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+use crate::error::{LoxError, RuntimeErrorKind};
+use crate::gc::Heap;
+use crate::host_fn::{argument, FromLox, IntoLox};
+use crate::lox_class::{LoxClass, Method};
+use crate::lox_value::LoxValue;
+use crate::native_fn::MethodFunction;
+
+// A Rust type exposed to Lox as a class. Calling the class runs `init`,
+// whose result becomes the Rust value behind the new instance; methods and
+// getters borrow that value. Lox classes can inherit from it like from any
+// other class, as long as their `init` calls `super.init`.
+//
+//     let counter = ForeignClass::new("Counter")
+//         .init(|start: f64| Counter { n: start })
+//         .method("add", |counter: &mut Counter, by: f64| counter.n += by)
+//         .getter("n", |counter: &Counter| counter.n);
+//     lox.define_class(counter);
+pub struct ForeignClass<T> {
+    name: String,
+    methods: HashMap<String, Method>,
+    host: PhantomData<T>,
+}
+
+// A method of a `ForeignClass`, called with the instance as the receiver.
+pub struct ForeignMethod {
+    pub name: String,
+    pub arity: usize,
+    // Getters run when the property is read rather than being bound.
+    pub getter: bool,
+    pub function: Rc<MethodFunction>,
+}
+
+impl fmt::Debug for ForeignMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ForeignMethod({})", self.name)
+    }
+}
+
+impl<T: 'static> ForeignClass<T> {
+    pub fn new(name: &str) -> ForeignClass<T> {
+        ForeignClass {
+            name: name.to_string(),
+            methods: HashMap::new(),
+            host: PhantomData,
+        }
+    }
+
+    // Creates the Rust value from the arguments the class is called with.
+    pub fn init<Args, F: ForeignInit<T, Args>>(self, init: F) -> ForeignClass<T> {
+        let init = init.into_init(&self.name);
+        self.with(init)
+    }
+
+    pub fn method<Args, F: ForeignFn<T, Args>>(self, name: &str, method: F) -> ForeignClass<T> {
+        let method = method.into_method(&self.name, name);
+        self.with(method)
+    }
+
+    // A property computed from the Rust value each time it is read.
+    pub fn getter<R, F>(self, name: &str, getter: F) -> ForeignClass<T>
+    where
+        R: IntoLox,
+        F: Fn(&T) -> R + 'static,
+    {
+        let (class, callee) = (self.name.clone(), name.to_string());
+        let function = move |heap: &mut Heap, this: &LoxValue, _args: &[LoxValue]| {
+            let host = host::<T>(&class, &callee, this)?;
+            let result = getter(&host.borrow());
+            result.into_lox(heap)
+        };
+        self.with(ForeignMethod {
+            name: name.to_string(),
+            arity: 0,
+            getter: true,
+            function: Rc::new(function),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn build(self) -> LoxClass {
+        LoxClass::new(self.name, None, self.methods)
+    }
+
+    fn with(mut self, method: ForeignMethod) -> ForeignClass<T> {
+        self.methods
+            .insert(method.name.clone(), Method::Foreign(Rc::new(method)));
+        self
+    }
+}
+
+// The Rust value behind `this`, which `method` of `class` was called on.
+fn host<T: 'static>(
+    class: &str,
+    method: &str,
+    this: &LoxValue,
+) -> Result<Rc<RefCell<T>>, LoxError> {
+    let host = match this {
+        LoxValue::Instance(instance) => instance.borrow().host().cloned(),
+        _ => None,
+    };
+    host.and_then(|host| host.downcast::<RefCell<T>>().ok())
+        .ok_or_else(|| {
+            LoxError::runtime(RuntimeErrorKind::NoHostValue {
+                class: class.to_string(),
+                method: method.to_string(),
+            })
+        })
+}
+
+// A Rust closure that can be a method of a `ForeignClass<T>`: it takes the
+// Rust value and then the arguments. `Args` is the tuple of the argument
+// types, which only tells the implementations apart.
+pub trait ForeignFn<T, Args> {
+    fn into_method(self, class: &str, name: &str) -> ForeignMethod;
+}
+
+// A Rust closure that can be the `init` of a `ForeignClass<T>`.
+pub trait ForeignInit<T, Args> {
+    fn into_init(self, class: &str) -> ForeignMethod;
+}
+
+macro_rules! foreign_fn {
+    ($arity:expr $(, $arg:ident $index:tt)*) => {
+        impl<T, F, R $(, $arg)*> ForeignFn<T, ($($arg,)*)> for F
+        where
+            T: 'static,
+            F: Fn(&mut T $(, $arg)*) -> R + 'static,
+            R: IntoLox,
+            $($arg: FromLox,)*
+        {
+            // Without arguments, `converted` and `args` go unused.
+            #[allow(unused_variables)]
+            fn into_method(self, class: &str, name: &str) -> ForeignMethod {
+                let (class, method) = (class.to_string(), name.to_string());
+                let callee = format!("{}.{}", class, method);
+                let function = move |heap: &mut Heap, this: &LoxValue, args: &[LoxValue]| {
+                    let converted = ($(argument::<$arg>(&callee, args, $index)?,)*);
+                    let host = host::<T>(&class, &method, this)?;
+                    let result = self(&mut *host.borrow_mut() $(, converted.$index)*);
+                    result.into_lox(heap)
+                };
+                ForeignMethod {
+                    name: name.to_string(),
+                    arity: $arity,
+                    getter: false,
+                    function: Rc::new(function),
+                }
+            }
+        }
+
+        impl<T, F $(, $arg)*> ForeignInit<T, ($($arg,)*)> for F
+        where
+            T: 'static,
+            F: Fn($($arg),*) -> T + 'static,
+            $($arg: FromLox,)*
+        {
+            #[allow(unused_variables)]
+            fn into_init(self, class: &str) -> ForeignMethod {
+                let callee = format!("{}.init", class);
+                let function = move |_heap: &mut Heap, this: &LoxValue, args: &[LoxValue]| {
+                    let host = self($(argument::<$arg>(&callee, args, $index)?),*);
+                    if let LoxValue::Instance(instance) = this {
+                        instance.borrow_mut().set_host(Rc::new(RefCell::new(host)));
+                    }
+                    Ok(LoxValue::Nil)
+                };
+                ForeignMethod {
+                    name: "init".to_string(),
+                    arity: $arity,
+                    getter: false,
+                    function: Rc::new(function),
+                }
+            }
+        }
+    };
+}
+
+foreign_fn!(0);
+foreign_fn!(1, A 0);
+foreign_fn!(2, A 0, B 1);
+foreign_fn!(3, A 0, B 1, C 2);
+foreign_fn!(4, A 0, B 1, C 2, D 3);
+foreign_fn!(5, A 0, B 1, C 2, D 3, E 4);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lox_value::LoxValue;
+    use crate::test_support::on_each_backend;
+
+    struct Connection {
+        url: String,
+        log: Vec<String>,
+        open: bool,
+    }
+
+    fn connection_class() -> ForeignClass<Connection> {
+        ForeignClass::new("Connection")
+            .init(|url: String| Connection {
+                url,
+                log: Vec::new(),
+                open: true,
+            })
+            .method("query", |conn: &mut Connection, sql: String| {
+                if !conn.open {
+                    return Err(format!("{} is closed", conn.url));
+                }
+                conn.log.push(sql);
+                Ok(conn.log.len() as f64)
+            })
+            .method("close", |conn: &mut Connection| conn.open = false)
+            .getter("url", |conn: &Connection| conn.url.clone())
+            .getter("log", |conn: &Connection| conn.log.clone())
+    }
+
+    #[test]
+    fn test_foreign_classes() {
+        let source = "var conn = Connection(\"db://a\");
+conn.query(\"select 1\");
+var query = conn.query;
+query(\"select 2\");
+class Pooled < Connection {
+  init(url, size) {
+    super.init(url);
+    this.size = size;
+  }
+  describe() { return this.url + \" x\" + str(this.size); }
+}
+var pooled = Pooled(\"db://b\", 4);
+pooled.query(\"begin\");
+conn.close();
+var caught;
+try { conn.query(\"select 3\"); } catch (e) { caught = e.message; }
+[conn.log, pooled.describe(), pooled.log, caught, type(conn)];";
+        on_each_backend(|mut lox, backend| {
+            lox.define_class(connection_class());
+            assert_eq!(
+                lox.run(source).unwrap().to_string(),
+                "[[\"select 1\", \"select 2\"], \"db://b x4\", [\"begin\"], \
+                 \"db://a is closed\", \"instance\"]",
+                "{:?}",
+                backend
+            );
+
+            let errors = [
+                ("Connection(1);", "E0415"),
+                ("Connection();", "E0407"),
+                ("Connection(\"c\").query();", "E0407"),
+                ("Connection(\"c\").query(nil);", "E0415"),
+                (
+                    "class Bad < Connection { init() {} }\nBad().query(\"x\");",
+                    "E0416",
+                ),
+                (
+                    "var c = Connection(\"c\"); c.close(); c.query(\"x\");",
+                    "E0417",
+                ),
+            ];
+            for (program, code) in errors.iter() {
+                let err = lox.run(program).unwrap_err();
+                assert_eq!(err.code(), *code, "{} ({:?})", program, backend);
+            }
+            let err = lox.run("Connection(\"c\").query(1);").unwrap_err();
+            assert_eq!(
+                err.to_string().lines().next().unwrap(),
+                "[line 1] error: argument 1 of `Connection.query` must be a string, got number"
+            );
+        });
+    }
+
+    #[test]
+    fn test_wrap_host_values() {
+        on_each_backend(|mut lox, backend| {
+            lox.define_class(connection_class());
+            let open = Connection {
+                url: "db://host".to_string(),
+                log: vec!["connect".to_string()],
+                open: true,
+            };
+            let conn = lox.wrap("Connection", open).unwrap();
+            lox.set_global("conn", conn.clone());
+            assert_eq!(
+                lox.run("conn.query(\"select 1\"); [conn.url, conn.log];")
+                    .unwrap()
+                    .to_string(),
+                "[\"db://host\", [\"connect\", \"select 1\"]]",
+                "{:?}",
+                backend
+            );
+            assert_eq!(lox.run("conn == conn;").unwrap(), LoxValue::Bool(true));
+
+            // The host still reaches the value through the instance.
+            if let LoxValue::Instance(instance) = &conn {
+                let host = instance.borrow().host().cloned().unwrap();
+                let host = host.downcast::<RefCell<Connection>>().unwrap();
+                assert_eq!(host.borrow().log.len(), 2);
+            }
+
+            assert_eq!(lox.wrap("Missing", 1), None);
+            lox.run("class Plain {}").unwrap();
+            assert_eq!(lox.wrap("Plain", 1), None);
+        });
+    }
+}
@@ -0,0 +1,507 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+use crate::callable::Callable;
+use crate::env::Env;
+use crate::error::{RuntimeError, RuntimeErrorKind};
+use crate::lox_class::{LoxClass, LoxClassInner, Method};
+use crate::lox_closure::{BoundMethod, LoxClosure, Upvalue};
+use crate::lox_instance::LoxInstance;
+use crate::lox_map::LoxMap;
+use crate::lox_value::LoxValue;
+
+pub const DEFAULT_THRESHOLD: usize = 1024;
+
+// Counters describing what the collector has done so far.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct GcStats {
+    // Objects allocated through the heap.
+    pub allocated: usize,
+    pub collections: usize,
+    // Objects freed by collections, i.e. that reference counting alone
+    // would have leaked.
+    pub reclaimed: usize,
+    // Heap objects still alive after the last collection.
+    pub live: usize,
+}
+
+// Keeps track of the mutable objects of a backend so that reference cycles
+// among them can be reclaimed. Values stay reference counted; a collection
+// finds the objects whose counts are only held up by other objects of the
+// same garbage and clears their contents, which lets the counts drop.
+//
+// Roots are not enumerated: any reference the heap cannot account for, such
+// as the globals, the current environment chain, the call stack or a value
+// held by Rust code, keeps an object alive. Collections are therefore safe
+// at every allocation.
+pub struct Heap {
+    objects: Vec<WeakObj>,
+    threshold: usize,
+    since_collection: usize,
+    stats: GcStats,
+}
+
+impl Default for Heap {
+    fn default() -> Heap {
+        Heap::new()
+    }
+}
+
+impl Heap {
+    pub fn new() -> Heap {
+        Heap {
+            objects: Vec::new(),
+            threshold: DEFAULT_THRESHOLD,
+            since_collection: 0,
+            stats: GcStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> GcStats {
+        self.stats
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    // Sets how many allocations trigger a collection. The count grows with
+    // the number of objects that survived the last collection so that large
+    // heaps are not traversed too often. Zero disables automatic collection.
+    pub fn set_threshold(&mut self, threshold: usize) {
+        self.threshold = threshold;
+    }
+
+    pub fn env(&mut self, env: Env) -> Rc<RefCell<Env>> {
+        let env = Rc::new(RefCell::new(env));
+        self.track(WeakObj::Env(Rc::downgrade(&env)));
+        env
+    }
+
+    pub fn instance(&mut self, instance: LoxInstance) -> Rc<RefCell<LoxInstance>> {
+        let instance = Rc::new(RefCell::new(instance));
+        self.track(WeakObj::Instance(Rc::downgrade(&instance)));
+        instance
+    }
+
+    // What a `catch` clause receives for `err`: `thrown`, the value of the
+    // `throw` that raised it, or else an instance of `class` whose `message`,
+    // `kind` and `line` fields describe an error the runtime raised. `kind`
+    // is the error code.
+    pub fn error_value(
+        &mut self,
+        err: &RuntimeError,
+        thrown: &mut Option<LoxValue>,
+        class: &LoxClass,
+    ) -> LoxValue {
+        if let RuntimeErrorKind::Thrown(_) = err.kind {
+            return thrown.take().expect("a thrown error holds its value");
+        }
+        let instance = self.instance(class.instantiate());
+        {
+            let mut instance = instance.borrow_mut();
+            instance.set_field("message".to_string(), LoxValue::Str(err.kind.to_string()));
+            instance.set_field(
+                "kind".to_string(),
+                LoxValue::Str(err.kind.code().to_string()),
+            );
+            let line = match err.span {
+                Some(span) => LoxValue::Number(span.line as f64),
+                None => LoxValue::Nil,
+            };
+            instance.set_field("line".to_string(), line);
+        }
+        LoxValue::Instance(instance)
+    }
+
+    pub fn list(&mut self, items: Vec<LoxValue>) -> Rc<RefCell<Vec<LoxValue>>> {
+        let list = Rc::new(RefCell::new(items));
+        self.track(WeakObj::List(Rc::downgrade(&list)));
+        list
+    }
+
+    pub fn map(&mut self, map: LoxMap) -> Rc<RefCell<LoxMap>> {
+        let map = Rc::new(RefCell::new(map));
+        self.track(WeakObj::Map(Rc::downgrade(&map)));
+        map
+    }
+
+    pub fn upvalue(&mut self, upvalue: Upvalue) -> Rc<RefCell<Upvalue>> {
+        let upvalue = Rc::new(RefCell::new(upvalue));
+        self.track(WeakObj::Upvalue(Rc::downgrade(&upvalue)));
+        upvalue
+    }
+
+    fn track(&mut self, object: WeakObj) {
+        self.objects.push(object);
+        self.stats.allocated += 1;
+        self.since_collection += 1;
+        if self.threshold > 0 && self.since_collection >= self.threshold.max(self.stats.live) {
+            self.collect();
+        }
+    }
+
+    // Frees every unreachable object and returns how many there were.
+    pub fn collect(&mut self) -> usize {
+        let mut graph = Graph::default();
+        for object in self.objects.iter() {
+            if let Some(object) = object.upgrade() {
+                graph.add(object);
+            }
+        }
+        graph.discover();
+        let garbage = graph.garbage();
+        for &node in garbage.iter() {
+            graph.nodes[node].clear();
+        }
+        // Dropping the graph releases the last references to the garbage.
+        drop(graph);
+
+        self.objects.retain(|object| object.is_alive());
+        self.since_collection = 0;
+        self.stats.collections += 1;
+        self.stats.reclaimed += garbage.len();
+        self.stats.live = self.objects.len();
+        garbage.len()
+    }
+}
+
+enum WeakObj {
+    Env(Weak<RefCell<Env>>),
+    Instance(Weak<RefCell<LoxInstance>>),
+    Upvalue(Weak<RefCell<Upvalue>>),
+    List(Weak<RefCell<Vec<LoxValue>>>),
+    Map(Weak<RefCell<LoxMap>>),
+}
+
+impl WeakObj {
+    fn upgrade(&self) -> Option<Obj> {
+        match self {
+            WeakObj::Env(env) => env.upgrade().map(Obj::Env),
+            WeakObj::Instance(instance) => instance.upgrade().map(Obj::Instance),
+            WeakObj::Upvalue(upvalue) => upvalue.upgrade().map(Obj::Upvalue),
+            WeakObj::List(list) => list.upgrade().map(Obj::List),
+            WeakObj::Map(map) => map.upgrade().map(Obj::Map),
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        match self {
+            WeakObj::Env(env) => env.strong_count() > 0,
+            WeakObj::Instance(instance) => instance.strong_count() > 0,
+            WeakObj::Upvalue(upvalue) => upvalue.strong_count() > 0,
+            WeakObj::List(list) => list.strong_count() > 0,
+            WeakObj::Map(map) => map.strong_count() > 0,
+        }
+    }
+}
+
+// A reference-counted object as seen by the collector.
+#[derive(Clone)]
+enum Obj {
+    Env(Rc<RefCell<Env>>),
+    Instance(Rc<RefCell<LoxInstance>>),
+    Upvalue(Rc<RefCell<Upvalue>>),
+    List(Rc<RefCell<Vec<LoxValue>>>),
+    Map(Rc<RefCell<LoxMap>>),
+    Callable(Rc<dyn Callable>),
+    Class(Rc<LoxClass>),
+    ClassInner(Rc<LoxClassInner>),
+    Closure(Rc<LoxClosure>),
+    BoundMethod(Rc<BoundMethod>),
+}
+
+impl Obj {
+    fn address(&self) -> usize {
+        match self {
+            Obj::Env(env) => Rc::as_ptr(env) as *const () as usize,
+            Obj::Instance(instance) => Rc::as_ptr(instance) as *const () as usize,
+            Obj::Upvalue(upvalue) => Rc::as_ptr(upvalue) as *const () as usize,
+            Obj::List(list) => Rc::as_ptr(list) as *const () as usize,
+            Obj::Map(map) => Rc::as_ptr(map) as *const () as usize,
+            Obj::Callable(callable) => Rc::as_ptr(callable) as *const () as usize,
+            Obj::Class(class) => Rc::as_ptr(class) as *const () as usize,
+            Obj::ClassInner(inner) => Rc::as_ptr(inner) as *const () as usize,
+            Obj::Closure(closure) => Rc::as_ptr(closure) as *const () as usize,
+            Obj::BoundMethod(bound) => Rc::as_ptr(bound) as *const () as usize,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Obj::Env(env) => Rc::strong_count(env),
+            Obj::Instance(instance) => Rc::strong_count(instance),
+            Obj::Upvalue(upvalue) => Rc::strong_count(upvalue),
+            Obj::List(list) => Rc::strong_count(list),
+            Obj::Map(map) => Rc::strong_count(map),
+            Obj::Callable(callable) => Rc::strong_count(callable),
+            Obj::Class(class) => Rc::strong_count(class),
+            Obj::ClassInner(inner) => Rc::strong_count(inner),
+            Obj::Closure(closure) => Rc::strong_count(closure),
+            Obj::BoundMethod(bound) => Rc::strong_count(bound),
+        }
+    }
+
+    // Reports one reference per reference the object holds. Returns false
+    // if the object is borrowed and cannot be looked into.
+    fn trace(&self, tracer: &mut Tracer) -> bool {
+        match self {
+            Obj::Env(env) => match env.try_borrow() {
+                Ok(env) => {
+                    for value in env.values.iter() {
+                        tracer.value(value);
+                    }
+                    if let Some(enclosing) = &env.enclosing {
+                        tracer.env(enclosing);
+                    }
+                }
+                Err(_) => return false,
+            },
+            Obj::Instance(instance) => match instance.try_borrow() {
+                Ok(instance) => {
+                    tracer.push(Obj::ClassInner(instance.class().clone()));
+                    for value in instance.fields().values() {
+                        tracer.value(value);
+                    }
+                }
+                Err(_) => return false,
+            },
+            Obj::Upvalue(upvalue) => match upvalue.try_borrow() {
+                Ok(upvalue) => {
+                    if let Upvalue::Closed(value) = &*upvalue {
+                        tracer.value(value);
+                    }
+                }
+                Err(_) => return false,
+            },
+            Obj::List(list) => match list.try_borrow() {
+                Ok(list) => {
+                    for value in list.iter() {
+                        tracer.value(value);
+                    }
+                }
+                Err(_) => return false,
+            },
+            Obj::Map(map) => match map.try_borrow() {
+                Ok(map) => {
+                    for (key, value) in map.iter() {
+                        tracer.value(key);
+                        tracer.value(value);
+                    }
+                }
+                Err(_) => return false,
+            },
+            Obj::Callable(callable) => callable.trace(tracer),
+            Obj::Class(class) => tracer.push(Obj::ClassInner(class.inner.clone())),
+            Obj::ClassInner(inner) => {
+                if let Some(superclass) = inner.superclass() {
+                    tracer.push(Obj::Class(superclass.clone()));
+                }
+                for method in inner.methods().values() {
+                    match method {
+                        Method::Function(function) => tracer.push(Obj::Callable(function.clone())),
+                        Method::Closure(closure) => tracer.push(Obj::Closure(closure.clone())),
+                        Method::Foreign(_) => {}
+                    }
+                }
+            }
+            Obj::Closure(closure) => {
+                for upvalue in closure.upvalues.iter() {
+                    tracer.push(Obj::Upvalue(upvalue.clone()));
+                }
+            }
+            Obj::BoundMethod(bound) => {
+                tracer.value(&bound.receiver);
+                tracer.push(Obj::Closure(bound.method.clone()));
+            }
+        }
+        true
+    }
+
+    // Drops whatever the object refers to. Only done to objects nothing
+    // outside the garbage can reach.
+    fn clear(&self) {
+        match self {
+            Obj::Env(env) => {
+                let mut env = env.borrow_mut();
+                env.values.clear();
+                env.enclosing = None;
+            }
+            Obj::Instance(instance) => instance.borrow_mut().fields_mut().clear(),
+            Obj::Upvalue(upvalue) => *upvalue.borrow_mut() = Upvalue::Closed(LoxValue::Nil),
+            Obj::List(list) => list.borrow_mut().clear(),
+            Obj::Map(map) => map.borrow_mut().clear(),
+            // Every cycle goes through one of the mutable objects above.
+            _ => {}
+        }
+    }
+}
+
+// Collects the references held by an object, see `Callable::trace`.
+#[derive(Default)]
+pub struct Tracer {
+    objects: Vec<Obj>,
+}
+
+impl Tracer {
+    pub fn value(&mut self, value: &LoxValue) {
+        match value {
+            LoxValue::Fn(callable) => self.push(Obj::Callable(callable.clone())),
+            LoxValue::Class(class) => self.push(Obj::Class(class.clone())),
+            LoxValue::Instance(instance) => self.push(Obj::Instance(instance.clone())),
+            LoxValue::Closure(closure) => self.push(Obj::Closure(closure.clone())),
+            LoxValue::BoundMethod(bound) => self.push(Obj::BoundMethod(bound.clone())),
+            LoxValue::List(list) => self.push(Obj::List(list.clone())),
+            LoxValue::Map(map) => self.push(Obj::Map(map.clone())),
+            LoxValue::NativeMethod(method) => self.push(Obj::Callable(method.clone())),
+            LoxValue::Number(_)
+            | LoxValue::Str(_)
+            | LoxValue::Bool(_)
+            | LoxValue::Time(_)
+            | LoxValue::Native(_)
+            | LoxValue::Nil => {}
+        }
+    }
+
+    pub fn env(&mut self, env: &Rc<RefCell<Env>>) {
+        self.push(Obj::Env(env.clone()));
+    }
+
+    fn push(&mut self, object: Obj) {
+        self.objects.push(object);
+    }
+}
+
+// The objects reachable from the tracked ones, holding one reference to
+// each, and the references between them.
+#[derive(Default)]
+struct Graph {
+    nodes: Vec<Obj>,
+    index: HashMap<usize, usize>,
+    edges: Vec<Vec<usize>>,
+    // Objects that were borrowed while tracing and are kept alive as is.
+    pinned: Vec<bool>,
+}
+
+impl Graph {
+    fn add(&mut self, object: Obj) -> usize {
+        let address = object.address();
+        if let Some(&node) = self.index.get(&address) {
+            return node;
+        }
+        self.nodes.push(object);
+        self.edges.push(Vec::new());
+        self.pinned.push(false);
+        self.index.insert(address, self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    fn discover(&mut self) {
+        let mut node = 0;
+        while node < self.nodes.len() {
+            let mut tracer = Tracer::default();
+            self.pinned[node] = !self.nodes[node].trace(&mut tracer);
+            for object in tracer.objects {
+                let target = self.add(object);
+                self.edges[node].push(target);
+            }
+            node += 1;
+        }
+    }
+
+    // Objects referenced from outside the graph are roots; whatever they do
+    // not lead to is garbage.
+    fn garbage(&self) -> Vec<usize> {
+        let mut internal = vec![0; self.nodes.len()];
+        for edges in self.edges.iter() {
+            for &target in edges.iter() {
+                internal[target] += 1;
+            }
+        }
+
+        let mut marked = vec![false; self.nodes.len()];
+        let mut pending = Vec::new();
+        for (node, object) in self.nodes.iter().enumerate() {
+            // One of the references is the graph's own.
+            if self.pinned[node] || object.strong_count() - 1 > internal[node] {
+                marked[node] = true;
+                pending.push(node);
+            }
+        }
+        while let Some(node) = pending.pop() {
+            for &target in self.edges[node].iter() {
+                if !marked[target] {
+                    marked[target] = true;
+                    pending.push(target);
+                }
+            }
+        }
+
+        (0..self.nodes.len())
+            .filter(|&node| !marked[node])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lox_value::LoxValue;
+    use crate::test_support::on_each_backend;
+
+    #[test]
+    fn test_gc_reclaims_cycles() {
+        let source = "class Node {}
+fun cycle() {
+  var node = Node();
+  node.next = node;
+  fun f() { return f; }
+  var list = [node];
+  list.push(list);
+  var map = {node: list};
+  map[0] = map;
+}
+for (var i = 0; i < 100; i = i + 1) cycle();";
+        on_each_backend(|mut lox, backend| {
+            lox.set_gc_threshold(0);
+            lox.run(source).unwrap();
+            assert!(lox.collect_garbage() >= 400, "{:?}", backend);
+            let stats = lox.gc_stats();
+            assert_eq!(stats.collections, 1);
+            assert_eq!(stats.live, 0, "{:?}", backend);
+
+            // With a threshold, collections keep the heap bounded.
+            lox.set_gc_threshold(50);
+            lox.run(source).unwrap();
+            let stats = lox.gc_stats();
+            assert!(stats.collections > 1, "{:?}", backend);
+            assert!(stats.live <= 100, "{:?}", backend);
+        });
+    }
+
+    #[test]
+    fn test_gc_keeps_reachable_objects() {
+        let source = "class Node { init(value) { this.value = value; } }
+var keep = Node(1);
+keep.self = keep;
+fun counter() { var i = 0; fun inc() { i = i + 1; return i; } return inc; }
+var c = counter();
+{ var local = Node(2); local.self = local; c(); keep.other = local; }
+c() + keep.self.self.value + keep.other.self.value;";
+        on_each_backend(|mut lox, backend| {
+            lox.set_gc_threshold(1);
+            assert_eq!(
+                lox.run(source).unwrap(),
+                LoxValue::Number(5f64),
+                "{:?}",
+                backend
+            );
+            assert!(lox.gc_stats().collections > 0);
+            assert_eq!(lox.collect_garbage(), 0);
+            assert_eq!(
+                lox.run("c() + keep.other.value;").unwrap(),
+                LoxValue::Number(5f64)
+            );
+        });
+    }
+}
@@ -0,0 +1,306 @@
+use std::fmt;
+use std::rc::Rc;
+
+use crate::error::{LoxError, RuntimeErrorKind};
+use crate::gc::Heap;
+use crate::lox_value::LoxValue;
+use crate::native_fn::NativeFn;
+
+// A Rust type that arguments of host functions can be converted to.
+pub trait FromLox: Sized {
+    // What a value must be to convert, without an article, as in "number".
+    fn type_name() -> String;
+
+    fn from_lox(value: &LoxValue) -> Option<Self>;
+}
+
+// A Rust type that host functions can return.
+pub trait IntoLox {
+    fn into_lox(self, heap: &mut Heap) -> Result<LoxValue, LoxError>;
+}
+
+impl FromLox for f64 {
+    fn type_name() -> String {
+        "number".to_string()
+    }
+
+    fn from_lox(value: &LoxValue) -> Option<f64> {
+        match value {
+            LoxValue::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+impl FromLox for bool {
+    fn type_name() -> String {
+        "bool".to_string()
+    }
+
+    fn from_lox(value: &LoxValue) -> Option<bool> {
+        match value {
+            LoxValue::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+impl FromLox for String {
+    fn type_name() -> String {
+        "string".to_string()
+    }
+
+    fn from_lox(value: &LoxValue) -> Option<String> {
+        match value {
+            LoxValue::Str(s) => Some(s.clone()),
+            _ => None,
+        }
+    }
+}
+
+// Any value converts as is.
+impl FromLox for LoxValue {
+    fn type_name() -> String {
+        "value".to_string()
+    }
+
+    fn from_lox(value: &LoxValue) -> Option<LoxValue> {
+        Some(value.clone())
+    }
+}
+
+// Nil converts to `None`.
+impl<T: FromLox> FromLox for Option<T> {
+    fn type_name() -> String {
+        format!("{} or nil", T::type_name())
+    }
+
+    fn from_lox(value: &LoxValue) -> Option<Option<T>> {
+        match value {
+            LoxValue::Nil => Some(None),
+            value => T::from_lox(value).map(Some),
+        }
+    }
+}
+
+// A list converts if all of its elements do. The elements are copied.
+impl<T: FromLox> FromLox for Vec<T> {
+    fn type_name() -> String {
+        format!("list of {} values", T::type_name())
+    }
+
+    fn from_lox(value: &LoxValue) -> Option<Vec<T>> {
+        match value {
+            LoxValue::List(list) => list.borrow().iter().map(T::from_lox).collect(),
+            _ => None,
+        }
+    }
+}
+
+impl IntoLox for f64 {
+    fn into_lox(self, _heap: &mut Heap) -> Result<LoxValue, LoxError> {
+        Ok(LoxValue::Number(self))
+    }
+}
+
+impl IntoLox for bool {
+    fn into_lox(self, _heap: &mut Heap) -> Result<LoxValue, LoxError> {
+        Ok(LoxValue::Bool(self))
+    }
+}
+
+impl IntoLox for String {
+    fn into_lox(self, _heap: &mut Heap) -> Result<LoxValue, LoxError> {
+        Ok(LoxValue::Str(self))
+    }
+}
+
+impl IntoLox for &str {
+    fn into_lox(self, _heap: &mut Heap) -> Result<LoxValue, LoxError> {
+        Ok(LoxValue::Str(self.to_string()))
+    }
+}
+
+impl IntoLox for LoxValue {
+    fn into_lox(self, _heap: &mut Heap) -> Result<LoxValue, LoxError> {
+        Ok(self)
+    }
+}
+
+// Functions without a result return nil.
+impl IntoLox for () {
+    fn into_lox(self, _heap: &mut Heap) -> Result<LoxValue, LoxError> {
+        Ok(LoxValue::Nil)
+    }
+}
+
+impl<T: IntoLox> IntoLox for Option<T> {
+    fn into_lox(self, heap: &mut Heap) -> Result<LoxValue, LoxError> {
+        match self {
+            Some(value) => value.into_lox(heap),
+            None => Ok(LoxValue::Nil),
+        }
+    }
+}
+
+impl<T: IntoLox> IntoLox for Vec<T> {
+    fn into_lox(self, heap: &mut Heap) -> Result<LoxValue, LoxError> {
+        let items = self
+            .into_iter()
+            .map(|item| item.into_lox(heap))
+            .collect::<Result<_, _>>()?;
+        Ok(LoxValue::List(heap.list(items)))
+    }
+}
+
+// An error is raised with its message as a runtime error, which scripts can
+// catch.
+impl<T: IntoLox, E: fmt::Display> IntoLox for Result<T, E> {
+    fn into_lox(self, heap: &mut Heap) -> Result<LoxValue, LoxError> {
+        match self {
+            Ok(value) => value.into_lox(heap),
+            Err(err) => Err(LoxError::runtime(RuntimeErrorKind::Host(err.to_string()))),
+        }
+    }
+}
+
+// A Rust closure that can be called from Lox. `Args` is the tuple of its
+// argument types, which only tells the implementations apart.
+pub trait HostFn<Args> {
+    fn into_native(self, name: &str) -> NativeFn;
+}
+
+// Argument `index` of `callee`, converted to `T`.
+pub fn argument<T: FromLox>(callee: &str, args: &[LoxValue], index: usize) -> Result<T, LoxError> {
+    T::from_lox(&args[index]).ok_or_else(|| {
+        let name = T::type_name();
+        let article = if name.starts_with(&['a', 'e', 'i', 'o', 'u'][..]) {
+            "an"
+        } else {
+            "a"
+        };
+        LoxError::runtime(RuntimeErrorKind::ArgumentType {
+            callee: callee.to_string(),
+            index: index + 1,
+            expected: format!("{} {}", article, name),
+            actual: args[index].type_name(),
+        })
+    })
+}
+
+macro_rules! host_fn {
+    ($arity:expr $(, $arg:ident $index:tt)*) => {
+        impl<F, R $(, $arg)*> HostFn<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R + 'static,
+            R: IntoLox,
+            $($arg: FromLox,)*
+        {
+            // Without arguments, `callee` and `args` go unused.
+            #[allow(unused_variables)]
+            fn into_native(self, name: &str) -> NativeFn {
+                let callee = name.to_string();
+                let function = move |heap: &mut Heap, args: &[LoxValue]| {
+                    let result = self($(argument::<$arg>(&callee, args, $index)?),*);
+                    result.into_lox(heap)
+                };
+                NativeFn {
+                    name: name.to_string(),
+                    arity: $arity,
+                    function: Rc::new(function),
+                }
+            }
+        }
+    };
+}
+
+host_fn!(0);
+host_fn!(1, A 0);
+host_fn!(2, A 0, B 1);
+host_fn!(3, A 0, B 1, C 2);
+host_fn!(4, A 0, B 1, C 2, D 3);
+host_fn!(5, A 0, B 1, C 2, D 3, E 4);
+host_fn!(6, A 0, B 1, C 2, D 3, E 4, G 5);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lox::{Backend, Lox};
+    use crate::lox_value::LoxValue;
+    use crate::test_support::on_each_backend;
+
+    #[test]
+    fn test_define_native() {
+        on_each_backend(|mut lox, backend| {
+            lox.define_native("add", |a: f64, b: f64| a + b);
+            lox.define_native("answer", || 42.0);
+            lox.define_native("greet", |name: Option<String>| {
+                format!("hello {}", name.unwrap_or_else(|| "world".to_string()))
+            });
+            lox.define_native("sum", |xs: Vec<f64>| xs.iter().sum::<f64>());
+            lox.define_native("range", |n: f64| {
+                (0..n as usize).map(|i| i as f64).collect::<Vec<f64>>()
+            });
+            lox.define_native(
+                "either",
+                |a: bool, b: LoxValue, c: LoxValue| if a { b } else { c },
+            );
+            lox.define_native("ignore", |_: LoxValue| ());
+
+            let program = "[add(1, 2), answer(), greet(nil), greet(\"lox\"), sum(range(4)),
+either(false, 1, \"two\"), ignore(1)];";
+            assert_eq!(
+                lox.run(program).unwrap().to_string(),
+                "[3, 42, \"hello world\", \"hello lox\", 6, \"two\", nil]"
+            );
+
+            let errors = [
+                (
+                    "add(1);",
+                    "wrong number of arguments in `add`: expected 2, got 1",
+                ),
+                (
+                    "add(1, \"2\");",
+                    "argument 2 of `add` must be a number, got string",
+                ),
+                (
+                    "greet(1);",
+                    "argument 1 of `greet` must be a string or nil, got number",
+                ),
+                (
+                    "sum([1, nil]);",
+                    "argument 1 of `sum` must be a list of number values, got list",
+                ),
+                (
+                    "either(nil, 1, 2);",
+                    "argument 1 of `either` must be a bool, got nil",
+                ),
+            ];
+            for (program, message) in errors.iter() {
+                let err = lox.run(program).unwrap_err().to_string();
+                assert_eq!(
+                    err.lines().next().unwrap(),
+                    format!("[line 1] error: {}", message),
+                    "{} ({:?})",
+                    program,
+                    backend
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn test_define_native_captures_state() {
+        use std::cell::Cell;
+
+        let calls = Rc::new(Cell::new(0));
+        let mut lox = Lox::with_backend(Backend::Bytecode);
+        let counter = calls.clone();
+        lox.define_native("tick", move || {
+            counter.set(counter.get() + 1);
+            counter.get() as f64
+        });
+        assert_eq!(lox.run("tick(); tick();").unwrap(), LoxValue::Number(2.0));
+        assert_eq!(calls.get(), 2);
+    }
+}
@@ -1,3 +1,4 @@
+use std::io::Write;
 use std::rc::Rc;
 use std::{
     cell::{Cell, RefCell},
@@ -5,20 +6,56 @@ use std::{
 };
 
 use crate::callable::Callable;
+use crate::diagnostic::Label;
 use crate::env::Env;
-use crate::error::Error;
-use crate::expr::{walk_expr, Expr};
-use crate::lox_class::LoxClass;
+use crate::error::{LoxError, RuntimeErrorKind};
+use crate::expr::{walk_expr, Expr, Slot};
+use crate::gc::Heap;
+use crate::limits::Budget;
+use crate::lox_class::{error_class, LoxClass, Method};
 use crate::lox_function::LoxFunction;
+use crate::lox_instance::LoxInstance;
+use crate::lox_map::LoxMap;
 use crate::lox_value::LoxValue;
-use crate::native_fn::ClockFn;
-use crate::stmt::{walk_stmt, Stmt};
+use crate::native_fn::{builtins, Streams};
+use crate::stmt::{walk_stmt, Flow, Stmt};
 use crate::token::{Literal, Token, TokenType};
 use crate::visitor::Visitor;
 
 pub struct Interpreter {
     pub env: Rc<RefCell<Env>>,
-    pub globals: Rc<RefCell<Env>>,
+    // Globals of each module, by id. The main program is module 0.
+    pub modules: Vec<HashMap<String, LoxValue>>,
+    // The module whose code is running.
+    pub module: usize,
+    pub frames: Vec<CallFrame>,
+    pub heap: Heap,
+    // Where `print` writes and the natives read and write.
+    pub streams: Streams,
+    pub budget: Budget,
+    // The global `Error` as the interpreter started, kept in case a script
+    // rebinds the name.
+    error_class: Rc<LoxClass>,
+    // Value of the `throw` being unwound, if any.
+    thrown: Option<LoxValue>,
+}
+
+// One active Lox call: what is running and the token of the call that
+// entered it.
+#[derive(Clone, Debug)]
+pub struct CallFrame {
+    pub function: String,
+    pub class: Option<String>,
+    pub call_site: Token,
+}
+
+impl CallFrame {
+    pub fn name(&self) -> String {
+        match &self.class {
+            Some(class) => format!("{}.{}", class, self.function),
+            None => self.function.clone(),
+        }
+    }
 }
 
 impl Visitor for Interpreter {
@@ -26,24 +63,35 @@ impl Visitor for Interpreter {
         &mut self,
         left: &Token,
         right: &Expr,
-        distance: Rc<Cell<i32>>,
-    ) -> Result<LoxValue, Error> {
+        slot: Rc<Cell<Slot>>,
+    ) -> Result<LoxValue, LoxError> {
         let value = walk_expr(self, right)?;
-        let distance = distance.get();
-        if distance < 0 {
-            self.globals.borrow_mut().assign(left.lexeme.clone(), value);
+        let slot = slot.get();
+        let previous = if slot.is_global() {
+            self.modules[self.module]
+                .get_mut(&left.lexeme)
+                .map(|global| std::mem::replace(global, value.clone()))
         } else {
-            self.env
-                .borrow_mut()
-                .assign_at(distance, left.lexeme.clone(), value);
+            self.env.borrow_mut().assign_at(slot, value.clone())
+        };
+        match previous {
+            Some(_) => Ok(value),
+            None => Err(LoxError::runtime(RuntimeErrorKind::UndefinedVariable(
+                left.lexeme.clone(),
+            ))
+            .at(left.span)),
         }
-        Ok(LoxValue::Nil)
     }
 
-    fn visit_binary(&mut self, left: &Expr, op: &Token, right: &Expr) -> Result<LoxValue, Error> {
+    fn visit_binary(
+        &mut self,
+        left: &Expr,
+        op: &Token,
+        right: &Expr,
+    ) -> Result<LoxValue, LoxError> {
         let left = walk_expr(self, left)?;
         let right = walk_expr(self, right)?;
-        match op.token_type {
+        let value = match op.token_type {
             TokenType::Minus => left.subtract(right),
             TokenType::Plus => left.plus(right),
             TokenType::Star => left.multiply(right),
@@ -54,73 +102,143 @@ impl Visitor for Interpreter {
             TokenType::LessEqual => left.less_equal(right),
             TokenType::EqualEqual => left.equal_equal(right),
             TokenType::BangEqual => left.bang_equal(right),
-            _ => Err(Error {
-                kind: "syntax error".to_string(),
-                msg: "invalid operator in binary".to_string(),
-            }),
-        }
+            _ => unreachable!("invalid operator in binary: {}", op.lexeme),
+        };
+        value.map_err(|err| err.at(op.span))
     }
 
-    fn visit_grouping(&mut self, expr: &Expr) -> Result<LoxValue, Error> {
+    fn visit_grouping(&mut self, expr: &Expr) -> Result<LoxValue, LoxError> {
         walk_expr(self, expr)
     }
 
-    fn visit_literal(&mut self, lit: &Literal) -> Result<LoxValue, Error> {
+    fn visit_literal(&mut self, lit: &Literal) -> Result<LoxValue, LoxError> {
         Ok(lit.value())
     }
 
-    fn visit_get(&mut self, expr: &Expr, name: &Token) -> Result<LoxValue, Error> {
+    fn visit_get(&mut self, expr: &Expr, name: &Token) -> Result<LoxValue, LoxError> {
         let expr = walk_expr(self, expr)?;
         match expr {
-            LoxValue::Instance(instance) => instance.borrow().get(name),
-            _ => Err(Error {
-                kind: "runtime error".to_string(),
-                msg: "only instances have properties".to_string(),
-            }),
+            LoxValue::Instance(instance) => LoxInstance::get(&instance, name, &mut self.heap),
+            LoxValue::List(_) | LoxValue::Map(_) => {
+                expr.builtin_method(&name.lexeme).ok_or_else(|| {
+                    LoxError::runtime(RuntimeErrorKind::UndefinedProperty(name.lexeme.clone()))
+                        .at(name.span)
+                })
+            }
+            _ => Err(LoxError::runtime(RuntimeErrorKind::NotAnInstance {
+                actual: expr.type_name(),
+            })
+            .at(name.span)),
         }
     }
 
-    fn visit_set(&mut self, expr: &Expr, name: &Token, value: &Expr) -> Result<LoxValue, Error> {
+    fn visit_set(&mut self, expr: &Expr, name: &Token, value: &Expr) -> Result<LoxValue, LoxError> {
         let expr = walk_expr(self, expr)?;
         match expr {
             LoxValue::Instance(instance) => {
                 let value = walk_expr(self, value)?;
-                instance.borrow_mut().set(name, value)
+                instance.borrow_mut().set(name, value.clone())?;
+                Ok(value)
             }
-            _ => Err(Error {
-                kind: "runtime error".to_string(),
-                msg: "only instances have fields".to_string(),
-            }),
+            _ => Err(LoxError::runtime(RuntimeErrorKind::NotAnInstance {
+                actual: expr.type_name(),
+            })
+            .at(name.span)),
         }
     }
 
-    fn visit_this(&mut self, token: &Token, distance: Rc<Cell<i32>>) -> Result<LoxValue, Error> {
-        self.lookup_variable(token, distance.get())
+    fn visit_lambda(
+        &mut self,
+        name: &Token,
+        args: &[Token],
+        body: &Stmt,
+    ) -> Result<LoxValue, LoxError> {
+        Ok(LoxValue::Fn(Rc::new(LoxFunction {
+            name: name.clone(),
+            args: args.to_vec(),
+            body: body.clone(),
+            closure: self.env.clone(),
+            is_initilizer: false,
+            class: None,
+            module: self.module,
+        })))
+    }
+
+    fn visit_list(&mut self, _bracket: &Token, items: &[Expr]) -> Result<LoxValue, LoxError> {
+        let mut values = Vec::new();
+        for item in items.iter() {
+            values.push(walk_expr(self, item)?);
+        }
+        Ok(LoxValue::List(self.heap.list(values)))
+    }
+
+    fn visit_map(&mut self, brace: &Token, entries: &[(Expr, Expr)]) -> Result<LoxValue, LoxError> {
+        let mut map = LoxMap::new();
+        for (key, value) in entries.iter() {
+            let key = walk_expr(self, key)?;
+            let value = walk_expr(self, value)?;
+            map.insert(key, value).map_err(|err| err.at(brace.span))?;
+        }
+        Ok(LoxValue::Map(self.heap.map(map)))
+    }
+
+    fn visit_index(
+        &mut self,
+        expr: &Expr,
+        bracket: &Token,
+        index: &Expr,
+    ) -> Result<LoxValue, LoxError> {
+        let expr = walk_expr(self, expr)?;
+        let index = walk_expr(self, index)?;
+        expr.get_index(&index).map_err(|err| err.at(bracket.span))
+    }
+
+    fn visit_set_index(
+        &mut self,
+        expr: &Expr,
+        bracket: &Token,
+        index: &Expr,
+        value: &Expr,
+    ) -> Result<LoxValue, LoxError> {
+        let expr = walk_expr(self, expr)?;
+        let index = walk_expr(self, index)?;
+        let value = walk_expr(self, value)?;
+        expr.set_index(&index, value.clone())
+            .map_err(|err| err.at(bracket.span))?;
+        Ok(value)
+    }
+
+    fn visit_this(&mut self, token: &Token, slot: Rc<Cell<Slot>>) -> Result<LoxValue, LoxError> {
+        self.lookup_variable(token, slot.get())
     }
 
     fn visit_super(
         &mut self,
         _keyword: &Token,
         method: &Token,
-        distance: Rc<Cell<i32>>,
-    ) -> Result<LoxValue, Error> {
-        let superclass = self
-            .env
-            .borrow()
-            .get_at("super".to_string(), distance.get());
-        let this = self
-            .env
-            .borrow()
-            .get_at("this".to_string(), distance.get() - 1);
-        match superclass {
-            Some(LoxValue::Class(superclass)) => {
-                if let Some(mut super_method) = superclass.inner.find_method(&method.lexeme) {
-                    return Ok(LoxValue::Fn(Rc::new(super_method.bind(this.unwrap()))));
+        slot: Rc<Cell<Slot>>,
+    ) -> Result<LoxValue, LoxError> {
+        // `this` is the only variable of the scope just inside `super`'s.
+        let slot = slot.get();
+        let superclass = self.env.borrow().get_at(slot);
+        let this = self.env.borrow().get_at(Slot {
+            depth: slot.depth - 1,
+            index: 0,
+        });
+        match (superclass, this) {
+            (Some(LoxValue::Class(superclass)), Some(this)) => {
+                match superclass.inner.find_method(&method.lexeme) {
+                    Some(super_method) => super_method
+                        .get(this, &mut self.heap)
+                        .map_err(|err| err.at(method.span)),
+                    None => Err(LoxError::runtime(RuntimeErrorKind::UndefinedProperty(
+                        method.lexeme.clone(),
+                    ))
+                    .at(method.span)),
                 }
             }
-            _ => {}
+            _ => unreachable!("`super` is resolved inside a subclass method"),
         }
-        unreachable!("undefined property '{}'", method.lexeme);
     }
 
     fn visit_logical(
@@ -128,47 +246,41 @@ impl Visitor for Interpreter {
         left_expr: &Expr,
         op: &Token,
         right_expr: &Expr,
-    ) -> Result<LoxValue, Error> {
+    ) -> Result<LoxValue, LoxError> {
         let left = walk_expr(self, left_expr)?;
+        let truthy = left.truthy()? == LoxValue::Bool(true);
         match op.token_type {
-            TokenType::Or => match left.truthy() {
-                Ok(LoxValue::Bool(true)) => return Ok(left),
-                _ => {}
-            },
-            _ => match left.truthy() {
-                Ok(LoxValue::Bool(false)) => return Ok(left),
-                _ => {}
-            },
+            TokenType::Or if truthy => return Ok(left),
+            TokenType::And if !truthy => return Ok(left),
+            _ => {}
         };
         walk_expr(self, right_expr)
     }
 
-    fn visit_unary(&mut self, token: &Token, expr: &Expr) -> Result<LoxValue, Error> {
+    fn visit_unary(&mut self, token: &Token, expr: &Expr) -> Result<LoxValue, LoxError> {
         let right = walk_expr(self, expr)?;
-        match token.token_type {
+        let value = match token.token_type {
             TokenType::Minus => right.negate_number(),
             TokenType::Bang => right.negate(),
-            _ => Err(Error {
-                kind: "syntax error".to_string(),
-                msg: "invalid operator in unary".to_string(),
-            }),
-        }
+            _ => unreachable!("invalid operator in unary: {}", token.lexeme),
+        };
+        value.map_err(|err| err.at(token.span))
     }
 
     fn visit_var_expr(
         &mut self,
         token: &Token,
-        distance: Rc<Cell<i32>>,
-    ) -> Result<LoxValue, Error> {
-        self.lookup_variable(token, distance.get())
+        slot: Rc<Cell<Slot>>,
+    ) -> Result<LoxValue, LoxError> {
+        self.lookup_variable(token, slot.get())
     }
 
     fn visit_call(
         &mut self,
         callee: &Expr,
-        _paren: &Token,
+        paren: &Token,
         args: Vec<Expr>,
-    ) -> Result<LoxValue, Error> {
+    ) -> Result<LoxValue, LoxError> {
         let callee = walk_expr(self, callee)?;
         let args = {
             let mut v = Vec::new();
@@ -179,61 +291,77 @@ impl Visitor for Interpreter {
             v
         };
         match callee {
-            LoxValue::Fn(callee) => {
+            LoxValue::Fn(_) | LoxValue::Native(_) | LoxValue::NativeMethod(_) => {
+                let callee: Rc<dyn Callable> = match callee {
+                    LoxValue::Fn(callee) => callee,
+                    LoxValue::Native(callee) => callee,
+                    LoxValue::NativeMethod(callee) => callee,
+                    _ => unreachable!(),
+                };
                 if args.len() != callee.arity() {
-                    return Err(Error {
-                        kind: "runtime error".to_string(),
-                        msg: format!(
-                            "wrong number of arguments in `{}`\nexpected: {}\ngot: {}",
-                            callee.name(),
-                            callee.arity(),
-                            args.len()
-                        ),
-                    });
+                    return Err(LoxError::runtime(RuntimeErrorKind::Arity {
+                        callee: callee.name(),
+                        expected: callee.arity(),
+                        got: args.len(),
+                    })
+                    .at(paren.span));
                 }
-                callee.call(self, args)
+                let frame = CallFrame {
+                    function: callee.name(),
+                    class: callee.class_name(),
+                    call_site: paren.clone(),
+                };
+                self.call(frame, callee.as_ref(), args)
             }
-            LoxValue::Class(callee) => callee.call(self, args),
-            _ => Err(Error {
-                kind: "runtime error".to_string(),
-                msg: "couldn't find the function".to_string(),
-            }),
+            LoxValue::Class(callee) => {
+                if args.len() != callee.arity() {
+                    return Err(LoxError::runtime(RuntimeErrorKind::Arity {
+                        callee: callee.name(),
+                        expected: callee.arity(),
+                        got: args.len(),
+                    })
+                    .at(paren.span));
+                }
+                let frame = CallFrame {
+                    function: "init".to_string(),
+                    class: Some(callee.name()),
+                    call_site: paren.clone(),
+                };
+                self.call(frame, callee.as_ref(), args)
+            }
+            _ => Err(LoxError::runtime(RuntimeErrorKind::NotCallable {
+                actual: callee.type_name(),
+            })
+            .at(paren.span)),
         }
     }
 
-    fn visit_expr_stmt(&mut self, expr: &Expr) -> Result<LoxValue, Error> {
-        walk_expr(self, expr)
+    fn visit_expr_stmt(&mut self, expr: &Expr) -> Result<Flow, LoxError> {
+        walk_expr(self, expr)?;
+        Ok(Flow::Normal)
     }
 
-    fn visit_print(&mut self, expr: &Expr) -> Result<LoxValue, Error> {
+    fn visit_print(&mut self, expr: &Expr) -> Result<Flow, LoxError> {
         let v = walk_expr(self, expr)?;
-        println!("{}", v);
-        Ok(LoxValue::Nil)
+        writeln!(self.streams.output.borrow_mut(), "{}", v)
+            .map_err(|err| LoxError::runtime(RuntimeErrorKind::Output(err.to_string())))?;
+        Ok(Flow::Normal)
     }
 
-    fn visit_block(&mut self, stmts: Vec<Stmt>) -> Result<LoxValue, Error> {
-        let mut child = Env::new();
+    fn visit_block(&mut self, stmts: Vec<Stmt>) -> Result<Flow, LoxError> {
         let parent = self.env.clone();
-        child.enclosing = Some(parent.clone());
-        self.env = Rc::new(RefCell::new(child));
+        self.env = self.heap.env(Env::with_enclosing(parent.clone()));
 
-        let mut return_value = None;
+        let mut flow = Ok(Flow::Normal);
         for stmt in stmts.iter() {
-            let value = walk_stmt(self, stmt)?;
-            match value {
-                LoxValue::Return(_) => {
-                    return_value = Some(value);
-                    break;
-                }
-                _ => {}
+            flow = self.budget.step().and_then(|()| walk_stmt(self, stmt));
+            if flow != Ok(Flow::Normal) {
+                break;
             }
         }
+        // Restored on errors too, which `catch` can recover from.
         self.env = parent;
-
-        match return_value {
-            Some(return_value) => Ok(return_value),
-            _ => Ok(LoxValue::Nil),
-        }
+        flow
     }
 
     fn visit_func(
@@ -241,18 +369,18 @@ impl Visitor for Interpreter {
         name: &Token,
         args: Vec<Token>,
         body: &Stmt,
-    ) -> Result<LoxValue, Error> {
+    ) -> Result<Flow, LoxError> {
         let function = LoxFunction {
             name: name.clone(),
             args,
             body: body.clone(),
             closure: self.env.clone(),
             is_initilizer: false,
+            class: None,
+            module: self.module,
         };
-        self.env
-            .borrow_mut()
-            .define(name.lexeme.clone(), LoxValue::Fn(Rc::new(function)));
-        Ok(LoxValue::Nil)
+        self.define(name, LoxValue::Fn(Rc::new(function)));
+        Ok(Flow::Normal)
     }
 
     fn visit_class(
@@ -260,41 +388,39 @@ impl Visitor for Interpreter {
         name: &Token,
         superclass: Option<Expr>,
         methods: Vec<Stmt>,
-    ) -> Result<LoxValue, Error> {
+    ) -> Result<Flow, LoxError> {
         let superclass = if let Some(superclass) = superclass {
+            let span = match superclass {
+                Expr::Variable(ref token, _) => token.span,
+                _ => name.span,
+            };
             let superclass = walk_expr(self, &superclass)?;
             match superclass {
                 LoxValue::Class(class) => Some(class.clone()),
-                _ => {
-                    return Err(Error {
-                        kind: "runtime error".to_string(),
-                        msg: "superclass must be a class".to_string(),
+                superclass => {
+                    return Err(LoxError::runtime(RuntimeErrorKind::SuperclassNotClass {
+                        actual: superclass.type_name(),
                     })
+                    .at(span))
                 }
             }
         } else {
             None
         };
 
-        self.env
-            .borrow_mut()
-            .define(name.lexeme.clone(), LoxValue::Nil);
+        let index = self.define(name, LoxValue::Nil);
 
         let parent = if let Some(superclass) = superclass.clone() {
-            let mut child = Env::new();
             let parent = self.env.clone();
-            child.enclosing = Some(parent.clone());
-            self.env = Rc::new(RefCell::new(child));
-
-            self.env
-                .borrow_mut()
-                .define("super".to_string(), LoxValue::Class(superclass));
-
+            let mut child = Env::with_enclosing(parent.clone());
+            child.define(LoxValue::Class(superclass));
+            self.env = self.heap.env(child);
             Some(parent)
         } else {
             None
         };
 
+        let klass_name = name.lexeme.clone();
         let mut class_methods = HashMap::new();
         for method in methods {
             match method {
@@ -305,15 +431,12 @@ impl Visitor for Interpreter {
                         body: *body.clone(),
                         closure: self.env.clone(),
                         is_initilizer: name.lexeme == "init",
+                        class: Some(klass_name.clone()),
+                        module: self.module,
                     };
-                    class_methods.insert(name.lexeme.clone(), function);
-                }
-                _ => {
-                    return Err(Error {
-                        kind: "runtime error".to_string(),
-                        msg: "function only in class'es methods".to_string(),
-                    })
+                    class_methods.insert(name.lexeme.clone(), Method::Function(Rc::new(function)));
                 }
+                _ => unreachable!("function only in class'es methods"),
             }
         }
         let klass = LoxClass::new(name.lexeme.clone(), superclass.clone(), class_methods);
@@ -322,18 +445,73 @@ impl Visitor for Interpreter {
             self.env = parent;
         }
 
-        self.env
-            .borrow_mut()
-            .assign(name.lexeme.clone(), LoxValue::Class(Rc::new(klass)));
-        Ok(LoxValue::Nil)
+        let klass = LoxValue::Class(Rc::new(klass));
+        match index {
+            Some(index) => self.env.borrow_mut().values[index] = klass,
+            None => {
+                self.modules[self.module].insert(name.lexeme.clone(), klass);
+            }
+        }
+        Ok(Flow::Normal)
     }
 
-    fn visit_return(&mut self, _token: &Token, value: Option<&Expr>) -> Result<LoxValue, Error> {
-        let return_value = match value {
+    fn visit_return(&mut self, _token: &Token, value: Option<&Expr>) -> Result<Flow, LoxError> {
+        let value = match value {
             Some(value) => walk_expr(self, value)?,
-            None => return Ok(LoxValue::Nil),
+            None => LoxValue::Nil,
         };
-        Ok(LoxValue::Return(Box::new(return_value)))
+        Ok(Flow::Return(value))
+    }
+
+    // Imports are bound before the module runs; see `Lox::link`.
+    fn visit_import(&mut self, _path: &Token, _names: Option<&[Token]>) -> Result<Flow, LoxError> {
+        Ok(Flow::Normal)
+    }
+
+    fn visit_throw(&mut self, keyword: &Token, value: &Expr) -> Result<Flow, LoxError> {
+        let value = walk_expr(self, value)?;
+        let kind = RuntimeErrorKind::thrown(&value);
+        self.thrown = Some(value);
+        Err(LoxError::runtime(kind).at(keyword.span))
+    }
+
+    fn visit_try(
+        &mut self,
+        body: &Stmt,
+        catch: Option<(&Token, &Stmt)>,
+        finally: Option<&Stmt>,
+    ) -> Result<Flow, LoxError> {
+        let mut result = walk_stmt(self, body);
+        let uncatchable =
+            |result: &Result<Flow, LoxError>| matches!(result, Err(err) if err.is_uncatchable());
+        if uncatchable(&result) {
+            return result;
+        }
+        if let (Err(LoxError::Runtime(err)), Some((_, handler))) = (&result, catch) {
+            let value = self
+                .heap
+                .error_value(err, &mut self.thrown, &self.error_class);
+            let parent = self.env.clone();
+            let mut env = Env::with_enclosing(parent.clone());
+            env.define(value);
+            self.env = self.heap.env(env);
+            result = walk_stmt(self, handler);
+            self.env = parent;
+            if uncatchable(&result) {
+                return result;
+            }
+        }
+        // The `finally` block runs however the rest ended, and only replaces
+        // that outcome if it unwinds itself.
+        if let Some(finally) = finally {
+            let thrown = self.thrown.take();
+            match walk_stmt(self, finally)? {
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+            self.thrown = thrown;
+        }
+        result
     }
 
     fn visit_if(
@@ -341,88 +519,178 @@ impl Visitor for Interpreter {
         cond: &Expr,
         then_branch: &Stmt,
         else_branch: Option<&Stmt>,
-    ) -> Result<LoxValue, Error> {
-        let cond_value = walk_expr(self, cond)?;
+    ) -> Result<Flow, LoxError> {
+        let cond_value = walk_expr(self, cond)?.truthy()?;
         match cond_value {
             LoxValue::Bool(true) => walk_stmt(self, then_branch),
             _ => match else_branch {
                 Some(else_branch_inside) => walk_stmt(self, else_branch_inside),
-                None => Ok(LoxValue::Nil),
+                None => Ok(Flow::Normal),
             },
         }
     }
 
-    fn visit_while(&mut self, cond: &Expr, body: &Stmt) -> Result<LoxValue, Error> {
-        let mut return_value = None;
+    fn visit_while(
+        &mut self,
+        cond: &Expr,
+        body: &Stmt,
+        inc: Option<&Expr>,
+    ) -> Result<Flow, LoxError> {
         while walk_expr(self, cond)?.truthy()? == LoxValue::Bool(true) {
-            let value = walk_stmt(self, body)?;
-            match value {
-                LoxValue::Return(_) => {
-                    return_value = Some(value);
-                    break;
-                }
-                _ => {}
+            self.budget.step()?;
+            match walk_stmt(self, body)? {
+                Flow::Normal | Flow::Continue => {}
+                Flow::Break => break,
+                flow @ Flow::Return(_) => return Ok(flow),
+            }
+            if let Some(inc) = inc {
+                walk_expr(self, inc)?;
             }
         }
+        Ok(Flow::Normal)
+    }
 
-        match return_value {
-            Some(return_value) => Ok(return_value),
-            _ => Ok(LoxValue::Nil),
-        }
+    fn visit_break(&mut self, _keyword: &Token) -> Result<Flow, LoxError> {
+        Ok(Flow::Break)
     }
 
-    fn visit_var_stmt(&mut self, name: &Token, init: Option<&Expr>) -> Result<LoxValue, Error> {
+    fn visit_continue(&mut self, _keyword: &Token) -> Result<Flow, LoxError> {
+        Ok(Flow::Continue)
+    }
+
+    fn visit_var_stmt(&mut self, name: &Token, init: Option<&Expr>) -> Result<Flow, LoxError> {
         let value = if let Some(expr) = init {
             walk_expr(self, expr)?
         } else {
             LoxValue::Nil
         };
-        self.env.borrow_mut().define(name.lexeme.clone(), value);
-        Ok(LoxValue::Nil)
+        self.define(name, value);
+        Ok(Flow::Normal)
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter::new()
     }
 }
 
 impl Interpreter {
     pub fn new() -> Interpreter {
-        let mut globals = Env::new();
-
-        let clock_fn = ClockFn {};
-        globals.define("clock".to_string(), LoxValue::Fn(Rc::new(clock_fn)));
-        let globals = Rc::new(RefCell::new(globals));
+        let error_class = Rc::new(error_class());
+        let streams = Streams::default();
         Interpreter {
-            env: globals.clone(),
-            globals: globals.clone(),
+            env: Rc::new(RefCell::new(Env::new())),
+            modules: vec![builtins(&error_class, &streams)],
+            module: 0,
+            frames: Vec::new(),
+            heap: Heap::new(),
+            streams,
+            budget: Budget::default(),
+            error_class,
+            thrown: None,
         }
     }
 
-    pub fn interpret(&mut self, stmts: Vec<Stmt>) -> Result<LoxValue, Error> {
+    // Adds a module with nothing but the builtins in its globals and returns
+    // its id.
+    pub fn add_module(&mut self) -> usize {
+        self.modules
+            .push(builtins(&self.error_class, &self.streams));
+        self.modules.len() - 1
+    }
+
+    // Runs the top level of `module`.
+    pub fn interpret_module(
+        &mut self,
+        module: usize,
+        stmts: Vec<Stmt>,
+    ) -> Result<LoxValue, LoxError> {
+        let main = std::mem::replace(&mut self.module, module);
+        let value = self.interpret(stmts);
+        self.module = main;
+        value
+    }
+
+    pub fn interpret(&mut self, stmts: Vec<Stmt>) -> Result<LoxValue, LoxError> {
+        let mut value = LoxValue::Nil;
         for stmt in stmts.iter() {
-            self.execute(stmt)?;
+            self.budget.step()?;
+            value = match stmt {
+                Stmt::Expr(expr) => walk_expr(self, expr)?,
+                _ => {
+                    self.execute(stmt)?;
+                    LoxValue::Nil
+                }
+            };
         }
-        Ok(LoxValue::Nil)
+        Ok(value)
     }
 
-    fn execute(&mut self, stmt: &Stmt) -> Result<LoxValue, Error> {
+    fn execute(&mut self, stmt: &Stmt) -> Result<Flow, LoxError> {
         walk_stmt(self, stmt)
     }
 
-    fn lookup_variable(&mut self, token: &Token, distance: i32) -> Result<LoxValue, Error> {
-        if distance < 0 {
-            match self.globals.borrow().get(&token.lexeme) {
-                Some(value) => Ok(value.clone()),
-                None => Err(Error {
-                    kind: "runtime error".to_string(),
-                    msg: format!("{}:{} is not initialized", token.lexeme, distance),
-                }),
-            }
-        } else {
-            match self.env.borrow().get_at(token.lexeme.clone(), distance) {
-                Some(value) => Ok(value.clone()),
-                None => Err(Error {
-                    kind: "runtime error".to_string(),
-                    msg: format!("{}:{} is not initialized", token.lexeme, distance),
-                }),
+    fn call(
+        &mut self,
+        frame: CallFrame,
+        callee: &dyn Callable,
+        args: Vec<LoxValue>,
+    ) -> Result<LoxValue, LoxError> {
+        let span = frame.call_site.span;
+        self.budget
+            .step()
+            .and_then(|()| self.budget.call(self.frames.len()))
+            .map_err(|err| self.backtrace(err.at(span)))?;
+        self.frames.push(frame);
+        let value = callee
+            .call(self, args)
+            .map_err(|err| self.backtrace(err.at(span)));
+        self.frames.pop();
+        value
+    }
+
+    // Records the call stack on a runtime error the first time it unwinds
+    // through a call, while the frame that raised it is still on the stack.
+    // Each entry names a function and where execution was inside it, innermost
+    // first, ending with the top-level script.
+    fn backtrace(&self, mut err: LoxError) -> LoxError {
+        if let LoxError::Runtime(ref mut err) = err {
+            if err.backtrace.is_empty() {
+                let mut span = err.span;
+                for frame in self.frames.iter().rev() {
+                    let here = span.unwrap_or(frame.call_site.span);
+                    err.backtrace.push(Label::new(here, frame.name()));
+                    span = Some(frame.call_site.span);
+                }
+                if let Some(span) = span {
+                    err.backtrace.push(Label::new(span, "<script>".to_string()));
+                }
             }
         }
+        err
+    }
+
+    // Declares a variable in the current scope, returning its slot index
+    // unless the scope is the global one. Only the outermost environment has
+    // no parent, and it never holds variables itself.
+    fn define(&mut self, name: &Token, value: LoxValue) -> Option<usize> {
+        if self.env.borrow().enclosing.is_none() {
+            self.modules[self.module].insert(name.lexeme.clone(), value);
+            return None;
+        }
+        Some(self.env.borrow_mut().define(value))
+    }
+
+    fn lookup_variable(&mut self, token: &Token, slot: Slot) -> Result<LoxValue, LoxError> {
+        let value = if slot.is_global() {
+            self.modules[self.module].get(&token.lexeme).cloned()
+        } else {
+            self.env.borrow().get_at(slot)
+        };
+        value.ok_or_else(|| {
+            LoxError::runtime(RuntimeErrorKind::UndefinedVariable(token.lexeme.clone()))
+                .at(token.span)
+        })
     }
 }
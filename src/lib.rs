@@ -0,0 +1,42 @@
+pub mod callable;
+pub mod chunk;
+pub mod compiler;
+pub mod diagnostic;
+pub mod env;
+pub mod error;
+pub mod expr;
+pub mod foreign_class;
+pub mod gc;
+pub mod host_fn;
+pub mod interpreter;
+pub mod limits;
+pub mod lox;
+pub mod lox_class;
+pub mod lox_closure;
+pub mod lox_function;
+pub mod lox_instance;
+pub mod lox_list;
+pub mod lox_map;
+pub mod lox_value;
+pub mod native_fn;
+pub mod parser;
+mod repl;
+pub mod resolver;
+mod rlox;
+pub mod scanner;
+pub mod stdlib;
+pub mod stmt;
+#[cfg(test)]
+mod test_support;
+pub mod token;
+pub mod visitor;
+pub mod vm;
+
+pub use crate::error::LoxError;
+pub use crate::foreign_class::ForeignClass;
+pub use crate::gc::GcStats;
+pub use crate::host_fn::{FromLox, HostFn, IntoLox};
+pub use crate::limits::{InterruptHandle, Limit, Limits};
+pub use crate::lox::{Backend, Lox};
+pub use crate::lox_value::LoxValue;
+pub use crate::rlox::{run_file, run_prompt};
@@ -0,0 +1,272 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::error::{LoxError, RuntimeErrorKind};
+
+// How often, in steps, the clock is read to enforce a timeout.
+const CLOCK_INTERVAL: u32 = 256;
+
+// How deeply calls may nest unless the host says otherwise, as clox's
+// FRAMES_MAX. The tree-walker needs the Rust stack for each call; this many
+// fit in a main thread's 8MB even in a debug build.
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+// Bounds on the work a single run may do. Unset limits don't apply; by
+// default only the call depth is limited.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Limits {
+    // Steps a run may take: statements, loop iterations and calls in the
+    // tree-walker, instructions in the VM.
+    pub fuel: Option<u64>,
+    // Time a run may take.
+    pub timeout: Option<Duration>,
+    // How deeply calls may nest.
+    pub max_depth: Option<usize>,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            fuel: None,
+            timeout: None,
+            max_depth: Some(DEFAULT_MAX_DEPTH),
+        }
+    }
+}
+
+// The limit a run exceeded, with its setting.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Limit {
+    Fuel(u64),
+    Timeout(Duration),
+    Depth(usize),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::Fuel(fuel) => write!(f, "ran out of fuel after {} steps", fuel),
+            Limit::Timeout(timeout) => write!(f, "ran longer than {:?}", timeout),
+            Limit::Depth(depth) => write!(f, "calls nested deeper than {}", depth),
+        }
+    }
+}
+
+// Stops a running script from another thread: the run fails with
+// `Cancelled` at its next step. An interrupt sent while nothing is running
+// is dropped when the next run starts, so it can't cancel an unrelated run.
+#[derive(Clone, Debug, Default)]
+pub struct InterruptHandle {
+    interrupted: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    pub fn new() -> InterruptHandle {
+        InterruptHandle::default()
+    }
+
+    pub fn interrupt(&self) {
+        self.interrupted.store(true, Ordering::Relaxed);
+    }
+
+    // Clears the interrupt if there is one and reports whether there was.
+    fn take(&self) -> bool {
+        self.interrupted.load(Ordering::Relaxed) && self.interrupted.swap(false, Ordering::Relaxed)
+    }
+}
+
+// What is left of the limits for the run in progress.
+#[derive(Clone, Debug)]
+pub struct Budget {
+    limits: Limits,
+    interrupt: InterruptHandle,
+    fuel: Option<u64>,
+    deadline: Option<Instant>,
+    // Steps until the clock is read again.
+    until_clock: u32,
+}
+
+impl Default for Budget {
+    fn default() -> Budget {
+        Budget::new(Limits::default(), InterruptHandle::new())
+    }
+}
+
+impl Budget {
+    // Starts a run's budget; the timeout counts from now and only
+    // interrupts sent from now on apply.
+    pub fn new(limits: Limits, interrupt: InterruptHandle) -> Budget {
+        interrupt.take();
+        Budget {
+            limits,
+            interrupt,
+            fuel: limits.fuel,
+            deadline: limits.timeout.map(|timeout| Instant::now() + timeout),
+            until_clock: CLOCK_INTERVAL,
+        }
+    }
+
+    // Spends one step of fuel, failing once the fuel or the time is up or
+    // the run has been interrupted.
+    pub fn step(&mut self) -> Result<(), LoxError> {
+        if self.interrupt.take() {
+            return Err(LoxError::runtime(RuntimeErrorKind::Cancelled));
+        }
+        if let Some(fuel) = &mut self.fuel {
+            if *fuel == 0 {
+                return Err(exceeded(Limit::Fuel(self.limits.fuel.unwrap_or(0))));
+            }
+            *fuel -= 1;
+        }
+        if let Some(deadline) = self.deadline {
+            self.until_clock -= 1;
+            if self.until_clock == 0 {
+                self.until_clock = CLOCK_INTERVAL;
+                if Instant::now() >= deadline {
+                    let timeout = self.limits.timeout.unwrap_or_default();
+                    return Err(exceeded(Limit::Timeout(timeout)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Checks that a call may be made with `depth` calls already running.
+    pub fn call(&self, depth: usize) -> Result<(), LoxError> {
+        match self.limits.max_depth {
+            Some(max_depth) if depth >= max_depth => Err(exceeded(Limit::Depth(max_depth))),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn exceeded(limit: Limit) -> LoxError {
+    LoxError::runtime(RuntimeErrorKind::LimitExceeded(limit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lox::{Backend, Lox};
+    use crate::lox_value::LoxValue;
+    use crate::test_support::{on_each_backend, on_main_stack};
+
+    #[test]
+    fn test_limits() {
+        let depth = "fun f(n) { if (n == 0) return 0; return f(n - 1) + 1; }";
+        on_each_backend(|mut lox, backend| {
+            lox.run(depth).unwrap();
+            lox.set_limits(Limits {
+                max_depth: Some(50),
+                ..Limits::default()
+            });
+            assert_eq!(lox.run("f(49);").unwrap(), LoxValue::Number(49.0));
+            let err = lox.run("f(50);").unwrap_err();
+            assert_eq!(err.code(), "E0419", "{:?}", backend);
+            assert!(err
+                .to_string()
+                .contains("execution limit exceeded: calls nested deeper than 50"));
+
+            // Neither `catch` nor `finally` runs, and the next run starts
+            // with a fresh budget.
+            lox.set_limits(Limits {
+                fuel: Some(10_000),
+                ..Limits::default()
+            });
+            let source = "var state = \"start\";
+try { while (true) {} } catch (e) { state = \"caught\"; } finally { state = \"finally\"; }";
+            let err = lox.run(source).unwrap_err();
+            assert!(err
+                .to_string()
+                .contains("execution limit exceeded: ran out of fuel after 10000 steps"));
+            assert_eq!(
+                lox.get_global("state"),
+                Some(LoxValue::Str("start".to_string()))
+            );
+            assert_eq!(lox.run("f(10);").unwrap(), LoxValue::Number(10.0));
+
+            lox.set_limits(Limits {
+                timeout: Some(std::time::Duration::from_millis(20)),
+                ..Limits::default()
+            });
+            let start = std::time::Instant::now();
+            let err = lox.run("while (true) { f(1); }").unwrap_err();
+            assert_eq!(err.code(), "E0419", "{:?}", backend);
+            assert!(start.elapsed() < std::time::Duration::from_secs(5));
+            assert_eq!(lox.run("f(3);").unwrap(), LoxValue::Number(3.0));
+        });
+    }
+
+    #[test]
+    fn test_default_limits() {
+        // The default depth has to fit in a main thread's stack.
+        on_main_stack(|| {
+            on_each_backend(|mut lox, backend| {
+                let err = lox.run("fun f() { f(); } f();").unwrap_err();
+                assert_eq!(err.code(), "E0419", "{:?}", backend);
+                assert!(err.to_string().contains("calls nested deeper than 256"));
+            });
+        });
+
+        // The VM stops runaway recursion even without a depth limit.
+        let mut lox = Lox::with_backend(Backend::Bytecode);
+        lox.set_limits(Limits {
+            max_depth: None,
+            ..Limits::default()
+        });
+        let err = lox.run("fun f() { f(); } f();").unwrap_err();
+        assert_eq!(err.code(), "E0419");
+        assert!(err.to_string().contains("calls nested deeper than 65536"));
+
+        // Long operator and `else if` chains aren't nesting.
+        let programs = [
+            format!("len(\"x\"{});", " + \"x\"".repeat(100)),
+            format!(
+                "var n = 149; var hit;\nif (n == 0) hit = 0;{}",
+                (1..150)
+                    .map(|i| format!("\nelse if (n == {}) hit = {};", i, i))
+                    .collect::<String>()
+                    + "\nhit;"
+            ),
+        ];
+        for (program, expected) in programs.iter().zip([101.0, 149.0].iter()) {
+            on_each_backend(|mut lox, backend| {
+                let value = lox.run(program).unwrap();
+                assert_eq!(value, LoxValue::Number(*expected), "{:?}", backend);
+            });
+        }
+    }
+
+    #[test]
+    fn test_interrupt() {
+        on_each_backend(|mut lox, backend| {
+            lox.run("var n = 0; fun spin() { var local = 1; while (true) { n = n + local; } }")
+                .unwrap();
+            let handle = lox.interrupt_handle();
+            let interrupter = std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(20));
+                handle.interrupt();
+            });
+            let err = lox
+                .run("try { { var shadow = 2; spin(); } } catch (e) { n = -1; }")
+                .unwrap_err();
+            interrupter.join().unwrap();
+            assert_eq!(err.code(), "E0420", "{:?}", backend);
+            assert!(err.to_string().contains("error: cancelled by the host"));
+
+            // The run unwound completely and the interrupt was used up.
+            assert_eq!(lox.run("n > 0;").unwrap(), LoxValue::Bool(true));
+            assert_eq!(
+                lox.run("var shadow = 3; shadow + 1;").unwrap(),
+                LoxValue::Number(4.0)
+            );
+
+            // A late interrupt, sent after its run finished, doesn't cancel
+            // the next one.
+            lox.interrupt_handle().interrupt();
+            assert_eq!(lox.run("n > 0;").unwrap(), LoxValue::Bool(true));
+        });
+    }
+}
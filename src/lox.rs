@@ -0,0 +1,1066 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::io::{stderr, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::compiler::Compiler;
+use crate::diagnostic::SourceMap;
+use crate::error::{LoxError, ModuleError, ModuleErrorKind, RuntimeErrorKind};
+use crate::foreign_class::ForeignClass;
+use crate::gc::{GcStats, Heap};
+use crate::host_fn::HostFn;
+use crate::interpreter::Interpreter;
+use crate::limits::{Budget, InterruptHandle, Limits};
+use crate::lox_value::LoxValue;
+use crate::native_fn::Streams;
+use crate::parser::Parser;
+use crate::resolver::Resolver;
+use crate::scanner::Scanner;
+use crate::stmt::Stmt;
+use crate::token::{Literal, Token};
+use crate::vm::Vm;
+
+// Which engine executes programs once they have been parsed and resolved.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Backend {
+    // Walks the AST directly.
+    TreeWalk,
+    // Compiles to bytecode and runs it on a stack VM.
+    Bytecode,
+}
+
+pub struct Lox {
+    engine: Engine,
+    sources: SourceMap,
+    // Where `report` writes rendered errors.
+    diagnostics: Box<dyn Write>,
+    limits: Limits,
+    interrupt: InterruptHandle,
+    // Directories searched for a module after the importing file's own.
+    search_path: Vec<PathBuf>,
+    // Modules that have run, by canonical path.
+    modules: HashMap<PathBuf, Module>,
+    // Files whose imports are being loaded, outermost first.
+    loading: Vec<PathBuf>,
+    // Globals defined by `define_native` and `define_class`, which modules
+    // loaded later start out with too.
+    host_globals: HashMap<String, LoxValue>,
+}
+
+// A module that has run. Its globals live in the engine under `id`.
+struct Module {
+    id: usize,
+    // Names declared at its top level.
+    exports: Vec<String>,
+}
+
+enum Engine {
+    TreeWalk(Interpreter),
+    Bytecode(Vm),
+}
+
+impl Default for Lox {
+    fn default() -> Lox {
+        Lox::new()
+    }
+}
+
+impl Lox {
+    pub fn new() -> Lox {
+        Lox::with_backend(Backend::TreeWalk)
+    }
+
+    pub fn with_backend(backend: Backend) -> Lox {
+        let engine = match backend {
+            Backend::TreeWalk => Engine::TreeWalk(Interpreter::new()),
+            Backend::Bytecode => Engine::Bytecode(Vm::new()),
+        };
+        Lox {
+            engine,
+            sources: SourceMap::new(),
+            diagnostics: Box::new(stderr()),
+            limits: Limits::default(),
+            interrupt: InterruptHandle::new(),
+            search_path: Vec::new(),
+            modules: HashMap::new(),
+            loading: Vec::new(),
+            host_globals: HashMap::new(),
+        }
+    }
+
+    // Adds a directory to look for imported modules in. Directories are
+    // tried in the order they were added.
+    pub fn add_search_path<P: AsRef<Path>>(&mut self, dir: P) {
+        self.search_path.push(dir.as_ref().to_path_buf());
+    }
+
+    pub fn backend(&self) -> Backend {
+        match self.engine {
+            Engine::TreeWalk(_) => Backend::TreeWalk,
+            Engine::Bytecode(_) => Backend::Bytecode,
+        }
+    }
+
+    // Runs `source` against the globals left by the previous runs and returns
+    // the value of the trailing expression statement, or nil if there is none.
+    pub fn run(&mut self, source: &str) -> Result<LoxValue, LoxError> {
+        self.run_source("<input>", source)
+    }
+
+    // Same as `run`, but diagnostics refer to the source as `name`. If `name`
+    // is a file, its imports are looked for next to it first; otherwise next
+    // to the working directory.
+    pub fn run_source(&mut self, name: &str, source: &str) -> Result<LoxValue, LoxError> {
+        // Modules the run imports share its budget.
+        let budget = Budget::new(self.limits, self.interrupt.clone());
+        match &mut self.engine {
+            Engine::TreeWalk(interpreter) => interpreter.budget = budget,
+            Engine::Bytecode(vm) => vm.budget = budget,
+        }
+        let stmts = self.parse(name, source)?;
+
+        let path = Path::new(name);
+        let importer = match path.canonicalize() {
+            Ok(canonical) if path.is_file() => Some(canonical),
+            _ => None,
+        };
+        if let Some(importer) = &importer {
+            self.loading.push(importer.clone());
+        }
+        let dir = importer
+            .as_deref()
+            .and_then(Path::parent)
+            .unwrap_or_else(|| Path::new(""))
+            .to_path_buf();
+        let linked = self.link(&dir, 0, &stmts);
+        if importer.is_some() {
+            self.loading.pop();
+        }
+        linked?;
+
+        self.execute(0, stmts)
+    }
+
+    // Scans `source` into tokens without running it. Diagnostics refer to
+    // the source as `name`.
+    pub fn scan(&mut self, name: &str, source: &str) -> Result<Vec<Token>, LoxError> {
+        let file_id = self.sources.add(name.to_string(), source.to_string());
+        let mut scanner = Scanner {
+            source: source.chars().collect(),
+            file_id,
+            ..Default::default()
+        };
+        scanner.scan_tokens().map_err(LoxError::Lex)
+    }
+
+    // Scans, parses and resolves `source` without running it.
+    pub fn parse(&mut self, name: &str, source: &str) -> Result<Vec<Stmt>, LoxError> {
+        let tokens = self.scan(name, source)?;
+        let mut parser = Parser::new(tokens);
+        let stmts = parser.parse().map_err(LoxError::Parse)?;
+
+        let mut resolver = Resolver::new();
+        resolver.resolve_stmts(stmts.clone())?;
+        Ok(stmts)
+    }
+
+    fn execute(&mut self, module: usize, stmts: Vec<Stmt>) -> Result<LoxValue, LoxError> {
+        let value = match &mut self.engine {
+            Engine::TreeWalk(interpreter) => interpreter.interpret_module(module, stmts),
+            Engine::Bytecode(vm) => {
+                let script = Compiler::for_module(module).compile(&stmts)?;
+                vm.interpret(script)
+            }
+        }?;
+        self.streams()
+            .output
+            .borrow_mut()
+            .flush()
+            .map_err(|err| LoxError::runtime(RuntimeErrorKind::Output(err.to_string())))?;
+        Ok(value)
+    }
+
+    // Limits each later run to the given fuel, time and call depth. A run
+    // that exceeds one fails with `LimitExceeded`, which scripts can't catch,
+    // and the next run starts afresh.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    // A handle that other threads can use to cancel the script running
+    // here; see `InterruptHandle`.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    // Sends what scripts `print`, and the prompts of `input`, to `output`
+    // rather than standard output. It is flushed after every run.
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        *self.streams().output.borrow_mut() = output;
+    }
+
+    // Makes `input` and `readLine` read from `input` rather than standard
+    // input.
+    pub fn set_input(&mut self, input: Box<dyn BufRead>) {
+        *self.streams().input.borrow_mut() = Some(input);
+    }
+
+    // Sends the errors `report` renders to `diagnostics` rather than
+    // standard error.
+    pub fn set_diagnostics(&mut self, diagnostics: Box<dyn Write>) {
+        self.diagnostics = diagnostics;
+    }
+
+    fn streams(&self) -> &Streams {
+        match &self.engine {
+            Engine::TreeWalk(interpreter) => &interpreter.streams,
+            Engine::Bytecode(vm) => &vm.streams,
+        }
+    }
+
+    // Loads the modules imported by `stmts` and binds what they export into
+    // the globals of `module`. `dir` holds the importing file.
+    fn link(&mut self, dir: &Path, module: usize, stmts: &[Stmt]) -> Result<(), LoxError> {
+        for stmt in stmts {
+            if let Stmt::Import(path, names) = stmt {
+                let (id, exports) = {
+                    let imported = self.load(dir, path)?;
+                    (imported.id, imported.exports.clone())
+                };
+                let bindings = match names {
+                    Some(names) => {
+                        if let Some(name) = names.iter().find(|n| !exports.contains(&n.lexeme)) {
+                            return Err(LoxError::Module(ModuleError {
+                                kind: ModuleErrorKind::NotExported {
+                                    path: path.lexeme.trim_matches('"').to_string(),
+                                    name: name.lexeme.clone(),
+                                },
+                                span: name.span,
+                            }));
+                        }
+                        names.iter().map(|name| name.lexeme.clone()).collect()
+                    }
+                    None => exports,
+                };
+                let globals = self.globals_mut();
+                for name in bindings {
+                    let value = globals[id].get(&name).cloned().unwrap_or(LoxValue::Nil);
+                    globals[module].insert(name, value);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Finds the module `path` names and runs it unless it already has.
+    fn load(&mut self, dir: &Path, path: &Token) -> Result<&Module, LoxError> {
+        let error = |kind| {
+            LoxError::Module(ModuleError {
+                kind,
+                span: path.span,
+            })
+        };
+        let name = match &path.lit {
+            Some(Literal::Str(name)) => name.clone(),
+            _ => unreachable!("import path is a string literal"),
+        };
+        let mut file = PathBuf::from(&name);
+        if file.extension().is_none() {
+            file.set_extension("lox");
+        }
+        let file = std::iter::once(dir)
+            .chain(self.search_path.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(&file))
+            .find(|candidate| candidate.is_file())
+            .ok_or_else(|| error(ModuleErrorKind::NotFound { path: name.clone() }))?;
+        let canonical = file.canonicalize().unwrap_or_else(|_| file.clone());
+
+        if self.modules.contains_key(&canonical) {
+            return Ok(&self.modules[&canonical]);
+        }
+        if let Some(start) = self.loading.iter().position(|f| *f == canonical) {
+            let files = self.loading[start..]
+                .iter()
+                .chain(std::iter::once(&canonical))
+                .map(|f| f.display().to_string())
+                .collect();
+            return Err(error(ModuleErrorKind::Cycle { files }));
+        }
+        let source = read_to_string(&file).map_err(|err| {
+            error(ModuleErrorKind::Unreadable {
+                path: name.clone(),
+                reason: err.to_string(),
+            })
+        })?;
+
+        let stmts = self.parse(&file.display().to_string(), &source)?;
+        let id = match &mut self.engine {
+            Engine::TreeWalk(interpreter) => interpreter.add_module(),
+            Engine::Bytecode(vm) => vm.add_module(),
+        };
+        let host_globals = self.host_globals.clone();
+        self.globals_mut()[id].extend(host_globals);
+        self.loading.push(canonical.clone());
+        let linked = self.link(canonical.parent().unwrap_or(dir), id, &stmts);
+        self.loading.pop();
+        linked?;
+
+        let exports = stmts
+            .iter()
+            .filter_map(|stmt| match stmt {
+                Stmt::Var(name, _) | Stmt::Func(name, _, _) | Stmt::Class(name, _, _) => {
+                    Some(name.lexeme.clone())
+                }
+                _ => None,
+            })
+            .collect();
+        self.execute(id, stmts)?;
+        Ok(self
+            .modules
+            .entry(canonical)
+            .or_insert(Module { id, exports }))
+    }
+
+    fn globals_mut(&mut self) -> &mut Vec<HashMap<String, LoxValue>> {
+        match &mut self.engine {
+            Engine::TreeWalk(interpreter) => &mut interpreter.modules,
+            Engine::Bytecode(vm) => &mut vm.modules,
+        }
+    }
+
+    // Renders `err` with the offending source lines underlined.
+    pub fn render(&self, err: &LoxError) -> String {
+        err.diagnostics()
+            .iter()
+            .map(|diagnostic| diagnostic.render(&self.sources))
+            .collect::<Vec<String>>()
+            .join("\n\n")
+    }
+
+    // Writes `err` as `render` does to the diagnostics sink.
+    pub fn report(&mut self, err: &LoxError) {
+        let rendered = self.render(err);
+        // There is nowhere left to report a failure to write.
+        let _ = writeln!(self.diagnostics, "{}", rendered);
+        let _ = self.diagnostics.flush();
+    }
+
+    // The globals of the main program, builtins included, sorted by name.
+    pub fn globals(&self) -> Vec<(String, LoxValue)> {
+        let globals = match &self.engine {
+            Engine::TreeWalk(interpreter) => &interpreter.modules[0],
+            Engine::Bytecode(vm) => &vm.modules[0],
+        };
+        let mut globals: Vec<_> = globals
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        globals.sort_by(|a, b| a.0.cmp(&b.0));
+        globals
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<LoxValue> {
+        match &self.engine {
+            Engine::TreeWalk(interpreter) => interpreter.modules[0].get(name).cloned(),
+            Engine::Bytecode(vm) => vm.modules[0].get(name).cloned(),
+        }
+    }
+
+    // Defines a global function that calls `function` in every module.
+    // Arguments are converted with `FromLox` and the result with `IntoLox`,
+    // so the arity comes from the closure's signature:
+    //
+    //     lox.define_native("add", |a: f64, b: f64| a + b);
+    pub fn define_native<Args, F: HostFn<Args>>(&mut self, name: &str, function: F) {
+        let native = LoxValue::Native(Rc::new(function.into_native(name)));
+        self.define_host_global(name, native);
+    }
+
+    // Defines a global class whose instances wrap a Rust value; see
+    // `ForeignClass`.
+    pub fn define_class<T: 'static>(&mut self, class: ForeignClass<T>) {
+        let name = class.name().to_string();
+        let class = LoxValue::Class(Rc::new(class.build()));
+        self.define_host_global(&name, class);
+    }
+
+    // An instance of the class `define_class` defined as `class` that wraps
+    // `value`, a Rust value the host already has, e.g. to hand a script an
+    // open connection. The class's `init` doesn't run. `None` if no such
+    // class was defined. `T` has to be the class's type for its methods to
+    // find the value.
+    pub fn wrap<T: 'static>(&mut self, class: &str, value: T) -> Option<LoxValue> {
+        let class = match self.host_globals.get(class) {
+            Some(LoxValue::Class(class)) => class.clone(),
+            _ => return None,
+        };
+        let instance = self.heap_mut().instance(class.instantiate());
+        instance.borrow_mut().set_host(Rc::new(RefCell::new(value)));
+        Some(LoxValue::Instance(instance))
+    }
+
+    fn define_host_global(&mut self, name: &str, value: LoxValue) {
+        for globals in self.globals_mut().iter_mut() {
+            globals.insert(name.to_string(), value.clone());
+        }
+        self.host_globals.insert(name.to_string(), value);
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.heap().stats()
+    }
+
+    // See `Heap::set_threshold`.
+    pub fn set_gc_threshold(&mut self, threshold: usize) {
+        self.heap_mut().set_threshold(threshold);
+    }
+
+    // Reclaims unreachable reference cycles now rather than at the next
+    // threshold and returns how many objects were freed.
+    pub fn collect_garbage(&mut self) -> usize {
+        self.heap_mut().collect()
+    }
+
+    fn heap(&self) -> &Heap {
+        match &self.engine {
+            Engine::TreeWalk(interpreter) => &interpreter.heap,
+            Engine::Bytecode(vm) => &vm.heap,
+        }
+    }
+
+    fn heap_mut(&mut self) -> &mut Heap {
+        match &mut self.engine {
+            Engine::TreeWalk(interpreter) => &mut interpreter.heap,
+            Engine::Bytecode(vm) => &mut vm.heap,
+        }
+    }
+
+    pub fn set_global(&mut self, name: &str, value: LoxValue) {
+        match &mut self.engine {
+            Engine::TreeWalk(interpreter) => {
+                interpreter.modules[0].insert(name.to_string(), value);
+            }
+            Engine::Bytecode(vm) => {
+                vm.modules[0].insert(name.to_string(), value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{LexError, LexErrorKind, RuntimeError, RuntimeErrorKind};
+    use crate::test_support::{on_each_backend, Captured, Closed};
+    use crate::token::Span;
+
+    #[test]
+    fn test_run_returns_trailing_expression() {
+        let mut lox = Lox::new();
+        assert_eq!(lox.run("1 + 2;").unwrap(), LoxValue::Number(3f64));
+        assert_eq!(lox.run("var a = 1;").unwrap(), LoxValue::Nil);
+    }
+
+    #[test]
+    fn test_return_unwinds_loops_and_blocks() {
+        let mut lox = Lox::new();
+        let source = "var hits = 0;
+fun find(n) {
+  var i = 0;
+  while (true) {
+    {
+      if (i == n) return i * 10;
+    }
+    i = i + 1;
+  }
+}
+fun early() {
+  hits = hits + 1;
+  return;
+  hits = hits + 1;
+}
+early();
+find(3);";
+        assert_eq!(lox.run(source).unwrap(), LoxValue::Number(30f64));
+        assert_eq!(lox.get_global("hits"), Some(LoxValue::Number(1f64)));
+        assert_eq!(lox.run("early();").unwrap(), LoxValue::Nil);
+    }
+
+    #[test]
+    fn test_local_slots() {
+        let mut lox = Lox::new();
+        let source = "var a = \"global\";
+var seen = \"\";
+{
+  var a = \"outer\";
+  var b = 1;
+  {
+    var a = \"inner\";
+    b = b + 1;
+    seen = seen + a;
+  }
+  seen = seen + a;
+  class Box {
+    init(v) { this.v = v; }
+    get() { return this.v + b; }
+  }
+  class Sub < Box {
+    get() { return super.get() * 10; }
+  }
+  fun step(n) { var total = n; { var by = b; total = total + by; } return total; }
+  fun twice(n) { return step(step(n)); }
+  seen = seen + a;
+  total = Sub(1).get() + twice(5);
+}
+seen + a;";
+        lox.run("var total;").unwrap();
+        assert_eq!(
+            lox.run(source).unwrap(),
+            LoxValue::Str("innerouterouterglobal".to_string())
+        );
+        assert_eq!(lox.get_global("total"), Some(LoxValue::Number(39f64)));
+    }
+
+    #[test]
+    fn test_globals_persist_between_runs() {
+        let mut lox = Lox::new();
+        lox.run("var greeting = \"hello\";").unwrap();
+        lox.set_global("name", LoxValue::Str("lox".to_string()));
+        assert_eq!(
+            lox.run("greeting + \" \" + name;").unwrap(),
+            LoxValue::Str("hello lox".to_string())
+        );
+        assert_eq!(
+            lox.get_global("greeting"),
+            Some(LoxValue::Str("hello".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let mut lox = Lox::new();
+        let err = lox.run("print 1 +;\nprint 2;\nvar;").unwrap_err();
+        match err {
+            LoxError::Parse(ref errors) => assert_eq!(errors.len(), 2),
+            _ => panic!("expected parse errors, got {:?}", err),
+        }
+        assert_eq!(
+            lox.render(&err),
+            "error[E0202]: expect expression
+ --> <input>:1:10
+  |
+1 | print 1 +;
+  |          ^
+
+error[E0201]: expect variable name
+ --> <input>:3:4
+  |
+3 | var;
+  |    ^"
+        );
+    }
+
+    #[test]
+    fn test_lex_error() {
+        let mut lox = Lox::new();
+        let err = lox.run("print \"a\" | 1;").unwrap_err();
+        assert_eq!(
+            err,
+            LoxError::Lex(vec![LexError {
+                kind: LexErrorKind::UnexpectedCharacter('|'),
+                span: Span {
+                    file_id: 0,
+                    start: 10,
+                    end: 11,
+                    line: 1,
+                    column: 11,
+                },
+            }])
+        );
+        assert_eq!(err.code(), "E0101");
+    }
+
+    #[test]
+    fn test_runtime_error() {
+        let mut lox = Lox::new();
+        let err = lox
+            .run_source("main.lox", "var a = 1;\nprint a / 0;")
+            .unwrap_err();
+        assert_eq!(err.code(), "E0402");
+        assert_eq!(
+            lox.render(&err),
+            "error[E0402]: divided by zero
+ --> main.lox:2:9
+  |
+2 | print a / 0;
+  |         ^"
+        );
+    }
+
+    #[test]
+    fn test_runtime_error_payload() {
+        let mut lox = Lox::new();
+        let err = lox.run("fun f(a, b) {}\nf(1);").unwrap_err();
+        match &err {
+            LoxError::Runtime(runtime) => match **runtime {
+                RuntimeError {
+                    kind:
+                        RuntimeErrorKind::Arity {
+                            ref callee,
+                            expected,
+                            got,
+                        },
+                    span: Some(span),
+                    ..
+                } => {
+                    assert_eq!(callee, "f");
+                    assert_eq!((expected, got), (2, 1));
+                    assert_eq!((span.line, span.column), (2, 4));
+                }
+                _ => panic!("expected an arity error, got {:?}", err),
+            },
+            _ => panic!("expected an arity error, got {:?}", err),
+        }
+
+        let err = lox.run("-\"a\";").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "[line 1] error: operands of `-` must be a number, got string"
+        );
+    }
+
+    #[test]
+    fn test_runtime_backtrace() {
+        let mut lox = Lox::new();
+        let source = "class Counter {
+  count(n) {
+    return n / 0;
+  }
+}
+fun run(counter) {
+  return counter.count(1);
+}
+run(Counter());";
+        let err = lox.run_source("main.lox", source).unwrap_err();
+        assert_eq!(
+            lox.render(&err),
+            "error[E0402]: divided by zero
+ --> main.lox:3:14
+  |
+3 |     return n / 0;
+  |              ^
+  = backtrace:
+      at Counter.count (main.lox:3)
+      at run (main.lox:7)
+      at <script> (main.lox:9)"
+        );
+
+        let err = lox.run("fun f() {\n  g();\n}\nf();").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "[line 2] error: undefined variable `g`
+    at f (line 2)
+    at <script> (line 4)"
+        );
+
+        // Frames are popped on the way out, so the next run starts clean.
+        let err = lox.run("nil();").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "[line 1] error: can only call functions and classes, got nil"
+        );
+    }
+
+    #[test]
+    fn test_resolve_error() {
+        let mut lox = Lox::new();
+        let err = lox.run("fun f() {\n  return;\n}\nreturn 1;").unwrap_err();
+        assert_eq!(err.code(), "E0304");
+        assert_eq!(
+            lox.render(&err),
+            "error[E0304]: can't return from top-level code
+ --> <input>:4:1
+  |
+4 | return 1;
+  | ^^^^^^"
+        );
+    }
+
+    #[test]
+    fn test_methods_mutate_their_instance() {
+        let source = "class Counter {
+  init() { this.count = 0; }
+  increment() { this.count = this.count + 1; return this.count; }
+}
+class Builder {
+  init() { this.parts = \"\"; }
+  add(part) { this.parts = this.parts + part; return this; }
+}
+var counter = Counter();
+counter.increment();
+var increment = counter.increment;
+increment();
+counter.increment();
+var builder = Builder();
+builder.add(\"a\").add(\"b\");
+builder.add(\"c\");
+builder.parts == \"abc\" and counter.count == 3;";
+        on_each_backend(|mut lox, backend| {
+            assert_eq!(
+                lox.run(source).unwrap(),
+                LoxValue::Bool(true),
+                "{:?}",
+                backend
+            );
+        });
+    }
+
+    #[test]
+    fn test_object_identity() {
+        let programs = [
+            ("class A {} var a = A(); a == a;", true),
+            ("class A {} A() == A();", false),
+            (
+                "class A {} var a = A(); var b = a; b.x = 1; a.x == 1 and a == b;",
+                true,
+            ),
+            (
+                "class A { me() { return this; } } var a = A(); a.me() == a;",
+                true,
+            ),
+            ("class A {} class B {} A == B;", false),
+            ("fun f() {} var g = f; f == g;", true),
+            ("fun f() {} fun g() {} f != g;", true),
+            ("clock == clock;", true),
+            // Values of different types are unequal rather than an error.
+            ("class A {} var a = A(); a == nil;", false),
+            ("class A {} var a = A(); a != nil;", true),
+            ("class A {} var a = A(); a == 1;", false),
+            ("1 == \"1\";", false),
+            ("nil != false;", true),
+        ];
+        for (program, expected) in programs.iter() {
+            on_each_backend(|mut lox, backend| {
+                assert_eq!(
+                    lox.run(program).unwrap(),
+                    LoxValue::Bool(*expected),
+                    "{} ({:?})",
+                    program,
+                    backend
+                );
+            });
+        }
+    }
+
+    #[test]
+    fn test_lambdas() {
+        let source = "fun map(xs, f) {
+  var out = [];
+  for (var i = 0; i < xs.len(); i = i + 1) out.push(f(xs[i]));
+  return out;
+}
+fun counter() {
+  var n = 0;
+  return fun () { n = n + 1; return n; };
+}
+var c = counter();
+c();
+var offset = 10;
+var add = (a, b) => a + b;
+[map([1, 2], (x) => x * 2), map([1, 2], fun (x) { return x + offset; }),
+  c(), add(1, 2), (() => \"none\")(), (1)];";
+        on_each_backend(|mut lox, backend| {
+            let value = lox.run(source).unwrap();
+            assert_eq!(
+                value.to_string(),
+                "[[2, 4], [11, 12], 2, 3, \"none\", 1]",
+                "{:?}",
+                backend
+            );
+        });
+    }
+
+    #[test]
+    fn test_exceptions() {
+        let source = "var log = [];
+fun risky(n) {
+  if (n == 0) return 1 / 0;
+  if (n == 1) throw \"one\";
+  return [1][n];
+}
+for (var i = 0; i < 3; i = i + 1) {
+  try {
+    log.push(risky(i));
+  } catch (e) {
+    if (i == 1) log.push(e);
+    else log.push([e.kind, e.line, e.message]);
+  }
+}
+var x = \"outer\";
+{
+  var x = \"block\";
+  try { { var x = \"inner\"; nil + 1; } } catch (e) {}
+  log.push(x);
+}
+class Oops < Error { init(message) { this.message = message; } }
+try { throw Oops(\"custom\"); } catch (e) { log.push(e.message); }
+log;";
+        on_each_backend(|mut lox, backend| {
+            let value = lox.run(source).unwrap();
+            assert_eq!(
+                value.to_string(),
+                "[[\"E0402\", 3, \"divided by zero\"], \"one\", \
+                 [\"E0411\", 5, \"index 2 out of bounds for list of length 1\"], \
+                 \"block\", \"custom\"]",
+                "{:?}",
+                backend
+            );
+        });
+    }
+
+    #[test]
+    fn test_finally() {
+        let source = "var log = [];
+fun early() {
+  for (var i = 0; i < 3; i = i + 1) {
+    try {
+      var local = i;
+      if (i == 1) continue;
+      if (i == 2) return \"returned\";
+    } finally {
+      log.push(i);
+    }
+  }
+}
+log.push(early());
+fun override() {
+  while (true) {
+    try { throw 1; } finally { break; }
+  }
+  return \"broke\";
+}
+log.push(override());
+try {
+  try { throw \"up\"; } catch (e) { var y = 1; fun f() { return y; } throw e + \"!\"; }
+  finally { log.push(\"cleanup\"); }
+} catch (e) {
+  log.push(e);
+}
+log;";
+        on_each_backend(|mut lox, backend| {
+            let value = lox.run(source).unwrap();
+            assert_eq!(
+                value.to_string(),
+                "[0, 1, 2, \"returned\", \"broke\", \"cleanup\", \"up!\"]",
+                "{:?}",
+                backend
+            );
+        });
+    }
+
+    #[test]
+    fn test_uncaught_exceptions() {
+        let programs = [
+            (
+                "throw \"boom\";",
+                "E0414",
+                "[line 1] error: uncaught exception: boom",
+            ),
+            (
+                "try { throw Error(); } catch (e) { e.message = \"again\"; throw e; }",
+                "E0414",
+                "[line 1] error: uncaught Error: again",
+            ),
+            // A `finally` block lets the original error carry on.
+            (
+                "fun f() {\n  try { nil + 1; } finally { 2; }\n}\nf();",
+                "E0401",
+                "[line 2] error: operands of `+` must be two numbers or two strings, got nil and number
+    at f (line 2)
+    at <script> (line 4)",
+            ),
+        ];
+        for (program, code, message) in programs.iter() {
+            on_each_backend(|mut lox, backend| {
+                let err = lox.run(program).unwrap_err();
+                assert_eq!(err.code(), *code, "{} ({:?})", program, backend);
+                assert_eq!(err.to_string(), *message, "{} ({:?})", program, backend);
+            });
+        }
+    }
+
+    #[test]
+    fn test_output_sinks() {
+        let source = "for (var i = 0; i < 3; i = i + 1) print i;
+print [\"a\", nil];
+fun f() { print \"before\"; return 1 / 0; }
+f();";
+        on_each_backend(|mut lox, backend| {
+            let (output, diagnostics) = (Captured::default(), Captured::default());
+            lox.set_output(Box::new(output.clone()));
+            lox.set_diagnostics(Box::new(diagnostics.clone()));
+
+            let err = lox.run_source("golden.lox", source).unwrap_err();
+            assert_eq!(output.contents(), "0\n1\n2\n[\"a\", nil]\nbefore\n");
+            assert_eq!(diagnostics.contents(), "");
+            lox.report(&err);
+            assert_eq!(diagnostics.contents(), format!("{}\n", lox.render(&err)));
+            assert!(diagnostics.contents().contains("golden.lox:3:"));
+
+            lox.set_output(Box::new(Closed));
+            let err = lox.run("print 1;").unwrap_err();
+            assert_eq!(err.code(), "E0418", "{:?}", backend);
+            assert!(err
+                .to_string()
+                .contains("error: couldn't write output: pipe closed"));
+        });
+    }
+
+    #[test]
+    fn test_break_and_continue() {
+        let source = "var s = \"\";
+for (var i = 0; i < 10; i = i + 1) {
+  if (i == 2) continue;
+  var j = i * 10;
+  if (i == 5) break;
+  s = s + \"x\";
+}
+var fs = [];
+var n = 0;
+while (true) {
+  n = n + 1;
+  var m = n;
+  fun get() { return m; }
+  fs.push(get);
+  if (n < 3) continue;
+  { var k = 0; if (n == 4) break; }
+}
+[s, n, fs[1](), fs[3]()];";
+        on_each_backend(|mut lox, backend| {
+            let value = lox.run(source).unwrap();
+            assert_eq!(value.to_string(), "[\"xxxx\", 4, 2, 4]", "{:?}", backend);
+        });
+    }
+
+    #[test]
+    fn test_break_outside_loop() {
+        let programs = [
+            "break;",
+            "if (true) continue;",
+            "while (true) { fun f() { break; } }",
+        ];
+        for program in programs.iter() {
+            let err = Lox::new().run(program).unwrap_err();
+            assert_eq!(err.code(), "E0309", "{}", program);
+        }
+        let err = Lox::new().run("continue;").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "[line 1] error: can't use 'continue' outside of a loop"
+        );
+    }
+
+    // Writes `files` to a fresh directory named after `test` and returns it.
+    fn module_dir(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rlox-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (name, source) in files.iter() {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, source).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_imports() {
+        let dir = module_dir(
+            "imports",
+            &[
+                ("lib/log.lox", "var log = [];"),
+                (
+                    "counter.lox",
+                    "import { log } from \"lib/log\";
+log.push(\"counter\");
+var count = 0;
+fun bump() { count = count + 1; return count; }",
+                ),
+                (
+                    "lib/other.lox",
+                    "import { log } from \"log.lox\";
+log.push(\"other\");",
+                ),
+                (
+                    "main.lox",
+                    "import { bump } from \"counter\";
+import \"counter.lox\";
+import \"other\";
+import { log } from \"log\";
+bump(); bump();",
+                ),
+            ],
+        );
+        let main = dir.join("main.lox");
+        let source = std::fs::read_to_string(&main).unwrap();
+        on_each_backend(|mut lox, _| {
+            lox.add_search_path(dir.join("lib"));
+            // `bump` updates the counter module's `count`; the importer got a
+            // copy of it at import time.
+            assert_eq!(
+                lox.run_source(main.to_str().unwrap(), &source).unwrap(),
+                LoxValue::Number(2.0)
+            );
+            assert_eq!(lox.get_global("count"), Some(LoxValue::Number(0.0)));
+            // Each module ran once, however many times it was imported.
+            assert_eq!(
+                lox.run("log;").unwrap().to_string(),
+                "[\"counter\", \"other\"]"
+            );
+            assert_eq!(lox.run("bump();").unwrap(), LoxValue::Number(3.0));
+        });
+    }
+
+    #[test]
+    fn test_import_errors() {
+        let dir = module_dir(
+            "import-errors",
+            &[
+                ("a.lox", "import \"b\";\nfun f() {}"),
+                ("b.lox", "import { f } from \"a\";"),
+                ("c.lox", "var visible = 1;\nif (true) { var hidden = 2; }"),
+            ],
+        );
+        let cases = [
+            ("import \"a\";", Err("E0603")),
+            ("import \"missing\";", Err("E0601")),
+            ("import { hidden } from \"c\";", Err("E0604")),
+            (
+                "import { visible } from \"c\";\nvisible;",
+                Ok(LoxValue::Number(1.0)),
+            ),
+        ];
+        for (source, expected) in cases.iter() {
+            on_each_backend(|mut lox, backend| {
+                lox.add_search_path(&dir);
+                let result = lox.run(source).map_err(|err| err.code());
+                assert_eq!(&result, expected, "{} ({:?})", source, backend);
+            });
+        }
+        let mut lox = Lox::new();
+        lox.add_search_path(&dir);
+        let err = lox.run("import \"a\";").unwrap_err();
+        let a = dir.join("a.lox").canonicalize().unwrap();
+        let b = dir.join("b.lox").canonicalize().unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "[line 1] error: import cycle: {} -> {} -> {}",
+                a.display(),
+                b.display(),
+                a.display()
+            )
+        );
+    }
+}
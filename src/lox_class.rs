@@ -1,13 +1,61 @@
-use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 
 use crate::callable::Callable;
-use crate::error::Error;
+use crate::error::LoxError;
+use crate::foreign_class::ForeignMethod;
+use crate::gc::Heap;
 use crate::interpreter::Interpreter;
+use crate::lox_closure::{BoundMethod, LoxClosure};
 use crate::lox_function::LoxFunction;
 use crate::lox_instance::LoxInstance;
 use crate::lox_value::LoxValue;
+use crate::native_fn::NativeMethod;
+
+// A method body, written in Lox and run by either backend, or in Rust for
+// classes defined with `ForeignClass`.
+#[derive(Clone, Debug)]
+pub enum Method {
+    Function(Rc<LoxFunction>),
+    Closure(Rc<LoxClosure>),
+    Foreign(Rc<ForeignMethod>),
+}
+
+impl Method {
+    pub fn arity(&self) -> usize {
+        match self {
+            Method::Function(function) => function.arity(),
+            Method::Closure(closure) => closure.proto.arity,
+            Method::Foreign(method) => method.arity,
+        }
+    }
+
+    // The method looked up as a property of `instance`: bound to it, or for
+    // a getter, its result.
+    pub fn get(&self, instance: LoxValue, heap: &mut Heap) -> Result<LoxValue, LoxError> {
+        match self {
+            Method::Foreign(method) if method.getter => (method.function)(heap, &instance, &[]),
+            _ => Ok(self.bind(instance)),
+        }
+    }
+
+    // The method as a callable value whose `this` is `instance`.
+    pub fn bind(&self, instance: LoxValue) -> LoxValue {
+        match self {
+            Method::Function(function) => LoxValue::Fn(Rc::new(function.bind(instance))),
+            Method::Closure(closure) => LoxValue::BoundMethod(Rc::new(BoundMethod {
+                receiver: instance,
+                method: closure.clone(),
+            })),
+            Method::Foreign(method) => LoxValue::NativeMethod(Rc::new(NativeMethod {
+                receiver: instance,
+                name: method.name.clone(),
+                arity: method.arity,
+                function: method.function.clone(),
+            })),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct LoxClass {
@@ -18,14 +66,14 @@ pub struct LoxClass {
 pub struct LoxClassInner {
     pub name: String,
     superclass: Option<Rc<LoxClass>>,
-    methods: HashMap<String, LoxFunction>,
+    methods: HashMap<String, Method>,
 }
 
 impl LoxClass {
     pub fn new(
         name: String,
         superclass: Option<Rc<LoxClass>>,
-        methods: HashMap<String, LoxFunction>,
+        methods: HashMap<String, Method>,
     ) -> LoxClass {
         LoxClass {
             inner: Rc::new(LoxClassInner::new(name, superclass, methods)),
@@ -40,11 +88,17 @@ impl LoxClass {
     }
 }
 
+// The class of the values `catch` receives for errors the runtime raises.
+// Scripts see it as the global `Error` and can throw and subclass it too.
+pub fn error_class() -> LoxClass {
+    LoxClass::new("Error".to_string(), None, HashMap::new())
+}
+
 impl LoxClassInner {
     fn new(
         name: String,
         superclass: Option<Rc<LoxClass>>,
-        methods: HashMap<String, LoxFunction>,
+        methods: HashMap<String, Method>,
     ) -> LoxClassInner {
         LoxClassInner {
             name,
@@ -53,10 +107,18 @@ impl LoxClassInner {
         }
     }
 
-    pub fn find_method(&self, name: &String) -> Option<LoxFunction> {
+    pub fn superclass(&self) -> Option<&Rc<LoxClass>> {
+        self.superclass.as_ref()
+    }
+
+    pub fn methods(&self) -> &HashMap<String, Method> {
+        &self.methods
+    }
+
+    pub fn find_method(&self, name: &String) -> Option<Method> {
         self.methods
             .get(name)
-            .map(|v| v.clone())
+            .cloned()
             .or_else(|| match &self.superclass {
                 Some(superclass) => superclass.inner.find_method(name),
                 None => None,
@@ -76,13 +138,22 @@ impl Callable for LoxClass {
         }
     }
 
-    fn call(&self, interpreter: &mut Interpreter, args: Vec<LoxValue>) -> Result<LoxValue, Error> {
-        let instance = Rc::new(RefCell::new(self.instantiate()));
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        args: Vec<LoxValue>,
+    ) -> Result<LoxValue, LoxError> {
+        let instance = interpreter.heap.instance(self.instantiate());
         match self.inner.find_method(&"init".to_string()) {
-            Some(mut initializer) => {
+            Some(Method::Function(initializer)) => {
                 let instance = LoxValue::Instance(instance.clone());
                 initializer.bind(instance).call(interpreter, args)?;
             }
+            Some(Method::Closure(_)) => unreachable!("compiled initializer in the tree-walker"),
+            Some(Method::Foreign(initializer)) => {
+                let instance = LoxValue::Instance(instance.clone());
+                (initializer.function)(&mut interpreter.heap, &instance, &args)?;
+            }
             None => {}
         }
         Ok(LoxValue::Instance(instance))
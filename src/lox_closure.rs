@@ -0,0 +1,37 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::chunk::FunctionProto;
+use crate::lox_value::LoxValue;
+
+// A compiled function together with the variables it captured, the VM's
+// counterpart of `LoxFunction`.
+#[derive(Debug)]
+pub struct LoxClosure {
+    pub proto: Rc<FunctionProto>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+impl LoxClosure {
+    pub fn name(&self) -> String {
+        match &self.proto.class {
+            Some(class) => format!("{}.{}", class, self.proto.name),
+            None => self.proto.name.clone(),
+        }
+    }
+}
+
+// A captured variable. It points at a VM stack slot while the variable's
+// scope is live and owns the value once the scope has been exited.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(LoxValue),
+}
+
+// A method looked up on an instance, remembering the instance it came from.
+#[derive(Debug)]
+pub struct BoundMethod {
+    pub receiver: LoxValue,
+    pub method: Rc<LoxClosure>,
+}
@@ -3,10 +3,12 @@ use std::rc::Rc;
 
 use crate::callable::Callable;
 use crate::env::Env;
-use crate::error::Error;
+use crate::error::LoxError;
+use crate::expr::Slot;
+use crate::gc::Tracer;
 use crate::interpreter::Interpreter;
 use crate::lox_value::LoxValue;
-use crate::stmt::{walk_stmt, Stmt};
+use crate::stmt::{walk_stmt, Flow, Stmt};
 use crate::token::Token;
 
 #[derive(Clone, Debug)]
@@ -16,19 +18,23 @@ pub struct LoxFunction {
     pub body: Stmt,
     pub closure: Rc<RefCell<Env>>,
     pub is_initilizer: bool,
+    pub class: Option<String>,
+    // Module whose globals the function sees.
+    pub module: usize,
 }
 
 impl LoxFunction {
-    pub fn bind(&mut self, instance: LoxValue) -> LoxFunction {
-        let mut env = Env::new();
-        env.enclosing = Some(self.closure.clone());
-        env.values.insert("this".to_string(), instance);
+    pub fn bind(&self, instance: LoxValue) -> LoxFunction {
+        let mut env = Env::with_enclosing(self.closure.clone());
+        env.define(instance);
         LoxFunction {
             name: self.name.clone(),
             args: self.args.clone(),
             body: self.body.clone(),
             closure: Rc::new(RefCell::new(env)),
             is_initilizer: self.is_initilizer,
+            class: self.class.clone(),
+            module: self.module,
         }
     }
 }
@@ -42,35 +48,44 @@ impl Callable for LoxFunction {
         self.args.len()
     }
 
-    fn call(&self, interpreter: &mut Interpreter, args: Vec<LoxValue>) -> Result<LoxValue, Error> {
-        let mut closure = Env::new();
-        closure.enclosing = Some(self.closure.clone());
-        let closure = Rc::new(RefCell::new(closure));
-        let env = interpreter.env.clone();
+    fn class_name(&self) -> Option<String> {
+        self.class.clone()
+    }
+
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.env(&self.closure);
+    }
 
-        for i in 0..self.args.len() {
-            closure
-                .borrow_mut()
-                .define(self.args[i].lexeme.clone(), args[i].clone());
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        args: Vec<LoxValue>,
+    ) -> Result<LoxValue, LoxError> {
+        let mut closure = Env::with_enclosing(self.closure.clone());
+        for value in args {
+            closure.define(value);
         }
+        let closure = interpreter.heap.env(closure);
+        let env = interpreter.env.clone();
+
         interpreter.env = closure.clone();
+        let module = std::mem::replace(&mut interpreter.module, self.module);
         let ret = walk_stmt(interpreter, &self.body);
+        interpreter.module = module;
         interpreter.env = env;
         if self.is_initilizer {
-            return closure.borrow().get_at("this".to_string(), 0).map_or(
-                Err(Error {
-                    kind: "runtime error".to_string(),
-                    msg: "no initializer exists".to_string(),
-                }),
-                |v| Ok(v),
-            );
+            ret?;
+            // `this` is the only variable of the environment `bind` made.
+            return Ok(self
+                .closure
+                .borrow()
+                .get_at(Slot { depth: 0, index: 0 })
+                .expect("initializer is bound to an instance"));
         }
-        match ret {
-            Ok(LoxValue::Return(value)) => Ok(*value),
-            _ => match closure.borrow().get_at("this".to_string(), 0) {
-                Some(value) => Ok(value),
-                None => ret,
-            },
+        match ret? {
+            Flow::Return(value) => Ok(value),
+            // The resolver keeps `break` and `continue` inside loops.
+            Flow::Normal | Flow::Break | Flow::Continue => Ok(LoxValue::Nil),
         }
     }
 }
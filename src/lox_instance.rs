@@ -1,9 +1,10 @@
+use std::any::Any;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 
-use crate::callable::Callable;
-use crate::error::Error;
+use crate::error::{LoxError, RuntimeErrorKind};
+use crate::gc::Heap;
 use crate::lox_class::{LoxClass, LoxClassInner};
 use crate::lox_value::LoxValue;
 use crate::token::Token;
@@ -12,6 +13,8 @@ use crate::token::Token;
 pub struct LoxInstance {
     klass: Rc<LoxClassInner>,
     field: HashMap<String, LoxValue>,
+    // The Rust value behind an instance of a host class, set by its `init`.
+    host: Option<Rc<dyn Any>>,
 }
 
 impl LoxInstance {
@@ -19,31 +22,66 @@ impl LoxInstance {
         LoxInstance {
             klass: klass.inner.clone(),
             field: HashMap::new(),
+            host: None,
         }
     }
 
-    pub fn get(&self, name: &Token) -> Result<LoxValue, Error> {
-        match self.field.get(&name.lexeme) {
-            Some(v) => return Ok(v.clone()),
-            None => {}
-        }
-
-        match self.klass.find_method(&name.lexeme) {
-            Some(mut function) => {
-                let instance = LoxValue::Instance(Rc::new(RefCell::new(self.clone())));
-                let function = function.bind(instance);
-                let function = Rc::new(function) as Rc<dyn Callable>;
-                Ok(LoxValue::Fn(function))
+    // Looks `name` up on `instance`. Methods are bound to the instance itself
+    // so that they see and update its fields.
+    pub fn get(
+        instance: &Rc<RefCell<LoxInstance>>,
+        name: &Token,
+        heap: &mut Heap,
+    ) -> Result<LoxValue, LoxError> {
+        let method = {
+            let this = instance.borrow();
+            if let Some(v) = this.field.get(&name.lexeme) {
+                return Ok(v.clone());
             }
-            None => Err(Error {
-                kind: "runtime error".to_string(),
-                msg: format!("undefined property: {}", name.lexeme),
-            }),
+            this.klass.find_method(&name.lexeme)
+        };
+
+        match method {
+            Some(method) => method
+                .get(LoxValue::Instance(instance.clone()), heap)
+                .map_err(|err| err.at(name.span)),
+            None => Err(LoxError::runtime(RuntimeErrorKind::UndefinedProperty(
+                name.lexeme.clone(),
+            ))
+            .at(name.span)),
         }
     }
 
-    pub fn set(&mut self, name: &Token, value: LoxValue) -> Result<LoxValue, Error> {
-        self.field.insert(name.lexeme.clone(), value);
+    pub fn field(&self, name: &str) -> Option<LoxValue> {
+        self.field.get(name).cloned()
+    }
+
+    pub fn fields(&self) -> &HashMap<String, LoxValue> {
+        &self.field
+    }
+
+    pub fn fields_mut(&mut self) -> &mut HashMap<String, LoxValue> {
+        &mut self.field
+    }
+
+    pub fn host(&self) -> Option<&Rc<dyn Any>> {
+        self.host.as_ref()
+    }
+
+    pub fn set_host(&mut self, host: Rc<dyn Any>) {
+        self.host = Some(host);
+    }
+
+    pub fn class(&self) -> &Rc<LoxClassInner> {
+        &self.klass
+    }
+
+    pub fn set(&mut self, name: &Token, value: LoxValue) -> Result<LoxValue, LoxError> {
+        self.set_field(name.lexeme.clone(), value);
         Ok(LoxValue::Nil)
     }
+
+    pub fn set_field(&mut self, name: String, value: LoxValue) {
+        self.field.insert(name, value);
+    }
 }
@@ -0,0 +1,189 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::error::{LoxError, RuntimeErrorKind};
+use crate::gc::Heap;
+use crate::lox_value::{write_nested, LoxValue};
+use crate::native_fn::NativeMethod;
+
+// Looks up the list method `name`, bound to `list`.
+pub fn method(list: &Rc<RefCell<Vec<LoxValue>>>, name: &str) -> Option<LoxValue> {
+    type Function = fn(&mut Heap, &LoxValue, &[LoxValue]) -> Result<LoxValue, LoxError>;
+    let (arity, function): (usize, Function) = match name {
+        "push" => (1, push),
+        "pop" => (0, pop),
+        "len" => (0, len),
+        "insert" => (2, insert),
+        "remove" => (1, remove),
+        "slice" => (2, slice),
+        "contains" => (1, contains),
+        _ => return None,
+    };
+    Some(LoxValue::NativeMethod(Rc::new(NativeMethod {
+        receiver: LoxValue::List(list.clone()),
+        name: name.to_string(),
+        arity,
+        function: Rc::new(function),
+    })))
+}
+
+pub fn get(list: &[LoxValue], index: &LoxValue) -> Result<LoxValue, LoxError> {
+    let index = to_index(index, list.len(), false)?;
+    Ok(list[index].clone())
+}
+
+pub fn set(list: &mut [LoxValue], index: &LoxValue, value: LoxValue) -> Result<(), LoxError> {
+    let index = to_index(index, list.len(), false)?;
+    list[index] = value;
+    Ok(())
+}
+
+// Checks that `index` is an integer within the list. `end` also accepts the
+// position just past the last element.
+fn to_index(index: &LoxValue, len: usize, end: bool) -> Result<usize, LoxError> {
+    let index = match index {
+        LoxValue::Number(n) if n.fract() == 0.0 => *n,
+        LoxValue::Number(n) => {
+            return Err(LoxError::runtime(RuntimeErrorKind::InvalidIndex {
+                actual: n.to_string(),
+            }))
+        }
+        value => {
+            return Err(LoxError::runtime(RuntimeErrorKind::InvalidIndex {
+                actual: value.type_name().to_string(),
+            }))
+        }
+    };
+    let limit = if end { len + 1 } else { len };
+    if index < 0.0 || index >= limit as f64 {
+        return Err(LoxError::runtime(RuntimeErrorKind::IndexOutOfBounds {
+            index,
+            len,
+        }));
+    }
+    Ok(index as usize)
+}
+
+fn list(receiver: &LoxValue) -> &Rc<RefCell<Vec<LoxValue>>> {
+    match receiver {
+        LoxValue::List(list) => list,
+        _ => unreachable!("list method bound to a {}", receiver.type_name()),
+    }
+}
+
+fn push(_heap: &mut Heap, receiver: &LoxValue, args: &[LoxValue]) -> Result<LoxValue, LoxError> {
+    list(receiver).borrow_mut().push(args[0].clone());
+    Ok(LoxValue::Nil)
+}
+
+// Removes and returns the last element, or nil if the list is empty.
+fn pop(_heap: &mut Heap, receiver: &LoxValue, _args: &[LoxValue]) -> Result<LoxValue, LoxError> {
+    Ok(list(receiver).borrow_mut().pop().unwrap_or(LoxValue::Nil))
+}
+
+fn len(_heap: &mut Heap, receiver: &LoxValue, _args: &[LoxValue]) -> Result<LoxValue, LoxError> {
+    Ok(LoxValue::Number(list(receiver).borrow().len() as f64))
+}
+
+fn insert(_heap: &mut Heap, receiver: &LoxValue, args: &[LoxValue]) -> Result<LoxValue, LoxError> {
+    let mut list = list(receiver).borrow_mut();
+    let index = to_index(&args[0], list.len(), true)?;
+    list.insert(index, args[1].clone());
+    Ok(LoxValue::Nil)
+}
+
+fn remove(_heap: &mut Heap, receiver: &LoxValue, args: &[LoxValue]) -> Result<LoxValue, LoxError> {
+    let mut list = list(receiver).borrow_mut();
+    let index = to_index(&args[0], list.len(), false)?;
+    Ok(list.remove(index))
+}
+
+// Copies the elements from `start` up to but not including `end`. The copy
+// is empty if `end` comes first.
+fn slice(heap: &mut Heap, receiver: &LoxValue, args: &[LoxValue]) -> Result<LoxValue, LoxError> {
+    let items = {
+        let list = list(receiver).borrow();
+        let start = to_index(&args[0], list.len(), true)?;
+        let end = to_index(&args[1], list.len(), true)?;
+        list[start..end.max(start)].to_vec()
+    };
+    Ok(LoxValue::List(heap.list(items)))
+}
+
+fn contains(
+    _heap: &mut Heap,
+    receiver: &LoxValue,
+    args: &[LoxValue],
+) -> Result<LoxValue, LoxError> {
+    Ok(LoxValue::Bool(list(receiver).borrow().contains(&args[0])))
+}
+
+// Writes `list` as `[1, "two", [3]]`. A list nested in itself is written as
+// `[...]` where it recurs.
+pub fn write(
+    f: &mut fmt::Formatter,
+    list: &Rc<RefCell<Vec<LoxValue>>>,
+    seen: &mut Vec<*const ()>,
+) -> fmt::Result {
+    let address = Rc::as_ptr(list) as *const ();
+    if seen.contains(&address) {
+        return write!(f, "[...]");
+    }
+    seen.push(address);
+    write!(f, "[")?;
+    for (i, item) in list.borrow().iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write_nested(f, item, seen)?;
+    }
+    seen.pop();
+    write!(f, "]")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::on_each_backend;
+
+    #[test]
+    fn test_lists() {
+        let source = "var xs = [1, 2, 3];
+xs.push(4);
+xs[0] = xs[0] + xs.pop();
+xs.insert(1, \"a\");
+var removed = xs.remove(2);
+var copy = xs.slice(1, 3);
+copy.push(nil);
+[xs, copy, removed, xs.len(), xs.contains(\"a\"), xs.contains(2), [].pop()];";
+        on_each_backend(|mut lox, backend| {
+            let value = lox.run(source).unwrap();
+            assert_eq!(
+                value.to_string(),
+                "[[5, \"a\", 3], [\"a\", 3, nil], 2, 3, true, false, nil]",
+                "{:?}",
+                backend
+            );
+        });
+    }
+
+    #[test]
+    fn test_list_errors() {
+        let programs = [
+            ("[1, 2][2];", "E0411"),
+            ("[1, 2][-1] = 0;", "E0411"),
+            ("[1].insert(2, 0);", "E0411"),
+            ("[1][0.5];", "E0410"),
+            ("[1][\"0\"];", "E0410"),
+            ("var a = 1; a[0];", "E0409"),
+            ("[].nope;", "E0404"),
+            ("[].push();", "E0407"),
+        ];
+        for (program, code) in programs.iter() {
+            on_each_backend(|mut lox, backend| {
+                let err = lox.run(program).unwrap_err();
+                assert_eq!(err.code(), *code, "{} ({:?})", program, backend);
+            });
+        }
+    }
+}
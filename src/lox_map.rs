@@ -0,0 +1,290 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::error::{LoxError, RuntimeErrorKind};
+use crate::gc::Heap;
+use crate::lox_value::{write_nested, LoxValue};
+use crate::native_fn::NativeMethod;
+
+// What a map hashes a key as. Keys are equal exactly when `==` holds for
+// them, except that NaN finds itself.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    // Bits of the number, with zeros and NaNs made canonical.
+    Number(u64),
+    Str(String),
+    Bool(bool),
+    Nil,
+    // Address of the instance, which the map keeps alive.
+    Instance(usize),
+}
+
+impl MapKey {
+    pub fn new(key: &LoxValue) -> Result<MapKey, LoxError> {
+        match key {
+            LoxValue::Number(n) if *n == 0.0 => Ok(MapKey::Number(0f64.to_bits())),
+            LoxValue::Number(n) if n.is_nan() => Ok(MapKey::Number(f64::NAN.to_bits())),
+            LoxValue::Number(n) => Ok(MapKey::Number(n.to_bits())),
+            LoxValue::Str(s) => Ok(MapKey::Str(s.clone())),
+            LoxValue::Bool(b) => Ok(MapKey::Bool(*b)),
+            LoxValue::Nil => Ok(MapKey::Nil),
+            LoxValue::Instance(instance) => {
+                Ok(MapKey::Instance(Rc::as_ptr(instance) as *const () as usize))
+            }
+            _ => Err(LoxError::runtime(RuntimeErrorKind::UnhashableKey {
+                actual: key.type_name(),
+            })),
+        }
+    }
+}
+
+// Key/value pairs kept in insertion order. Overwriting a key keeps its
+// position; deleting leaves a hole that is compacted away later.
+#[derive(Clone, Debug, Default)]
+pub struct LoxMap {
+    index: HashMap<MapKey, usize>,
+    entries: Vec<Option<(LoxValue, LoxValue)>>,
+}
+
+impl LoxMap {
+    pub fn new() -> LoxMap {
+        LoxMap::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn get(&self, key: &LoxValue) -> Result<Option<LoxValue>, LoxError> {
+        let key = MapKey::new(key)?;
+        Ok(self
+            .index
+            .get(&key)
+            .and_then(|&i| self.entries[i].as_ref().map(|(_, value)| value.clone())))
+    }
+
+    pub fn insert(&mut self, key: LoxValue, value: LoxValue) -> Result<(), LoxError> {
+        let hashed = MapKey::new(&key)?;
+        match self.index.get(&hashed) {
+            Some(&i) => self.entries[i] = Some((key, value)),
+            None => {
+                self.index.insert(hashed, self.entries.len());
+                self.entries.push(Some((key, value)));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &LoxValue) -> Result<Option<LoxValue>, LoxError> {
+        let i = match self.index.remove(&MapKey::new(key)?) {
+            Some(i) => i,
+            None => return Ok(None),
+        };
+        let removed = self.entries[i].take().map(|(_, value)| value);
+        if self.entries.len() > 2 * self.index.len() + 8 {
+            self.compact();
+        }
+        Ok(removed)
+    }
+
+    pub fn clear(&mut self) {
+        self.index.clear();
+        self.entries.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&LoxValue, &LoxValue)> {
+        self.entries
+            .iter()
+            .filter_map(|entry| entry.as_ref().map(|(key, value)| (key, value)))
+    }
+
+    fn compact(&mut self) {
+        self.entries.retain(|entry| entry.is_some());
+        for (i, entry) in self.entries.iter().enumerate() {
+            if let Some((key, _)) = entry {
+                let key = MapKey::new(key).expect("keys were hashed on insertion");
+                self.index.insert(key, i);
+            }
+        }
+    }
+}
+
+// Looks up the map method `name`, bound to `map`.
+pub fn method(map: &Rc<RefCell<LoxMap>>, name: &str) -> Option<LoxValue> {
+    type Function = fn(&mut Heap, &LoxValue, &[LoxValue]) -> Result<LoxValue, LoxError>;
+    let (arity, function): (usize, Function) = match name {
+        "keys" => (0, keys),
+        "values" => (0, values),
+        "has" => (1, has),
+        "delete" => (1, delete),
+        "len" => (0, len),
+        _ => return None,
+    };
+    Some(LoxValue::NativeMethod(Rc::new(NativeMethod {
+        receiver: LoxValue::Map(map.clone()),
+        name: name.to_string(),
+        arity,
+        function: Rc::new(function),
+    })))
+}
+
+pub fn get(map: &LoxMap, key: &LoxValue) -> Result<LoxValue, LoxError> {
+    match map.get(key)? {
+        Some(value) => Ok(value),
+        None => Err(LoxError::runtime(RuntimeErrorKind::KeyNotFound {
+            key: match key {
+                LoxValue::Str(s) => format!("{:?}", s),
+                _ => key.to_string(),
+            },
+        })),
+    }
+}
+
+fn map(receiver: &LoxValue) -> &Rc<RefCell<LoxMap>> {
+    match receiver {
+        LoxValue::Map(map) => map,
+        _ => unreachable!("map method bound to a {}", receiver.type_name()),
+    }
+}
+
+fn keys(heap: &mut Heap, receiver: &LoxValue, _args: &[LoxValue]) -> Result<LoxValue, LoxError> {
+    let keys = map(receiver)
+        .borrow()
+        .iter()
+        .map(|(key, _)| key.clone())
+        .collect();
+    Ok(LoxValue::List(heap.list(keys)))
+}
+
+fn values(heap: &mut Heap, receiver: &LoxValue, _args: &[LoxValue]) -> Result<LoxValue, LoxError> {
+    let values = map(receiver)
+        .borrow()
+        .iter()
+        .map(|(_, value)| value.clone())
+        .collect();
+    Ok(LoxValue::List(heap.list(values)))
+}
+
+fn has(_heap: &mut Heap, receiver: &LoxValue, args: &[LoxValue]) -> Result<LoxValue, LoxError> {
+    Ok(LoxValue::Bool(
+        map(receiver).borrow().get(&args[0])?.is_some(),
+    ))
+}
+
+// Removes the key and returns its value, or nil if it was not there.
+fn delete(_heap: &mut Heap, receiver: &LoxValue, args: &[LoxValue]) -> Result<LoxValue, LoxError> {
+    let removed = map(receiver).borrow_mut().remove(&args[0])?;
+    Ok(removed.unwrap_or(LoxValue::Nil))
+}
+
+fn len(_heap: &mut Heap, receiver: &LoxValue, _args: &[LoxValue]) -> Result<LoxValue, LoxError> {
+    Ok(LoxValue::Number(map(receiver).borrow().len() as f64))
+}
+
+// Writes `map` as `{"a": 1, 2: [3]}`. A map nested in itself is written as
+// `{...}` where it recurs.
+pub fn write(
+    f: &mut fmt::Formatter,
+    map: &Rc<RefCell<LoxMap>>,
+    seen: &mut Vec<*const ()>,
+) -> fmt::Result {
+    let address = Rc::as_ptr(map) as *const ();
+    if seen.contains(&address) {
+        return write!(f, "{{...}}");
+    }
+    seen.push(address);
+    write!(f, "{{")?;
+    for (i, (key, value)) in map.borrow().iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write_nested(f, key, seen)?;
+        write!(f, ": ")?;
+        write_nested(f, value, seen)?;
+    }
+    seen.pop();
+    write!(f, "}}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lox::Lox;
+    use crate::test_support::on_each_backend;
+
+    #[test]
+    fn test_order_survives_compaction() {
+        let mut map = LoxMap::new();
+        for i in 0..100 {
+            map.insert(LoxValue::Number(i as f64), LoxValue::Nil)
+                .unwrap();
+        }
+        for i in 0..95 {
+            map.remove(&LoxValue::Number(i as f64)).unwrap();
+        }
+        map.insert(LoxValue::Number(0.0), LoxValue::Nil).unwrap();
+        map.insert(LoxValue::Number(97.0), LoxValue::Bool(true))
+            .unwrap();
+
+        let keys: Vec<String> = map.iter().map(|(key, _)| key.to_string()).collect();
+        assert_eq!(keys, vec!["95", "96", "97", "98", "99", "0"]);
+        assert!(map.entries.len() < 20);
+        assert_eq!(
+            map.get(&LoxValue::Number(97.0)).unwrap(),
+            Some(LoxValue::Bool(true))
+        );
+    }
+
+    #[test]
+    fn test_maps() {
+        let source = "class Key {}
+var a = Key();
+var b = Key();
+var m = {\"z\": 1, 2: \"two\", true: nil};
+m[a] = \"a\";
+m[b] = \"b\";
+m[\"z\"] = 26;
+m[-0] = \"zero\";
+var deleted = m.delete(2);
+m[2] = \"again\";
+[m.keys().len(), m.values(), m[a], m[b], m[0], m.has(true), m.has(Key()), deleted, m.delete(99)];";
+        on_each_backend(|mut lox, backend| {
+            let value = lox.run(source).unwrap();
+            assert_eq!(
+                value.to_string(),
+                "[6, [26, nil, \"a\", \"b\", \"zero\", \"again\"], \"a\", \"b\", \"zero\", true, false, \"two\", nil]",
+                "{:?}",
+                backend
+            );
+        });
+    }
+
+    #[test]
+    fn test_map_errors() {
+        let programs = [
+            ("var m = {}; m[[]] = 1;", "E0412"),
+            ("var m = {[]: 1};", "E0412"),
+            ("var m = {}; m.has({});", "E0412"),
+            ("var m = {\"a\": 1}; m[\"b\"];", "E0413"),
+            ("var m = {}; m.nope;", "E0404"),
+        ];
+        for (program, code) in programs.iter() {
+            on_each_backend(|mut lox, backend| {
+                let err = lox.run(program).unwrap_err();
+                assert_eq!(err.code(), *code, "{} ({:?})", program, backend);
+            });
+        }
+        let mut lox = Lox::new();
+        let err = lox.run("var m = {\"a\": 1}; m[\"b\"];").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "[line 1] error: key \"b\" not found in map"
+        );
+    }
+}
@@ -5,9 +5,13 @@ use std::rc::Rc;
 use std::time::SystemTime;
 
 use crate::callable::Callable;
-use crate::error::Error;
+use crate::error::{LoxError, RuntimeErrorKind};
 use crate::lox_class::LoxClass;
+use crate::lox_closure::{BoundMethod, LoxClosure};
 use crate::lox_instance::LoxInstance;
+use crate::lox_list;
+use crate::lox_map::{self, LoxMap};
+use crate::native_fn::{NativeFn, NativeMethod};
 
 #[derive(Clone, Debug)]
 pub enum LoxValue {
@@ -18,7 +22,12 @@ pub enum LoxValue {
     Fn(Rc<dyn Callable>),
     Class(Rc<LoxClass>),
     Instance(Rc<RefCell<LoxInstance>>),
-    Return(Box<LoxValue>),
+    Native(Rc<NativeFn>),
+    Closure(Rc<LoxClosure>),
+    BoundMethod(Rc<BoundMethod>),
+    List(Rc<RefCell<Vec<LoxValue>>>),
+    Map(Rc<RefCell<LoxMap>>),
+    NativeMethod(Rc<NativeMethod>),
     Nil,
 }
 
@@ -30,11 +39,42 @@ impl PartialEq for LoxValue {
             (LoxValue::Bool(l), LoxValue::Bool(r)) => *l == *r,
             (LoxValue::Nil, LoxValue::Nil) => true,
             (LoxValue::Time(l), LoxValue::Time(r)) => *l == *r,
+            // Objects are equal only to themselves.
+            (LoxValue::Fn(l), LoxValue::Fn(r)) => same(l, r),
+            (LoxValue::Class(l), LoxValue::Class(r)) => Rc::ptr_eq(l, r),
+            (LoxValue::Instance(l), LoxValue::Instance(r)) => Rc::ptr_eq(l, r),
+            (LoxValue::Native(l), LoxValue::Native(r)) => Rc::ptr_eq(l, r),
+            (LoxValue::Closure(l), LoxValue::Closure(r)) => Rc::ptr_eq(l, r),
+            (LoxValue::BoundMethod(l), LoxValue::BoundMethod(r)) => Rc::ptr_eq(l, r),
+            (LoxValue::List(l), LoxValue::List(r)) => Rc::ptr_eq(l, r),
+            (LoxValue::Map(l), LoxValue::Map(r)) => Rc::ptr_eq(l, r),
+            (LoxValue::NativeMethod(l), LoxValue::NativeMethod(r)) => Rc::ptr_eq(l, r),
             _ => false,
         }
     }
 }
 
+// Compares by address only; vtable pointers of the same type may differ
+// between codegen units.
+fn same(l: &Rc<dyn Callable>, r: &Rc<dyn Callable>) -> bool {
+    Rc::as_ptr(l) as *const () == Rc::as_ptr(r) as *const ()
+}
+
+// Writes `value` as an element of a list or map: strings are quoted, and
+// `seen` holds the collections being written so that cycles are cut short.
+pub fn write_nested(
+    f: &mut fmt::Formatter,
+    value: &LoxValue,
+    seen: &mut Vec<*const ()>,
+) -> fmt::Result {
+    match value {
+        LoxValue::Str(s) => write!(f, "{:?}", s),
+        LoxValue::List(list) => lox_list::write(f, list, seen),
+        LoxValue::Map(map) => lox_map::write(f, map, seen),
+        _ => write!(f, "{}", value),
+    }
+}
+
 impl fmt::Display for LoxValue {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -43,26 +83,53 @@ impl fmt::Display for LoxValue {
             LoxValue::Bool(b) => write!(f, "{}", b),
             LoxValue::Nil => write!(f, "nil"),
             LoxValue::Time(t) => write!(f, "{:?}", t),
-            LoxValue::Fn(callable) => write!(f, "function: {:?}", callable),
-            LoxValue::Class(callable) => write!(f, "class: {:?}", callable),
-            LoxValue::Instance(callable) => write!(f, "instance: {:?}", callable),
-            LoxValue::Return(value) => write!(f, "{:?}", value),
+            LoxValue::Fn(callable) => write!(f, "<fn {}>", callable.name()),
+            LoxValue::Class(class) => write!(f, "{}", class.inner.name),
+            LoxValue::Instance(instance) => {
+                write!(f, "{} instance", instance.borrow().class().name)
+            }
+            LoxValue::Native(native) => write!(f, "<native fn {}>", native.name),
+            LoxValue::Closure(closure) => write!(f, "<fn {}>", closure.proto.name),
+            LoxValue::BoundMethod(bound) => write!(f, "<fn {}>", bound.method.proto.name),
+            LoxValue::List(list) => lox_list::write(f, list, &mut Vec::new()),
+            LoxValue::Map(map) => lox_map::write(f, map, &mut Vec::new()),
+            LoxValue::NativeMethod(method) => write!(f, "<native fn {}>", method.name),
         }
     }
 }
 
 impl LoxValue {
-    pub fn negate_number(&self) -> Result<LoxValue, Error> {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            LoxValue::Number(_) => "number",
+            LoxValue::Str(_) => "string",
+            LoxValue::Bool(_) => "bool",
+            LoxValue::Time(_) => "time",
+            LoxValue::Fn(_) => "function",
+            LoxValue::Class(_) => "class",
+            LoxValue::Instance(_) => "instance",
+            LoxValue::Native(_)
+            | LoxValue::Closure(_)
+            | LoxValue::BoundMethod(_)
+            | LoxValue::NativeMethod(_) => "function",
+            LoxValue::List(_) => "list",
+            LoxValue::Map(_) => "map",
+            LoxValue::Nil => "nil",
+        }
+    }
+
+    pub fn negate_number(&self) -> Result<LoxValue, LoxError> {
         match self {
             LoxValue::Number(n) => Ok(LoxValue::Number(-1f64 * *n)),
-            _ => Err(Error {
-                kind: "negate type error".to_string(),
-                msg: "not number".to_string(),
-            }),
+            _ => Err(LoxError::runtime(RuntimeErrorKind::OperandType {
+                op: "-".to_string(),
+                expected: "a number",
+                actual: vec![self.type_name()],
+            })),
         }
     }
 
-    pub fn negate(&self) -> Result<LoxValue, Error> {
+    pub fn negate(&self) -> Result<LoxValue, LoxError> {
         match self {
             LoxValue::Bool(b) => Ok(LoxValue::Bool(!b)),
             LoxValue::Nil => Ok(LoxValue::Bool(true)),
@@ -70,123 +137,119 @@ impl LoxValue {
         }
     }
 
-    pub fn subtract(&self, v: LoxValue) -> Result<LoxValue, Error> {
-        match (self, v) {
+    pub fn subtract(&self, v: LoxValue) -> Result<LoxValue, LoxError> {
+        match (self, &v) {
             (LoxValue::Number(left), LoxValue::Number(right)) => Ok(LoxValue::Number(left - right)),
-            _ => Err(Error {
-                kind: "subtract type error".to_string(),
-                msg: "not number".to_string(),
-            }),
+            _ => Err(self.operand_error("-", "numbers", &v)),
         }
     }
 
-    pub fn multiply(&self, v: LoxValue) -> Result<LoxValue, Error> {
-        match (self, v) {
+    pub fn multiply(&self, v: LoxValue) -> Result<LoxValue, LoxError> {
+        match (self, &v) {
             (LoxValue::Number(left), LoxValue::Number(right)) => Ok(LoxValue::Number(left * right)),
-            _ => Err(Error {
-                kind: "multiply type error".to_string(),
-                msg: "not number".to_string(),
-            }),
+            _ => Err(self.operand_error("*", "numbers", &v)),
         }
     }
 
-    pub fn plus(&self, v: LoxValue) -> Result<LoxValue, Error> {
-        match (self, v) {
+    pub fn plus(&self, v: LoxValue) -> Result<LoxValue, LoxError> {
+        match (self, &v) {
             (LoxValue::Number(left), LoxValue::Number(right)) => Ok(LoxValue::Number(left + right)),
             (LoxValue::Str(left), LoxValue::Str(right)) => {
                 Ok(LoxValue::Str(left.clone() + &right[..]))
             }
-            _ => Err(Error {
-                kind: "plus type error".to_string(),
-                msg: "not number".to_string(),
-            }),
+            _ => Err(self.operand_error("+", "two numbers or two strings", &v)),
         }
     }
 
-    pub fn divide(&self, v: LoxValue) -> Result<LoxValue, Error> {
-        match (self, v) {
-            (LoxValue::Number(_), LoxValue::Number(n)) if n == 0f64 => Err(Error {
-                kind: "runtime error".to_string(),
-                msg: "divided by zero".to_string(),
-            }),
+    pub fn divide(&self, v: LoxValue) -> Result<LoxValue, LoxError> {
+        match (self, &v) {
+            (LoxValue::Number(_), LoxValue::Number(n)) if *n == 0.0 => {
+                Err(LoxError::runtime(RuntimeErrorKind::DivisionByZero))
+            }
             (LoxValue::Number(left), LoxValue::Number(right)) => Ok(LoxValue::Number(left / right)),
-            _ => Err(Error {
-                kind: "divide type error".to_string(),
-                msg: "not number".to_string(),
-            }),
+            _ => Err(self.operand_error("/", "numbers", &v)),
         }
     }
 
-    pub fn greater(&self, v: LoxValue) -> Result<LoxValue, Error> {
-        match (self, v) {
-            (LoxValue::Number(left), LoxValue::Number(right)) => Ok(LoxValue::Bool(left > &right)),
-            _ => Err(Error {
-                kind: "greater type error".to_string(),
-                msg: "not number".to_string(),
-            }),
+    pub fn greater(&self, v: LoxValue) -> Result<LoxValue, LoxError> {
+        match (self, &v) {
+            (LoxValue::Number(left), LoxValue::Number(right)) => Ok(LoxValue::Bool(left > right)),
+            _ => Err(self.operand_error(">", "numbers", &v)),
         }
     }
 
-    pub fn greater_equal(&self, v: LoxValue) -> Result<LoxValue, Error> {
-        match (self, v) {
-            (LoxValue::Number(left), LoxValue::Number(right)) => Ok(LoxValue::Bool(left >= &right)),
-            _ => Err(Error {
-                kind: "greater equal type error".to_string(),
-                msg: "not number".to_string(),
-            }),
+    pub fn greater_equal(&self, v: LoxValue) -> Result<LoxValue, LoxError> {
+        match (self, &v) {
+            (LoxValue::Number(left), LoxValue::Number(right)) => Ok(LoxValue::Bool(left >= right)),
+            _ => Err(self.operand_error(">=", "numbers", &v)),
         }
     }
 
-    pub fn less(&self, v: LoxValue) -> Result<LoxValue, Error> {
-        match (self, v) {
-            (LoxValue::Number(left), LoxValue::Number(right)) => Ok(LoxValue::Bool(left < &right)),
-            _ => Err(Error {
-                kind: "less type error".to_string(),
-                msg: "not number".to_string(),
-            }),
+    pub fn less(&self, v: LoxValue) -> Result<LoxValue, LoxError> {
+        match (self, &v) {
+            (LoxValue::Number(left), LoxValue::Number(right)) => Ok(LoxValue::Bool(left < right)),
+            _ => Err(self.operand_error("<", "numbers", &v)),
         }
     }
 
-    pub fn less_equal(&self, v: LoxValue) -> Result<LoxValue, Error> {
-        match (self, v) {
-            (LoxValue::Number(left), LoxValue::Number(right)) => Ok(LoxValue::Bool(left <= &right)),
-            _ => Err(Error {
-                kind: "less equal type error".to_string(),
-                msg: "not number".to_string(),
-            }),
+    pub fn less_equal(&self, v: LoxValue) -> Result<LoxValue, LoxError> {
+        match (self, &v) {
+            (LoxValue::Number(left), LoxValue::Number(right)) => Ok(LoxValue::Bool(left <= right)),
+            _ => Err(self.operand_error("<=", "numbers", &v)),
         }
     }
 
-    pub fn equal_equal(&self, v: LoxValue) -> Result<LoxValue, Error> {
-        match (self, v) {
-            (LoxValue::Number(left), LoxValue::Number(right)) => Ok(LoxValue::Bool(left == &right)),
-            (LoxValue::Str(left), LoxValue::Str(right)) => Ok(LoxValue::Bool(left == &right)),
-            (LoxValue::Bool(left), LoxValue::Bool(right)) => Ok(LoxValue::Bool(left == &right)),
-            (LoxValue::Nil, LoxValue::Nil) => Ok(LoxValue::Bool(true)),
-            _ => Err(Error {
-                kind: "equal equal type error".to_string(),
-                msg: "not number".to_string(),
-            }),
+    // Values of different types are never equal, as in clox's `valuesEqual`;
+    // objects are compared by identity.
+    pub fn equal_equal(&self, v: LoxValue) -> Result<LoxValue, LoxError> {
+        Ok(LoxValue::Bool(*self == v))
+    }
+
+    pub fn bang_equal(&self, v: LoxValue) -> Result<LoxValue, LoxError> {
+        Ok(LoxValue::Bool(*self != v))
+    }
+
+    // Methods of the built-in collection types, bound to the value.
+    pub fn builtin_method(&self, name: &str) -> Option<LoxValue> {
+        match self {
+            LoxValue::List(list) => lox_list::method(list, name),
+            LoxValue::Map(map) => lox_map::method(map, name),
+            _ => None,
         }
     }
 
-    pub fn bang_equal(&self, v: LoxValue) -> Result<LoxValue, Error> {
-        match (self, v) {
-            (LoxValue::Number(left), LoxValue::Number(right)) => Ok(LoxValue::Bool(left != &right)),
-            (LoxValue::Str(left), LoxValue::Str(right)) => Ok(LoxValue::Bool(left != &right)),
-            (LoxValue::Bool(left), LoxValue::Bool(right)) => Ok(LoxValue::Bool(left != &right)),
-            (LoxValue::Nil, LoxValue::Nil) => Ok(LoxValue::Bool(false)),
-            _ => Err(Error {
-                kind: "bang equal type error".to_string(),
-                msg: "not number".to_string(),
-            }),
+    pub fn get_index(&self, index: &LoxValue) -> Result<LoxValue, LoxError> {
+        match self {
+            LoxValue::List(list) => lox_list::get(&list.borrow(), index),
+            LoxValue::Map(map) => lox_map::get(&map.borrow(), index),
+            _ => Err(LoxError::runtime(RuntimeErrorKind::NotIndexable {
+                actual: self.type_name(),
+            })),
         }
     }
 
-    pub fn truthy(&self) -> Result<LoxValue, Error> {
+    pub fn set_index(&self, index: &LoxValue, value: LoxValue) -> Result<(), LoxError> {
+        match self {
+            LoxValue::List(list) => lox_list::set(&mut list.borrow_mut(), index, value),
+            LoxValue::Map(map) => map.borrow_mut().insert(index.clone(), value),
+            _ => Err(LoxError::runtime(RuntimeErrorKind::NotIndexable {
+                actual: self.type_name(),
+            })),
+        }
+    }
+
+    pub fn truthy(&self) -> Result<LoxValue, LoxError> {
         match self {
             LoxValue::Bool(false) | LoxValue::Nil => Ok(LoxValue::Bool(false)),
             _ => Ok(LoxValue::Bool(true)),
         }
     }
+
+    fn operand_error(&self, op: &str, expected: &'static str, right: &LoxValue) -> LoxError {
+        LoxError::runtime(RuntimeErrorKind::OperandType {
+            op: op.to_string(),
+            expected,
+            actual: vec![self.type_name(), right.type_name()],
+        })
+    }
 }
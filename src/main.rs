@@ -1,36 +1,68 @@
-mod callable;
-mod env;
-mod error;
-mod expr;
-mod interpreter;
-mod lox_class;
-mod lox_function;
-mod lox_instance;
-mod lox_value;
-mod native_fn;
-mod parser;
-mod resolver;
-mod rlox;
-mod scanner;
-mod stmt;
-mod token;
-mod visitor;
-
 use anyhow::Result;
 use clap::{App, Arg};
-use rlox::{run_file, run_prompt};
+use rlox::error::explain;
+use rlox::{run_file, run_prompt, Backend, LoxError};
 
 use std::process::exit;
 
 fn main() -> Result<()> {
     let matches = App::new("input")
         .arg(Arg::new("input").index(1))
+        .arg(
+            Arg::new("backend")
+                .long("backend")
+                .about("Engine that runs the program")
+                .takes_value(true)
+                .possible_values(&["tree", "vm"])
+                .default_value("tree"),
+        )
+        .arg(
+            Arg::new("module-path")
+                .long("module-path")
+                .about("Directory to look for imported modules in")
+                .takes_value(true)
+                .multiple_occurrences(true),
+        )
+        .arg(
+            Arg::new("explain")
+                .long("explain")
+                .about("Describe an error code, such as E0402, and exit")
+                .takes_value(true),
+        )
         .get_matches();
 
+    if let Some(code) = matches.value_of("explain") {
+        match explain(code) {
+            Some(text) => println!("{}", text),
+            None => {
+                eprintln!("error: no error code `{}`", code);
+                exit(1);
+            }
+        }
+        exit(0);
+    }
+
+    let backend = match matches.value_of("backend") {
+        Some("vm") => Backend::Bytecode,
+        _ => Backend::TreeWalk,
+    };
+
+    let search_path: Vec<String> = matches
+        .values_of("module-path")
+        .map(|dirs| dirs.map(String::from).collect())
+        .unwrap_or_default();
+
     if let Some(i) = matches.value_of("input") {
-        run_file(i.to_string())?;
+        if let Err(err) = run_file(i.to_string(), backend, &search_path) {
+            // Same exit codes as jlox: 65 for bad input, 70 for runtime errors.
+            match err.downcast_ref::<LoxError>() {
+                Some(LoxError::Runtime(_)) => exit(70),
+                Some(_) => exit(65),
+                None => return Err(err),
+            }
+        }
     } else {
-        run_prompt()?;
+        run_prompt(backend, &search_path)?;
     }
 
     exit(0);
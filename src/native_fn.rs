@@ -1,27 +1,135 @@
-use std::time::SystemTime;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{stdout, BufRead, Write};
+use std::rc::Rc;
 
 use crate::callable::Callable;
-use crate::error::Error;
+use crate::error::LoxError;
+use crate::gc::{Heap, Tracer};
 use crate::interpreter::Interpreter;
+use crate::lox_class::LoxClass;
 use crate::lox_value::LoxValue;
+use crate::stdlib::register_stdlib;
 
-#[derive(Clone, Debug)]
-pub struct ClockFn;
+// A function implemented in Rust. Natives only see the heap and their
+// arguments, so the same value can be called from both the tree-walker and
+// the VM.
+#[derive(Clone)]
+pub struct NativeFn {
+    pub name: String,
+    pub arity: usize,
+    pub function: Rc<NativeFunction>,
+}
+
+pub type NativeFunction = dyn Fn(&mut Heap, &[LoxValue]) -> Result<LoxValue, LoxError>;
+
+impl fmt::Debug for NativeFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NativeFn({})", self.name)
+    }
+}
+
+impl Callable for NativeFn {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        args: Vec<LoxValue>,
+    ) -> Result<LoxValue, LoxError> {
+        (self.function)(&mut interpreter.heap, &args)
+    }
+}
+
+// A method of a built-in type bound to the value it was looked up on, e.g.
+// `xs.push`. The heap is passed for methods that allocate.
+#[derive(Clone)]
+pub struct NativeMethod {
+    pub receiver: LoxValue,
+    pub name: String,
+    pub arity: usize,
+    pub function: Rc<MethodFunction>,
+}
 
-impl Callable for ClockFn {
+pub type MethodFunction = dyn Fn(&mut Heap, &LoxValue, &[LoxValue]) -> Result<LoxValue, LoxError>;
+
+impl fmt::Debug for NativeMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "NativeMethod({}.{})",
+            self.receiver.type_name(),
+            self.name
+        )
+    }
+}
+
+impl NativeMethod {
+    // The class of the receiver if it is an instance, else its type.
+    pub fn receiver_name(&self) -> String {
+        match &self.receiver {
+            LoxValue::Instance(instance) => instance.borrow().class().name.clone(),
+            receiver => receiver.type_name().to_string(),
+        }
+    }
+}
+
+impl Callable for NativeMethod {
     fn name(&self) -> String {
-        "clock".to_string()
+        self.name.clone()
     }
 
     fn arity(&self) -> usize {
-        0
+        self.arity
+    }
+
+    fn class_name(&self) -> Option<String> {
+        Some(self.receiver_name())
+    }
+
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.value(&self.receiver);
     }
 
     fn call(
         &self,
-        _interpreter: &mut Interpreter,
-        _args: Vec<LoxValue>,
-    ) -> Result<LoxValue, Error> {
-        Ok(LoxValue::Time(SystemTime::now()))
+        interpreter: &mut Interpreter,
+        args: Vec<LoxValue>,
+    ) -> Result<LoxValue, LoxError> {
+        (self.function)(&mut interpreter.heap, &self.receiver, &args)
     }
 }
+
+// Where `print` writes and where `input` and `readLine` read. An engine
+// shares them with the natives it defines, so replacing one reaches both.
+#[derive(Clone)]
+pub struct Streams {
+    pub output: Rc<RefCell<Box<dyn Write>>>,
+    // Standard input if unset.
+    pub input: Rc<RefCell<Option<Box<dyn BufRead>>>>,
+}
+
+impl Default for Streams {
+    fn default() -> Streams {
+        Streams {
+            output: Rc::new(RefCell::new(Box::new(stdout()))),
+            input: Rc::new(RefCell::new(None)),
+        }
+    }
+}
+
+// The globals every module starts out with: the standard library and
+// `Error`.
+pub fn builtins(error_class: &Rc<LoxClass>, streams: &Streams) -> HashMap<String, LoxValue> {
+    let mut globals = HashMap::new();
+    register_stdlib(&mut globals, streams);
+    globals.insert("Error".to_string(), LoxValue::Class(error_class.clone()));
+    globals
+}
@@ -1,28 +1,73 @@
-use crate::expr::Expr;
+use crate::error::{ParseError, ParseErrorKind};
+use crate::expr::{Expr, Slot};
 use crate::stmt::Stmt;
 use crate::token::{Literal, Token, TokenType};
 
 use std::cell::Cell;
 use std::rc::Rc;
 
+// How deeply statements and expressions may nest. The parser and the
+// passes after it recurse on the tree, so deeper input would overflow the
+// stack. Parsing up to the limit takes about 1.2MB of stack in a debug
+// build, so it fits in a spawned thread's default 2MB.
+pub const MAX_NESTING: usize = 32;
+
 pub struct Parser {
     pub tokens: Vec<Token>,
     pub current: usize,
+    // Levels of nesting around the current token.
+    depth: usize,
 }
 
 impl Parser {
-    pub fn parse(&mut self) -> Vec<Stmt> {
+    pub fn new(tokens: Vec<Token>) -> Parser {
+        Parser {
+            tokens,
+            current: 0,
+            depth: 0,
+        }
+    }
+
+    pub fn parse(&mut self) -> Result<Vec<Stmt>, Vec<ParseError>> {
         let mut stmts = Vec::new();
+        let mut errors = Vec::new();
         while !self.is_at_end() {
-            stmts.push(self.declaration());
+            // An error may have left the count of the statement before it
+            // unbalanced.
+            self.depth = 0;
+            let stmt = if self.is_match(vec![TokenType::Import]) {
+                self.import_declaration()
+            } else {
+                self.declaration()
+            };
+            match stmt {
+                Ok(stmt) => stmts.push(stmt),
+                Err(err) => {
+                    // Past the nesting limit there's no telling where the
+                    // nesting ends, so the rest would only report bogus
+                    // errors.
+                    let too_deep = matches!(err.kind, ParseErrorKind::TooDeep(_));
+                    errors.push(err);
+                    if too_deep {
+                        break;
+                    }
+                    self.synchronize();
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(stmts)
+        } else {
+            Err(errors)
         }
-        stmts
     }
 
-    fn declaration(&mut self) -> Stmt {
+    fn declaration(&mut self) -> Result<Stmt, ParseError> {
         if self.is_match(vec![TokenType::Var]) {
             self.var_declaration()
-        } else if self.is_match(vec![TokenType::Fun]) {
+        } else if self.check(TokenType::Fun) && !self.check_next(TokenType::LeftParen) {
+            self.advance();
             self.function("function".to_string())
         } else if self.is_match(vec![TokenType::Class]) {
             self.class_declaration()
@@ -31,12 +76,51 @@ impl Parser {
         }
     }
 
-    fn class_declaration(&mut self) -> Stmt {
-        let name = self.consume(TokenType::Identifier, "expect class name".to_string());
+    // `import "path";` or `import { a, b } from "path";`, with `from` only a
+    // keyword here.
+    fn import_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let names = if self.is_match(vec![TokenType::LeftBrace]) {
+            let mut names = Vec::new();
+            loop {
+                names.push(
+                    self.consume(TokenType::Identifier, "expect name to import".to_string())?,
+                );
+                if !self.is_match(vec![TokenType::Comma]) {
+                    break;
+                }
+            }
+            self.consume(
+                TokenType::RightBrace,
+                "expect '}' after imported names".to_string(),
+            )?;
+            if !(self.check(TokenType::Identifier) && self.peek().lexeme == "from") {
+                return Err(self.error(
+                    self.peek(),
+                    ParseErrorKind::Expected {
+                        expected: TokenType::Identifier,
+                        msg: "expect 'from' after imported names".to_string(),
+                    },
+                ));
+            }
+            self.advance();
+            Some(names)
+        } else {
+            None
+        };
+        let path = self.consume(TokenType::Str, "expect module path".to_string())?;
+        self.consume(TokenType::Semicolon, "expect ';' after import".to_string())?;
+        Ok(Stmt::Import(path, names))
+    }
+
+    fn class_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let name = self.consume(TokenType::Identifier, "expect class name".to_string())?;
 
         let superclass = if self.is_match(vec![TokenType::Less]) {
-            self.consume(TokenType::Identifier, "expect superclass name".to_string());
-            Some(Expr::Variable(self.previous(), Rc::new(Cell::new(-1))))
+            self.consume(TokenType::Identifier, "expect superclass name".to_string())?;
+            Some(Expr::Variable(
+                self.previous(),
+                Rc::new(Cell::new(Slot::default())),
+            ))
         } else {
             None
         };
@@ -44,35 +128,47 @@ impl Parser {
         self.consume(
             TokenType::LeftBrace,
             "expect '{' before class body".to_string(),
-        );
+        )?;
 
         let mut methods = Vec::new();
         while !self.check(TokenType::RightBrace) && !self.is_at_end() {
-            methods.push(self.function("method".to_string()));
+            methods.push(self.function("method".to_string())?);
         }
 
         self.consume(
             TokenType::RightBrace,
             "expect '}' after class body".to_string(),
-        );
+        )?;
 
-        Stmt::Class(name, superclass, Box::new(methods))
+        Ok(Stmt::Class(name, superclass, methods))
     }
 
-    fn function(&mut self, kind: String) -> Stmt {
-        let name = self.consume(TokenType::Identifier, format!("expect {} name", kind));
+    fn function(&mut self, kind: String) -> Result<Stmt, ParseError> {
+        let name = self.consume(TokenType::Identifier, format!("expect {} name", kind))?;
         self.consume(
             TokenType::LeftParen,
             format!("expect '(' after {} name", kind),
-        );
+        )?;
+        let args = self.parameters()?;
+        self.consume(TokenType::LeftBrace, "Expect '{' before body.".to_string())?;
+        self.descend()?;
+        let body = self.block_statement()?;
+        self.ascend();
+        Ok(Stmt::Func(name, args, Box::new(body)))
+    }
+
+    // Parses a parameter list up to and including its closing parenthesis.
+    fn parameters(&mut self) -> Result<Vec<Token>, ParseError> {
         let mut args = Vec::new();
         if !self.check(TokenType::RightParen) {
             loop {
                 if args.len() >= 255 {
-                    panic!("arguments too long!");
+                    return Err(self.error(self.peek(), ParseErrorKind::TooManyParameters));
                 }
 
-                args.push(self.consume(TokenType::Identifier, "expect arguments name".to_string()));
+                args.push(
+                    self.consume(TokenType::Identifier, "expect arguments name".to_string())?,
+                );
 
                 if !self.is_match(vec![TokenType::Comma]) {
                     break;
@@ -82,28 +178,74 @@ impl Parser {
         self.consume(
             TokenType::RightParen,
             "expect ')' after arguments".to_string(),
-        );
+        )?;
+        Ok(args)
+    }
 
-        self.consume(TokenType::LeftBrace, "Expect '{' before body.".to_string());
-        let body = self.block_statement();
-        Stmt::Func(name, args, Box::new(body))
+    // Parses the rest of `fun (a) { ... }` once `fun` is consumed.
+    fn lambda(&mut self) -> Result<Expr, ParseError> {
+        let name = lambda_name(&self.previous());
+        self.consume(TokenType::LeftParen, "expect '(' after 'fun'".to_string())?;
+        let args = self.parameters()?;
+        self.consume(TokenType::LeftBrace, "expect '{' before body".to_string())?;
+        let body = self.block_statement()?;
+        Ok(Expr::Lambda(name, args, Box::new(body)))
+    }
+
+    // Parses the rest of `(a) => a * 2` once `(` is consumed. The body is
+    // returned as if it were `{ return a * 2; }`.
+    fn arrow(&mut self) -> Result<Expr, ParseError> {
+        let name = lambda_name(&self.previous());
+        let args = self.parameters()?;
+        let arrow = self.consume(TokenType::Arrow, "expect '=>' after parameters".to_string())?;
+        let value = self.expression()?;
+        let body = Stmt::Block(vec![Stmt::Return(arrow, Some(value))]);
+        Ok(Expr::Lambda(name, args, Box::new(body)))
+    }
+
+    // Whether the tokens from `start` on are the parameters of an arrow
+    // function, `a, b) =>`, so the `(` before them starts one rather than a
+    // grouping.
+    fn at_arrow_parameters(&self, start: usize) -> bool {
+        let mut i = start;
+        if self.tokens[i].token_type != TokenType::RightParen {
+            loop {
+                if self.tokens[i].token_type != TokenType::Identifier {
+                    return false;
+                }
+                i += 1;
+                if self.tokens[i].token_type != TokenType::Comma {
+                    break;
+                }
+                i += 1;
+            }
+        }
+        self.tokens[i].token_type == TokenType::RightParen
+            && self.tokens.get(i + 1).map(|token| token.token_type) == Some(TokenType::Arrow)
     }
 
-    fn var_declaration(&mut self) -> Stmt {
-        let name = self.consume(TokenType::Identifier, "expect variable name".to_string());
+    fn var_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let name = self.consume(TokenType::Identifier, "expect variable name".to_string())?;
         let init = if self.is_match(vec![TokenType::Equal]) {
-            Some(self.expression())
+            Some(self.expression()?)
         } else {
             None
         };
         self.consume(
             TokenType::Semicolon,
             "Expect ';' after variable declaration.".to_string(),
-        );
-        Stmt::Var(name, init)
+        )?;
+        Ok(Stmt::Var(name, init))
     }
 
-    fn statement(&mut self) -> Stmt {
+    fn statement(&mut self) -> Result<Stmt, ParseError> {
+        self.descend()?;
+        let stmt = self.statement_body()?;
+        self.ascend();
+        Ok(stmt)
+    }
+
+    fn statement_body(&mut self) -> Result<Stmt, ParseError> {
         if self.is_match(vec![TokenType::Print]) {
             self.print_statement()
         } else if self.is_match(vec![TokenType::LeftBrace]) {
@@ -116,185 +258,270 @@ impl Parser {
             self.for_statement()
         } else if self.is_match(vec![TokenType::Return]) {
             self.return_statement()
+        } else if self.check(TokenType::Import) {
+            Err(self.error(self.peek(), ParseErrorKind::ImportNotAtTopLevel))
+        } else if self.is_match(vec![TokenType::Throw]) {
+            self.throw_statement()
+        } else if self.is_match(vec![TokenType::Try]) {
+            self.try_statement()
+        } else if self.is_match(vec![TokenType::Break]) {
+            let keyword = self.previous();
+            self.consume(TokenType::Semicolon, "expect ';' after 'break'".to_string())?;
+            Ok(Stmt::Break(keyword))
+        } else if self.is_match(vec![TokenType::Continue]) {
+            let keyword = self.previous();
+            self.consume(
+                TokenType::Semicolon,
+                "expect ';' after 'continue'".to_string(),
+            )?;
+            Ok(Stmt::Continue(keyword))
         } else {
             self.expr_statement()
         }
     }
 
-    fn print_statement(&mut self) -> Stmt {
-        let expr = self.expression();
-        self.consume(TokenType::Semicolon, "Expect ';' after value.".to_string());
-        Stmt::Print(expr)
+    fn print_statement(&mut self) -> Result<Stmt, ParseError> {
+        let expr = self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after value.".to_string())?;
+        Ok(Stmt::Print(expr))
     }
 
-    fn return_statement(&mut self) -> Stmt {
+    fn return_statement(&mut self) -> Result<Stmt, ParseError> {
         let keyword = self.previous();
         let value = if !self.check(TokenType::Semicolon) {
-            Some(self.expression())
+            Some(self.expression()?)
         } else {
             None
         };
         self.consume(
             TokenType::Semicolon,
             "Expect ';' after return value.".to_string(),
-        );
-        Stmt::Return(keyword, value)
+        )?;
+        Ok(Stmt::Return(keyword, value))
+    }
+
+    fn throw_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous();
+        let value = self.expression()?;
+        self.consume(
+            TokenType::Semicolon,
+            "expect ';' after thrown value".to_string(),
+        )?;
+        Ok(Stmt::Throw(keyword, value))
     }
 
-    fn block_statement(&mut self) -> Stmt {
+    fn try_statement(&mut self) -> Result<Stmt, ParseError> {
+        self.consume(TokenType::LeftBrace, "expect '{' after 'try'".to_string())?;
+        let body = self.block_statement()?;
+
+        let catch = if self.is_match(vec![TokenType::Catch]) {
+            self.consume(TokenType::LeftParen, "expect '(' after 'catch'".to_string())?;
+            let name = self.consume(
+                TokenType::Identifier,
+                "expect exception variable name".to_string(),
+            )?;
+            self.consume(
+                TokenType::RightParen,
+                "expect ')' after exception variable".to_string(),
+            )?;
+            self.consume(
+                TokenType::LeftBrace,
+                "expect '{' before catch body".to_string(),
+            )?;
+            Some((name, Box::new(self.block_statement()?)))
+        } else {
+            None
+        };
+
+        let finally = if self.is_match(vec![TokenType::Finally]) {
+            self.consume(
+                TokenType::LeftBrace,
+                "expect '{' after 'finally'".to_string(),
+            )?;
+            Some(Box::new(self.block_statement()?))
+        } else {
+            None
+        };
+
+        if catch.is_none() && finally.is_none() {
+            return Err(self.error(
+                self.peek(),
+                ParseErrorKind::Expected {
+                    expected: TokenType::Catch,
+                    msg: "expect 'catch' or 'finally' after try block".to_string(),
+                },
+            ));
+        }
+        Ok(Stmt::Try(Box::new(body), catch, finally))
+    }
+
+    fn block_statement(&mut self) -> Result<Stmt, ParseError> {
         let mut stmts = Vec::new();
         while !self.check(TokenType::RightBrace) && !self.is_at_end() {
-            let stmt = self.declaration();
+            let stmt = self.declaration()?;
             stmts.push(stmt);
         }
-        self.consume(TokenType::RightBrace, "Expect '}' after block.".to_string());
-        Stmt::Block(stmts)
+        self.consume(TokenType::RightBrace, "Expect '}' after block.".to_string())?;
+        Ok(Stmt::Block(stmts))
     }
 
-    fn if_statement(&mut self) -> Stmt {
-        self.consume(TokenType::LeftParen, "Expect '(' after 'if'.".to_string());
-        let cond = self.expression();
-        self.consume(
-            TokenType::RightParen,
-            "Expect ')' after 'if' condition.".to_string(),
-        );
-        let then_branch = Box::new(self.statement());
-        let else_branch = if self.is_match(vec![TokenType::Else]) {
-            Some(Box::new(self.statement()))
-        } else {
-            None
-        };
-        Stmt::If(cond, then_branch, else_branch)
+    fn if_statement(&mut self) -> Result<Stmt, ParseError> {
+        // An `else if` chain is read in a loop, so a long one doesn't count
+        // as deep nesting.
+        let mut branches = Vec::new();
+        let mut else_branch = None;
+        loop {
+            self.consume(TokenType::LeftParen, "Expect '(' after 'if'.".to_string())?;
+            let cond = self.expression()?;
+            self.consume(
+                TokenType::RightParen,
+                "Expect ')' after 'if' condition.".to_string(),
+            )?;
+            branches.push((cond, Box::new(self.statement()?)));
+            if !self.is_match(vec![TokenType::Else]) {
+                break;
+            }
+            if !self.is_match(vec![TokenType::If]) {
+                else_branch = Some(Box::new(self.statement()?));
+                break;
+            }
+        }
+
+        let (cond, then_branch) = branches.pop().unwrap();
+        let mut stmt = Stmt::If(cond, then_branch, else_branch);
+        while let Some((cond, then_branch)) = branches.pop() {
+            stmt = Stmt::If(cond, then_branch, Some(Box::new(stmt)));
+        }
+        Ok(stmt)
     }
 
-    fn while_statement(&mut self) -> Stmt {
-        self.consume(TokenType::LeftParen, "Expect '(' after while.".to_string());
-        let cond = self.expression();
+    fn while_statement(&mut self) -> Result<Stmt, ParseError> {
+        self.consume(TokenType::LeftParen, "Expect '(' after while.".to_string())?;
+        let cond = self.expression()?;
         self.consume(
             TokenType::RightParen,
             "Expect ')' after condition.".to_string(),
-        );
-        let body = self.statement();
-        Stmt::While(cond, Box::new(body))
+        )?;
+        let body = self.statement()?;
+        Ok(Stmt::While(cond, Box::new(body), None))
     }
 
-    fn for_statement(&mut self) -> Stmt {
-        self.consume(TokenType::LeftParen, "Expect '(' after for.".to_string());
+    fn for_statement(&mut self) -> Result<Stmt, ParseError> {
+        self.consume(TokenType::LeftParen, "Expect '(' after for.".to_string())?;
 
         let init = if self.is_match(vec![TokenType::Semicolon]) {
             None
         } else if self.is_match(vec![TokenType::Var]) {
-            Some(self.var_declaration())
+            Some(self.var_declaration()?)
         } else {
-            Some(self.expr_statement())
+            Some(self.expr_statement()?)
         };
 
         let cond = if !self.check(TokenType::Semicolon) {
-            Some(self.expression())
+            Some(self.expression()?)
         } else {
             None
         };
         self.consume(
             TokenType::Semicolon,
             "Expect ';' after loop condition.".to_string(),
-        );
+        )?;
 
         let inc = if !self.check(TokenType::RightParen) {
-            Some(self.expression())
+            Some(self.expression()?)
         } else {
             None
         };
         self.consume(
             TokenType::RightParen,
             "Expect ')' after for clauses.".to_string(),
-        );
+        )?;
 
-        let mut body = self.statement();
-        if let Some(inc) = inc {
-            body = Stmt::Block(vec![body, Stmt::Expr(inc)]);
-        };
+        let body = self.statement()?;
 
         let mut stmts = Vec::new();
         if let Some(init) = init {
             stmts.push(init);
         };
-        if let Some(cond) = cond {
-            stmts.push(Stmt::While(cond, Box::new(body)));
-        } else {
-            stmts.push(Stmt::While(
-                Expr::Literal(Literal::Bool(true)),
-                Box::new(body),
-            ));
-        }
+        let cond = cond.unwrap_or(Expr::Literal(Literal::Bool(true)));
+        stmts.push(Stmt::While(cond, Box::new(body), inc));
 
-        Stmt::Block(stmts)
+        Ok(Stmt::Block(stmts))
     }
 
-    fn expr_statement(&mut self) -> Stmt {
-        let expr = self.expression();
-        self.consume(TokenType::Semicolon, "Expect ';' after value.".to_string());
-        Stmt::Expr(expr)
+    fn expr_statement(&mut self) -> Result<Stmt, ParseError> {
+        let expr = self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after value.".to_string())?;
+        Ok(Stmt::Expr(expr))
     }
 
-    pub fn expression(&mut self) -> Expr {
+    pub fn expression(&mut self) -> Result<Expr, ParseError> {
         self.assignment()
     }
 
-    pub fn assignment(&mut self) -> Expr {
-        let mut expr = self.or();
+    pub fn assignment(&mut self) -> Result<Expr, ParseError> {
+        self.descend()?;
+        let mut expr = self.or()?;
 
         if self.is_match(vec![TokenType::Equal]) {
-            let value = self.assignment();
+            let equals = self.previous();
+            let value = self.assignment()?;
             expr = match expr {
                 Expr::Variable(token, _) => {
-                    Expr::Assign(token, Box::new(value), Rc::new(Cell::new(-1)))
+                    Expr::Assign(token, Box::new(value), Rc::new(Cell::new(Slot::default())))
                 }
                 Expr::Get(expr, name) => Expr::Set(expr, name, Box::new(value)),
-                _ => panic!("invalid assignment target"),
+                Expr::Index(expr, bracket, index) => {
+                    Expr::SetIndex(expr, bracket, index, Box::new(value))
+                }
+                _ => {
+                    return Err(self.error(equals, ParseErrorKind::InvalidAssignmentTarget));
+                }
             }
         }
 
-        expr
+        self.ascend();
+        Ok(expr)
     }
 
-    pub fn or(&mut self) -> Expr {
-        let mut expr = self.and();
+    pub fn or(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.and()?;
 
         if self.is_match(vec![TokenType::Or]) {
             let op = self.previous();
-            let right = self.and();
+            let right = self.and()?;
             expr = Expr::Logical(Box::new(expr), op, Box::new(right));
         }
 
-        expr
+        Ok(expr)
     }
 
-    pub fn and(&mut self) -> Expr {
-        let mut expr = self.equality();
+    pub fn and(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.equality()?;
 
         if self.is_match(vec![TokenType::And]) {
             let op = self.previous();
-            let right = self.equality();
+            let right = self.equality()?;
             expr = Expr::Logical(Box::new(expr), op, Box::new(right));
         }
 
-        expr
+        Ok(expr)
     }
 
-    fn equality(&mut self) -> Expr {
-        let mut expr = self.comparison();
+    fn equality(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.comparison()?;
 
         while self.is_match(vec![TokenType::BangEqual, TokenType::EqualEqual]) {
             let op = self.previous();
-            let right = self.comparison();
+            let right = self.comparison()?;
             expr = Expr::Binary(Box::new(expr), op, Box::new(right));
         }
 
-        expr
+        Ok(expr)
     }
 
-    fn comparison(&mut self) -> Expr {
-        let mut expr = self.term();
+    fn comparison(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.term()?;
 
         while self.is_match(vec![
             TokenType::GreaterEqual,
@@ -303,73 +530,82 @@ impl Parser {
             TokenType::Less,
         ]) {
             let op = self.previous();
-            let right = self.term();
+            let right = self.term()?;
             expr = Expr::Binary(Box::new(expr), op, Box::new(right));
         }
 
-        expr
+        Ok(expr)
     }
 
-    fn term(&mut self) -> Expr {
-        let mut expr = self.factor();
+    fn term(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.factor()?;
 
         while self.is_match(vec![TokenType::Plus, TokenType::Minus]) {
             let op = self.previous();
-            let right = self.factor();
+            let right = self.factor()?;
             expr = Expr::Binary(Box::new(expr), op, Box::new(right));
         }
 
-        expr
+        Ok(expr)
     }
 
-    fn factor(&mut self) -> Expr {
-        let mut expr = self.unary();
+    fn factor(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.unary()?;
 
         while self.is_match(vec![TokenType::Star, TokenType::Slash]) {
             let op = self.previous();
-            let right = self.unary();
+            let right = self.unary()?;
             expr = Expr::Binary(Box::new(expr), op, Box::new(right));
         }
 
-        expr
+        Ok(expr)
     }
 
-    fn unary(&mut self) -> Expr {
+    fn unary(&mut self) -> Result<Expr, ParseError> {
         if self.is_match(vec![TokenType::Bang, TokenType::Minus]) {
             let op = self.previous();
-            let right = self.unary();
-            Expr::Unary(op, Box::new(right))
+            self.descend()?;
+            let right = self.unary()?;
+            self.ascend();
+            Ok(Expr::Unary(op, Box::new(right)))
         } else {
             self.call()
         }
     }
 
-    fn call(&mut self) -> Expr {
-        let mut expr = self.primary();
+    fn call(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.primary()?;
         loop {
             if self.is_match(vec![TokenType::LeftParen]) {
-                expr = self.finish_call(expr);
+                expr = self.finish_call(expr)?;
             } else if self.is_match(vec![TokenType::Dot]) {
                 let name = self.consume(
                     TokenType::Identifier,
                     "expect property name after '.'".to_string(),
-                );
+                )?;
                 expr = Expr::Get(Box::new(expr), name);
+            } else if self.is_match(vec![TokenType::LeftBracket]) {
+                let index = self.expression()?;
+                let bracket = self.consume(
+                    TokenType::RightBracket,
+                    "expect ']' after index".to_string(),
+                )?;
+                expr = Expr::Index(Box::new(expr), bracket, Box::new(index));
             } else {
                 break;
             }
         }
-        expr
+        Ok(expr)
     }
 
-    fn finish_call(&mut self, callee: Expr) -> Expr {
+    fn finish_call(&mut self, callee: Expr) -> Result<Expr, ParseError> {
         let mut args = Vec::new();
         if !self.check(TokenType::RightParen) {
             loop {
                 if args.len() >= 255 {
-                    panic!("Can't have more than 255 arguments.");
+                    return Err(self.error(self.peek(), ParseErrorKind::TooManyArguments));
                 }
-                args.push(self.expression());
+                args.push(self.expression()?);
                 if !self.is_match(vec![TokenType::Comma]) {
                     break;
                 }
@@ -378,48 +614,169 @@ impl Parser {
         let paren = self.consume(
             TokenType::RightParen,
             "Expect ')' after arguments.".to_string(),
-        );
-        Expr::Call(Box::new(callee), paren, args)
+        )?;
+        Ok(Expr::Call(Box::new(callee), paren, args))
     }
 
-    fn primary(&mut self) -> Expr {
+    fn primary(&mut self) -> Result<Expr, ParseError> {
         if self.is_match(vec![TokenType::False]) {
-            Expr::Literal(Literal::Bool(false))
+            Ok(Expr::Literal(Literal::Bool(false)))
         } else if self.is_match(vec![TokenType::True]) {
-            Expr::Literal(Literal::Bool(true))
+            Ok(Expr::Literal(Literal::Bool(true)))
         } else if self.is_match(vec![TokenType::Nil]) {
-            Expr::Literal(Literal::Nil)
+            Ok(Expr::Literal(Literal::Nil))
         } else if self.is_match(vec![TokenType::Number, TokenType::Str]) {
-            Expr::Literal(self.previous().lit.unwrap())
+            Ok(Expr::Literal(self.previous().lit.unwrap()))
+        } else if self.is_match(vec![TokenType::Fun]) {
+            self.lambda()
+        } else if self.check(TokenType::LeftParen) && self.at_arrow_parameters(self.current + 1) {
+            self.advance();
+            self.arrow()
         } else if self.is_match(vec![TokenType::LeftParen]) {
-            let expr = self.expression();
-            self.consume(
-                TokenType::RightParen,
-                "Expect ')' after expression.".to_string(),
-            );
-            Expr::Grouping(Box::new(expr))
+            self.grouping()
+        } else if self.is_match(vec![TokenType::LeftBracket]) {
+            self.list()
+        } else if self.is_match(vec![TokenType::LeftBrace]) {
+            self.map()
         } else if self.is_match(vec![TokenType::Identifier]) {
-            Expr::Variable(self.previous(), Rc::new(Cell::new(-1)))
+            Ok(Expr::Variable(
+                self.previous(),
+                Rc::new(Cell::new(Slot::default())),
+            ))
         } else if self.is_match(vec![TokenType::This]) {
-            Expr::This(self.previous(), Rc::new(Cell::new(-1)))
+            Ok(Expr::This(
+                self.previous(),
+                Rc::new(Cell::new(Slot::default())),
+            ))
         } else if self.is_match(vec![TokenType::Super]) {
-            let keyword = self.previous();
-            self.consume(TokenType::Dot, "expect '.' after 'super'".to_string());
-            let method = self.consume(
-                TokenType::Identifier,
-                "expect superclass method name".to_string(),
-            );
-            Expr::Super(keyword, method, Rc::new(Cell::new(-1)))
+            self.super_method()
         } else {
-            panic!("{:?} not to land here", self.peek());
+            Err(self.error(self.peek(), ParseErrorKind::ExpectedExpression))
         }
     }
 
-    fn consume(&mut self, token_type: TokenType, s: String) -> Token {
+    // The forms below are parsed outside `primary`, which every nested
+    // expression passes through, to keep its stack frame small.
+
+    fn grouping(&mut self) -> Result<Expr, ParseError> {
+        let expr = self.expression()?;
+        self.consume(
+            TokenType::RightParen,
+            "Expect ')' after expression.".to_string(),
+        )?;
+        Ok(Expr::Grouping(Box::new(expr)))
+    }
+
+    fn list(&mut self) -> Result<Expr, ParseError> {
+        let bracket = self.previous();
+        let mut items = Vec::new();
+        if !self.check(TokenType::RightBracket) {
+            loop {
+                items.push(self.expression()?);
+                if !self.is_match(vec![TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(
+            TokenType::RightBracket,
+            "expect ']' after list elements".to_string(),
+        )?;
+        Ok(Expr::List(bracket, items))
+    }
+
+    fn map(&mut self) -> Result<Expr, ParseError> {
+        let brace = self.previous();
+        let mut entries = Vec::new();
+        if !self.check(TokenType::RightBrace) {
+            loop {
+                let key = self.expression()?;
+                self.consume(TokenType::Colon, "expect ':' after map key".to_string())?;
+                entries.push((key, self.expression()?));
+                if !self.is_match(vec![TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(
+            TokenType::RightBrace,
+            "expect '}' after map entries".to_string(),
+        )?;
+        Ok(Expr::Map(brace, entries))
+    }
+
+    fn super_method(&mut self) -> Result<Expr, ParseError> {
+        let keyword = self.previous();
+        self.consume(TokenType::Dot, "expect '.' after 'super'".to_string())?;
+        let method = self.consume(
+            TokenType::Identifier,
+            "expect superclass method name".to_string(),
+        )?;
+        Ok(Expr::Super(
+            keyword,
+            method,
+            Rc::new(Cell::new(Slot::default())),
+        ))
+    }
+
+    fn consume(&mut self, token_type: TokenType, s: String) -> Result<Token, ParseError> {
         if self.check(token_type) {
-            self.advance()
+            Ok(self.advance())
         } else {
-            panic!(s);
+            Err(self.error(
+                self.peek(),
+                ParseErrorKind::Expected {
+                    expected: token_type,
+                    msg: s,
+                },
+            ))
+        }
+    }
+
+    // Goes one level deeper into the tree being built.
+    fn descend(&mut self) -> Result<(), ParseError> {
+        if self.depth >= MAX_NESTING {
+            return Err(self.error(self.peek(), ParseErrorKind::TooDeep(MAX_NESTING)));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn ascend(&mut self) {
+        self.depth -= 1;
+    }
+
+    fn error(&self, token: Token, kind: ParseErrorKind) -> ParseError {
+        ParseError::new(token, kind)
+    }
+
+    // Discards tokens until the start of the next statement so that one
+    // syntax error doesn't cascade into a pile of bogus ones.
+    fn synchronize(&mut self) {
+        self.advance();
+        while !self.is_at_end() {
+            if self.previous().token_type == TokenType::Semicolon {
+                return;
+            }
+
+            match self.peek().token_type {
+                TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return
+                | TokenType::Break
+                | TokenType::Continue
+                | TokenType::Throw
+                | TokenType::Try
+                | TokenType::Import => return,
+                _ => {}
+            }
+
+            self.advance();
         }
     }
 
@@ -447,6 +804,13 @@ impl Parser {
         self.previous()
     }
 
+    fn check_next(&self, token_type: TokenType) -> bool {
+        match self.tokens.get(self.current + 1) {
+            Some(token) => token.token_type == token_type,
+            None => false,
+        }
+    }
+
     fn peek(&self) -> Token {
         self.tokens[self.current].clone()
     }
@@ -460,11 +824,23 @@ impl Parser {
     }
 }
 
+// Lambdas have no name of their own, so backtraces show them as `<lambda>`
+// at the token that starts them.
+fn lambda_name(start: &Token) -> Token {
+    Token {
+        token_type: TokenType::Identifier,
+        lexeme: "<lambda>".to_string(),
+        lit: None,
+        span: start.span,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::expr::Expr;
     use crate::scanner::Scanner;
+    use crate::token::Span;
 
     #[test]
     fn test_expression() {
@@ -473,23 +849,193 @@ mod tests {
             source: s.chars().collect(),
             ..Default::default()
         };
-        let tokens = scanner.scan_tokens();
-        let mut parser = Parser {
-            tokens: tokens,
-            current: 0,
-        };
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens);
         assert_eq!(
-            parser.expression(),
+            parser.expression().unwrap(),
             Expr::Binary(
                 Box::new(Expr::Literal(Literal::Number(1f64))),
                 Token {
                     token_type: TokenType::Plus,
                     lexeme: "+".to_string(),
                     lit: None,
-                    line: 1
+                    span: Span {
+                        file_id: 0,
+                        start: 2,
+                        end: 3,
+                        line: 1,
+                        column: 3,
+                    },
                 },
                 Box::new(Expr::Literal(Literal::Number(2f64)))
             )
         );
     }
+
+    fn parse(s: &str) -> Result<Vec<Stmt>, Vec<ParseError>> {
+        let mut scanner = Scanner {
+            source: s.chars().collect(),
+            ..Default::default()
+        };
+        let tokens = scanner.scan_tokens().unwrap();
+        Parser::new(tokens).parse()
+    }
+
+    #[test]
+    fn test_missing_semicolon() {
+        let errors = parse("print 1").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].token.token_type, TokenType::Eof);
+        assert_eq!(
+            errors[0].kind,
+            ParseErrorKind::Expected {
+                expected: TokenType::Semicolon,
+                msg: "Expect ';' after value.".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_invalid_assignment_target() {
+        let errors = parse("1 + 2 = 3;").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].token.lexeme, "=");
+        assert_eq!(errors[0].kind, ParseErrorKind::InvalidAssignmentTarget);
+        assert_eq!(errors[0].kind.code(), "E0203");
+    }
+
+    #[test]
+    fn test_list_and_index() {
+        let stmts = parse("xs[0] = [1, [2], xs[i + 1]];").unwrap();
+        match &stmts[0] {
+            Stmt::Expr(Expr::SetIndex(expr, bracket, _, value)) => {
+                assert_eq!(expr.to_string(), "variable: (xs)");
+                assert_eq!(bracket.token_type, TokenType::RightBracket);
+                match value.as_ref() {
+                    Expr::List(_, items) => {
+                        assert_eq!(items.len(), 3);
+                        assert!(matches!(items[2], Expr::Index(..)));
+                    }
+                    _ => panic!("expected a list literal, got {}", value),
+                }
+            }
+            stmt => panic!("expected an index assignment, got {:?}", stmt),
+        }
+
+        let errors = parse("[1, 2;").unwrap_err();
+        assert_eq!(
+            errors[0].kind,
+            ParseErrorKind::Expected {
+                expected: TokenType::RightBracket,
+                msg: "expect ']' after list elements".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_lambdas() {
+        let errors = parse("(a, b) => a; ();").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].token.lexeme, ")");
+
+        let stmts = parse("fun (a) { return a; }; (a, b) => a + b; (a);").unwrap();
+        let exprs: Vec<String> = stmts
+            .iter()
+            .map(|stmt| match stmt {
+                Stmt::Expr(expr) => expr.to_string(),
+                stmt => panic!("expected an expression, got {:?}", stmt),
+            })
+            .collect();
+        assert_eq!(
+            exprs,
+            vec!["lambda: (a)", "lambda: (a b)", "grouping: (variable: (a))"]
+        );
+    }
+
+    #[test]
+    fn test_try_needs_a_clause() {
+        let errors = parse("try { throw 1; }\nprint 2;").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].token.token_type, TokenType::Print);
+        assert_eq!(
+            errors[0].kind,
+            ParseErrorKind::Expected {
+                expected: TokenType::Catch,
+                msg: "expect 'catch' or 'finally' after try block".to_string(),
+            }
+        );
+        assert!(parse("try {} finally {}\ntry {} catch (e) {} finally {}").is_ok());
+    }
+
+    #[test]
+    fn test_imports() {
+        let stmts = parse("import \"a\";\nimport { b, c } from \"d.lox\";").unwrap();
+        match &stmts[1] {
+            Stmt::Import(path, Some(names)) => {
+                assert_eq!(path.lit, Some(Literal::Str("d.lox".to_string())));
+                let names: Vec<&str> = names.iter().map(|name| name.lexeme.as_str()).collect();
+                assert_eq!(names, vec!["b", "c"]);
+            }
+            stmt => panic!("expected an import, got {:?}", stmt),
+        }
+
+        for s in ["{ import \"a\"; }", "fun f() { import \"b\"; }"].iter() {
+            let errors = parse(s).unwrap_err();
+            assert_eq!(errors[0].kind, ParseErrorKind::ImportNotAtTopLevel, "{}", s);
+        }
+        assert!(parse("import { a } \"b\";").is_err());
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let deep = MAX_NESTING + 1;
+        let programs = vec![
+            format!("print {}1{};", "(".repeat(deep), ")".repeat(deep)),
+            format!("print {}1;", "-".repeat(deep)),
+            format!("print {}1{};", "[".repeat(deep), "]".repeat(deep)),
+            format!("print {}1{};", "f(".repeat(deep), ")".repeat(deep)),
+            format!("{}{}", "{".repeat(deep), "}".repeat(deep)),
+            format!("{}{}", "fun f() {".repeat(deep), "}".repeat(deep)),
+            format!("{}1; else 2; else 3;", "if (a) ".repeat(deep)),
+            format!("print {}1{};", "(".repeat(50_000), ")".repeat(50_000)),
+            format!("print {}1;", "-".repeat(50_000)),
+        ];
+        // The limit keeps parsing within a spawned thread's default stack,
+        // even in a debug build.
+        let run = std::thread::spawn(move || {
+            for program in programs {
+                // Nothing after the error is parsed, so it reports no
+                // cascade of bogus ones.
+                let errors = parse(&program).unwrap_err();
+                assert_eq!(errors, vec![errors[0].clone()]);
+                assert_eq!(errors[0].kind, ParseErrorKind::TooDeep(MAX_NESTING));
+            }
+            // The statement and the expression around the groupings count
+            // too.
+            let fine = MAX_NESTING - 2;
+            assert!(parse(&format!("print {}1{};", "(".repeat(fine), ")".repeat(fine))).is_ok());
+        });
+        run.join().unwrap();
+    }
+
+    #[test]
+    fn test_flat_chains_are_not_nesting() {
+        let programs = vec![
+            format!("print \"x\"{};", " + \"x\"".repeat(1000)),
+            format!("print 1{};", " * 2 - 3 < 4 == true".repeat(1000)),
+            format!("print a{};", ".b(1)[2]".repeat(1000)),
+            format!("if (a) 1;{} else 2;", " else if (b) 1;".repeat(1000)),
+        ];
+        for program in programs {
+            assert!(parse(&program).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_synchronize_reports_every_error() {
+        let s = "var = 1;\nprint 2;\nvar b = ;\nfun f( {}\nprint 3;";
+        let errors = parse(s).unwrap_err();
+        let lines: Vec<usize> = errors.iter().map(|err| err.line()).collect();
+        assert_eq!(lines, vec![1, 3, 4]);
+    }
 }
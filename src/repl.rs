@@ -0,0 +1,404 @@
+use anyhow::Result;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+
+use crate::error::{LexError, LexErrorKind};
+use crate::lox::Lox;
+use crate::lox_instance::LoxInstance;
+use crate::lox_value::LoxValue;
+use crate::parser::Parser;
+use crate::scanner::{Scanner, KEYWORDS};
+use crate::stmt::Stmt;
+use crate::token::{Token, TokenType};
+
+const HELP: &str = "\
+:help           show this message
+:quit           leave the REPL, as does Ctrl-D
+:env            list the globals defined so far
+:ast <code>     show the statements <code> parses to
+:tokens <code>  show the tokens <code> scans to";
+
+// Reads input until its brackets balance, runs it and echoes the value of a
+// trailing expression statement. Lines starting with `:` are commands.
+pub fn run(lox: &mut Lox) -> Result<()> {
+    let mut editor = Editor::new();
+    editor.set_helper(Some(Completions::default()));
+    let history = history_file();
+    if let Some(history) = &history {
+        // There is no history on the first run.
+        let _ = editor.load_history(history);
+    }
+    // What is defined before anything is typed; `:env` leaves it out.
+    let builtins: HashMap<String, LoxValue> = lox.globals().into_iter().collect();
+
+    let mut buffer = String::new();
+    loop {
+        if let Some(completions) = editor.helper_mut() {
+            completions.globals = lox.globals();
+        }
+        let prompt = if buffer.is_empty() { "> " } else { "... " };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            // Ctrl-C drops the input typed so far.
+            Err(ReadlineError::Interrupted) => {
+                buffer.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(err.into()),
+        };
+
+        if buffer.is_empty() && line.trim_start().starts_with(':') {
+            editor.add_history_entry(line.trim());
+            if !command(lox, &builtins, line.trim()) {
+                break;
+            }
+            continue;
+        }
+
+        buffer.push_str(&line);
+        buffer.push('\n');
+        if !is_complete(&buffer) {
+            continue;
+        }
+        let input = std::mem::take(&mut buffer);
+        if input.trim().is_empty() {
+            continue;
+        }
+        editor.add_history_entry(input.trim_end());
+        eval(lox, &terminate(&input));
+    }
+
+    if let Some(history) = &history {
+        let _ = editor.save_history(history);
+    }
+    Ok(())
+}
+
+fn history_file() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".rlox_history"))
+}
+
+fn eval(lox: &mut Lox, source: &str) {
+    let echo = ends_with_expression(source);
+    match lox.run_source("<stdin>", source) {
+        Ok(value) if echo => println!("{}", show(&value)),
+        Ok(_) => {}
+        Err(err) => lox.report(&err),
+    }
+}
+
+// Runs a `:` command and returns whether the REPL should keep going.
+fn command(lox: &mut Lox, builtins: &HashMap<String, LoxValue>, line: &str) -> bool {
+    let (name, code) = match line.find(char::is_whitespace) {
+        Some(end) => (&line[..end], line[end..].trim()),
+        None => (line, ""),
+    };
+    match name {
+        ":quit" | ":q" => return false,
+        ":help" => println!("{}", HELP),
+        ":env" => {
+            for (name, value) in lox.globals() {
+                if builtins.get(&name) != Some(&value) {
+                    println!("{} = {}", name, show(&value));
+                }
+            }
+        }
+        ":ast" => match lox.parse("<stdin>", &terminate(code)) {
+            Ok(stmts) => {
+                for stmt in stmts {
+                    println!("{:#?}", stmt);
+                }
+            }
+            Err(err) => lox.report(&err),
+        },
+        ":tokens" => match lox.scan("<stdin>", code) {
+            Ok(tokens) => {
+                for token in tokens {
+                    println!(
+                        "{}:{} {:?} {}",
+                        token.span.line, token.span.column, token.token_type, token.lexeme
+                    );
+                }
+            }
+            Err(err) => lox.report(&err),
+        },
+        _ => println!("unknown command `{}`; try :help", name),
+    }
+    true
+}
+
+fn scan(source: &str) -> Result<Vec<Token>, Vec<LexError>> {
+    let mut scanner = Scanner {
+        source: source.chars().collect(),
+        ..Default::default()
+    };
+    scanner.scan_tokens()
+}
+
+// Whether `source` can be run as it is: every bracket it opens is closed and
+// no string is left open. Other mistakes are reported when it runs.
+fn is_complete(source: &str) -> bool {
+    let tokens = match scan(source) {
+        Ok(tokens) => tokens,
+        Err(errors) => {
+            return !errors
+                .iter()
+                .any(|err| err.kind == LexErrorKind::UnterminatedString)
+        }
+    };
+    let depth: i32 = tokens
+        .iter()
+        .map(|token| match token.token_type {
+            TokenType::LeftParen | TokenType::LeftBrace | TokenType::LeftBracket => 1,
+            TokenType::RightParen | TokenType::RightBrace | TokenType::RightBracket => -1,
+            _ => 0,
+        })
+        .sum();
+    depth <= 0
+}
+
+// Adds the `;` that is easy to leave off after the last statement, if the
+// input only parses with it.
+fn terminate(source: &str) -> String {
+    if parses(source) {
+        return source.to_string();
+    }
+    let tokens = scan(source).unwrap_or_default();
+    let last = tokens
+        .iter()
+        .rev()
+        .find(|token| token.token_type != TokenType::Eof);
+    if let Some(token) = last {
+        let mut terminated = source.to_string();
+        terminated.insert(token.span.end, ';');
+        if parses(&terminated) {
+            return terminated;
+        }
+    }
+    source.to_string()
+}
+
+fn parses(source: &str) -> bool {
+    match scan(source) {
+        Ok(tokens) => Parser::new(tokens).parse().is_ok(),
+        Err(_) => false,
+    }
+}
+
+// Whether the last statement of `source` is an expression, whose value is
+// worth echoing.
+fn ends_with_expression(source: &str) -> bool {
+    let tokens = match scan(source) {
+        Ok(tokens) => tokens,
+        Err(_) => return false,
+    };
+    match Parser::new(tokens).parse() {
+        Ok(stmts) => matches!(stmts.last(), Some(Stmt::Expr(_))),
+        Err(_) => false,
+    }
+}
+
+// Tab completion of keywords and globals, and of the fields and methods of
+// an instance after `name.`, where `name` is a global or a chain of fields.
+#[derive(Default)]
+struct Completions {
+    // The globals as of the last run.
+    globals: Vec<(String, LoxValue)>,
+}
+
+impl Completions {
+    // Where the word before `pos` starts and the names that complete it.
+    fn candidates(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let before = &line[..pos];
+        let start = before
+            .rfind(|c: char| !is_identifier(c))
+            .map_or(0, |i| i + 1);
+        let word = &before[start..];
+
+        let mut names: Vec<String> = match before[..start].strip_suffix('.') {
+            Some(receiver) => match self.receiver(receiver) {
+                Some(LoxValue::Instance(instance)) => members(&instance.borrow()),
+                _ => Vec::new(),
+            },
+            None => KEYWORDS
+                .keys()
+                .map(|keyword| keyword.to_string())
+                .chain(self.globals.iter().map(|(name, _)| name.clone()))
+                .collect(),
+        };
+        names.retain(|name| name.starts_with(word));
+        names.sort();
+        names.dedup();
+        (start, names)
+    }
+
+    // The value of the chain of names that `before` ends with, if each link
+    // is a global or a field. Nothing is called to find it.
+    fn receiver(&self, before: &str) -> Option<LoxValue> {
+        let start = before
+            .rfind(|c: char| !is_identifier(c) && c != '.')
+            .map_or(0, |i| i + 1);
+        let mut names = before[start..].split('.');
+        let first = names.next()?;
+        let global = self.globals.iter().find(|(name, _)| name == first);
+        names.try_fold(global?.1.clone(), |value, name| match value {
+            LoxValue::Instance(instance) => instance.borrow().field(name),
+            _ => None,
+        })
+    }
+}
+
+// The fields of `instance` and the methods of its class and superclasses.
+fn members(instance: &LoxInstance) -> Vec<String> {
+    let mut names: Vec<String> = instance.fields().keys().cloned().collect();
+    let mut class = Some(instance.class().clone());
+    while let Some(inner) = class {
+        names.extend(inner.methods().keys().cloned());
+        class = inner
+            .superclass()
+            .map(|superclass| superclass.inner.clone());
+    }
+    names
+}
+
+fn is_identifier(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+impl Completer for Completions {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.candidates(line, pos))
+    }
+}
+
+impl Hinter for Completions {
+    type Hint = String;
+}
+
+impl Highlighter for Completions {}
+
+impl Validator for Completions {}
+
+impl Helper for Completions {}
+
+// Strings are quoted so that they can be told apart from other values.
+fn show(value: &LoxValue) -> String {
+    match value {
+        LoxValue::Str(s) => format!("{:?}", s),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::on_each_backend;
+
+    #[test]
+    fn test_is_complete() {
+        assert!(is_complete("print 1;"));
+        assert!(is_complete("fun f() { return 1; }"));
+        assert!(!is_complete("fun f() {"));
+        assert!(!is_complete("class A {\n  m() {\n    print [1,\n"));
+        assert!(is_complete("class A {\n  m() {}\n}\n"));
+        assert!(!is_complete("print \"two\nlines"));
+        // Strays are left for the parser to report.
+        assert!(is_complete("print 1);"));
+        assert!(is_complete("print @;"));
+    }
+
+    #[test]
+    fn test_terminate() {
+        assert_eq!(terminate("1 + 2"), "1 + 2;");
+        assert_eq!(terminate("1 + 2\n"), "1 + 2;\n");
+        assert_eq!(terminate("x // note"), "x; // note");
+        assert_eq!(terminate("print 1;"), "print 1;");
+        assert_eq!(terminate("fun f() {}"), "fun f() {}");
+        assert_eq!(terminate("var m = {\"a\": 1}"), "var m = {\"a\": 1};");
+        assert_eq!(
+            terminate("var f = fun () { return 2; }"),
+            "var f = fun () { return 2; };"
+        );
+        assert_eq!(terminate("class A {}"), "class A {}");
+        assert_eq!(terminate("if (true) { 1; }"), "if (true) { 1; }");
+        // Input that is wrong either way is left for the error to show.
+        assert_eq!(terminate("1 +"), "1 +");
+        assert_eq!(terminate(""), "");
+    }
+
+    #[test]
+    fn test_echo() {
+        assert!(ends_with_expression("1 + 2;"));
+        assert!(ends_with_expression("var a = 1; a;"));
+        assert!(!ends_with_expression("var a = 1;"));
+        assert!(!ends_with_expression("print 1;"));
+        assert!(!ends_with_expression("1 +;"));
+        assert_eq!(show(&LoxValue::Str("hi".to_string())), "\"hi\"");
+        assert_eq!(show(&LoxValue::Number(3.0)), "3");
+    }
+
+    #[test]
+    fn test_completions() {
+        on_each_backend(|mut lox, _| {
+            lox.run(
+                "class Shape { area() { return 0; } describe() {} }
+                 class Square < Shape {
+                   init(side) { this.side = side; }
+                   area() { return this.side * this.side; }
+                 }
+                 var square = Square(2);
+                 var box = Square(1);
+                 box.inner = square;
+                 var sum = 0;",
+            )
+            .unwrap();
+            let completions = Completions {
+                globals: lox.globals(),
+            };
+            let complete = |line: &str| completions.candidates(line, line.len());
+
+            assert_eq!(
+                complete("var n = su"),
+                (
+                    8,
+                    vec!["substr".to_string(), "sum".to_string(), "super".to_string()]
+                )
+            );
+            assert_eq!(complete("whi"), (0, vec!["while".to_string()]));
+            assert_eq!(
+                complete("print square."),
+                (
+                    13,
+                    vec!["area", "describe", "init", "side"]
+                        .into_iter()
+                        .map(String::from)
+                        .collect()
+                )
+            );
+            assert_eq!(complete("box.inner.si"), (10, vec!["side".to_string()]));
+            assert_eq!(
+                complete("box.in"),
+                (4, vec!["init".to_string(), "inner".to_string()])
+            );
+            // Only globals and fields are followed; nothing is called.
+            assert_eq!(complete("Square(3)."), (10, Vec::<String>::new()));
+            assert_eq!(complete("sum."), (4, Vec::<String>::new()));
+            assert_eq!(complete("nope.x"), (5, Vec::<String>::new()));
+        });
+    }
+}
@@ -2,17 +2,27 @@ use std::cell::Cell;
 use std::collections::HashMap;
 use std::rc::Rc;
 
-use crate::error::Error;
-use crate::expr::{walk_expr, Expr};
+use crate::error::{LoxError, ResolveError, ResolveErrorKind};
+use crate::expr::{walk_expr, Expr, Slot};
 use crate::lox_value::LoxValue;
-use crate::stmt::{walk_stmt, Stmt};
+use crate::stmt::{walk_stmt, Flow, Stmt};
 use crate::token::Token;
 use crate::visitor::Visitor;
 
 pub struct Resolver {
-    scopes: Vec<HashMap<String, bool>>,
+    scopes: Vec<HashMap<String, Local>>,
     functoin_type: FunctionType,
     class_type: ClassType,
+    // Loops enclosing the code being resolved, within the current function.
+    loop_depth: usize,
+}
+
+// A variable declared in a local scope: its slot in the scope, and whether
+// its initializer has finished.
+#[derive(Copy, Clone)]
+struct Local {
+    index: usize,
+    defined: bool,
 }
 
 #[derive(Copy, Clone)]
@@ -30,34 +40,44 @@ enum ClassType {
     None,
 }
 
+impl Default for Resolver {
+    fn default() -> Resolver {
+        Resolver::new()
+    }
+}
+
 impl Resolver {
     pub fn new() -> Resolver {
         Resolver {
             scopes: Vec::new(),
             functoin_type: FunctionType::None,
             class_type: ClassType::None,
+            loop_depth: 0,
         }
     }
 
-    pub fn resolve_stmts(&mut self, stmts: Vec<Stmt>) -> Result<LoxValue, Error> {
+    pub fn resolve_stmts(&mut self, stmts: Vec<Stmt>) -> Result<(), LoxError> {
         for stmt in stmts.iter() {
             self.resolve_stmt(stmt)?;
         }
-        Ok(LoxValue::Nil)
+        Ok(())
     }
 
-    fn resolve_stmt(&mut self, stmt: &Stmt) -> Result<LoxValue, Error> {
+    fn resolve_stmt(&mut self, stmt: &Stmt) -> Result<Flow, LoxError> {
         walk_stmt(self, stmt)
     }
 
-    fn resolve_expr(&mut self, expr: &Expr) -> Result<LoxValue, Error> {
+    fn resolve_expr(&mut self, expr: &Expr) -> Result<LoxValue, LoxError> {
         walk_expr(self, expr)
     }
 
-    fn resolve_local(&mut self, distance: Rc<Cell<i32>>, name: &Token) {
-        for (i, scope) in self.scopes.iter().rev().enumerate() {
-            if scope.contains_key(&name.lexeme) {
-                distance.set(i as i32);
+    fn resolve_local(&mut self, slot: Rc<Cell<Slot>>, name: &Token) {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(local) = scope.get(&name.lexeme) {
+                slot.set(Slot {
+                    depth: depth as i32,
+                    index: local.index,
+                });
                 return;
             }
         }
@@ -68,15 +88,18 @@ impl Resolver {
         args: Vec<Token>,
         body: &Stmt,
         function_type: FunctionType,
-    ) -> Result<LoxValue, Error> {
+    ) -> Result<LoxValue, LoxError> {
         let encloging_function_type = self.functoin_type;
         self.functoin_type = function_type;
+        let enclosing_loop_depth = std::mem::replace(&mut self.loop_depth, 0);
         self.begin_scope();
         for arg in args.iter() {
             self.declare(arg)?;
             self.define(arg);
         }
-        self.resolve_stmt(body)?;
+        let resolved = self.resolve_stmt(body);
+        self.loop_depth = enclosing_loop_depth;
+        resolved?;
         self.end_scope();
         self.functoin_type = encloging_function_type;
         Ok(LoxValue::Nil)
@@ -90,44 +113,67 @@ impl Resolver {
         self.scopes.pop();
     }
 
-    fn declare(&mut self, name: &Token) -> Result<LoxValue, Error> {
+    fn declare(&mut self, name: &Token) -> Result<LoxValue, LoxError> {
         if let Some(scope) = self.scopes.last_mut() {
             if scope.contains_key(&name.lexeme) {
-                return Err(Error {
-                    kind: "resolving error".to_string(),
-                    msg: format!(
-                        "variable `{}` that has the name already exists in this scope\nline: {}",
-                        name.lexeme, name.line,
-                    ),
-                });
+                return Err(error(
+                    name,
+                    ResolveErrorKind::AlreadyDeclared(name.lexeme.clone()),
+                ));
             }
-            scope.insert(name.lexeme.clone(), false);
+            let local = Local {
+                index: scope.len(),
+                defined: false,
+            };
+            scope.insert(name.lexeme.clone(), local);
         }
         Ok(LoxValue::Nil)
     }
 
     fn define(&mut self, name: &Token) {
+        if let Some(local) = self
+            .scopes
+            .last_mut()
+            .and_then(|scope| scope.get_mut(&name.lexeme))
+        {
+            local.defined = true;
+        }
+    }
+
+    // Declares a variable the interpreter binds itself, such as `this`.
+    fn bind(&mut self, name: &str) {
         if let Some(scope) = self.scopes.last_mut() {
-            scope.insert(name.lexeme.clone(), true);
+            let local = Local {
+                index: scope.len(),
+                defined: true,
+            };
+            scope.insert(name.to_string(), local);
         }
     }
 }
 
+fn error(token: &Token, kind: ResolveErrorKind) -> LoxError {
+    LoxError::Resolve(Box::new(ResolveError {
+        kind,
+        token: token.clone(),
+    }))
+}
+
 impl Visitor for Resolver {
-    fn visit_block(&mut self, stmts: Vec<Stmt>) -> Result<LoxValue, Error> {
+    fn visit_block(&mut self, stmts: Vec<Stmt>) -> Result<Flow, LoxError> {
         self.begin_scope();
         self.resolve_stmts(stmts)?;
         self.end_scope();
-        Ok(LoxValue::Nil)
+        Ok(Flow::Normal)
     }
 
-    fn visit_var_stmt(&mut self, token: &Token, expr: Option<&Expr>) -> Result<LoxValue, Error> {
+    fn visit_var_stmt(&mut self, token: &Token, expr: Option<&Expr>) -> Result<Flow, LoxError> {
         self.declare(token)?;
         if let Some(init) = expr {
             self.resolve_expr(init)?;
         }
         self.define(token);
-        Ok(LoxValue::Nil)
+        Ok(Flow::Normal)
     }
 
     fn visit_class(
@@ -135,7 +181,7 @@ impl Visitor for Resolver {
         name: &Token,
         superclass: Option<Expr>,
         methods: Vec<Stmt>,
-    ) -> Result<LoxValue, Error> {
+    ) -> Result<Flow, LoxError> {
         let enclosing_class = self.class_type;
         self.class_type = ClassType::Class;
         self.declare(name)?;
@@ -144,96 +190,79 @@ impl Visitor for Resolver {
         if let Some(superclass) = superclass.clone() {
             if let Expr::Variable(ref superclass, _) = superclass {
                 if name.lexeme == superclass.lexeme {
-                    return Err(Error {
-                        kind: "resolving error".to_string(),
-                        msg: "a class can't inherit from itself".to_string(),
-                    });
+                    return Err(error(
+                        superclass,
+                        ResolveErrorKind::InheritFromSelf(name.lexeme.clone()),
+                    ));
                 }
             }
             self.class_type = ClassType::Subclass;
             self.resolve_expr(&superclass)?;
 
             self.begin_scope();
-            if let Some(scope) = self.scopes.last_mut() {
-                scope.insert("super".to_string(), true);
-            }
+            self.bind("super");
         }
 
         self.begin_scope();
-        if let Some(scope) = self.scopes.last_mut() {
-            scope.insert("this".to_string(), true);
-        }
+        self.bind("this");
 
         for method in methods.iter() {
             match method {
                 Stmt::Func(name, args, body) => {
-                    let function_type = if name.lexeme == "init".to_string() {
+                    let function_type = if name.lexeme == "init" {
                         FunctionType::Initializer
                     } else {
                         FunctionType::Method
                     };
                     self.resolve_function(args.to_vec(), body, function_type)?;
                 }
-                _ => {
-                    return Err(Error {
-                        kind: "resolving error".to_string(),
-                        msg: "function only in class'es methods".to_string(),
-                    })
-                }
+                _ => unreachable!("function only in class'es methods"),
             }
         }
 
         self.end_scope();
 
-        if let Some(_) = superclass {
+        if superclass.is_some() {
             self.end_scope();
         }
 
         self.class_type = enclosing_class;
-        Ok(LoxValue::Nil)
+        Ok(Flow::Normal)
     }
 
     fn visit_super(
         &mut self,
         keyword: &Token,
         _method: &Token,
-        distance: Rc<Cell<i32>>,
-    ) -> Result<LoxValue, Error> {
+        slot: Rc<Cell<Slot>>,
+    ) -> Result<LoxValue, LoxError> {
         match self.class_type {
-            ClassType::None => {
-                return Err(Error {
-                    kind: "resolving error".to_string(),
-                    msg: "can't use 'super' outside of a class".to_string(),
-                })
-            }
+            ClassType::None => return Err(error(keyword, ResolveErrorKind::SuperOutsideClass)),
             ClassType::Class => {
-                return Err(Error {
-                    kind: "resolving error".to_string(),
-                    msg: "can't use 'super' in a class with no superclass".to_string(),
-                })
+                return Err(error(keyword, ResolveErrorKind::SuperWithoutSuperclass))
             }
             ClassType::Subclass => {}
         }
-        self.resolve_local(distance, keyword);
+        self.resolve_local(slot, keyword);
         Ok(LoxValue::Nil)
     }
 
     fn visit_var_expr(
         &mut self,
         token: &Token,
-        distance: Rc<Cell<i32>>,
-    ) -> Result<LoxValue, Error> {
+        slot: Rc<Cell<Slot>>,
+    ) -> Result<LoxValue, LoxError> {
         if let Some(scope) = self.scopes.last() {
-            if let Some(available) = scope.get(&token.lexeme) {
-                if !available {
-                    return Err(Error {
-                        kind: "resolving error".to_string(),
-                        msg: "can't read local variable in its own initializer".to_string(),
-                    });
+            if let Some(local) = scope.get(&token.lexeme) {
+                if !local.defined {
+                    return Err(error(
+                        token,
+                        ResolveErrorKind::ReadInOwnInitializer(token.lexeme.clone()),
+                    ));
                 }
             }
         }
-        self.resolve_local(distance, token);
+        self.resolve_local(slot, token);
         Ok(LoxValue::Nil)
     }
 
@@ -241,35 +270,45 @@ impl Visitor for Resolver {
         &mut self,
         left: &Token,
         right: &Expr,
-        distance: Rc<Cell<i32>>,
-    ) -> Result<LoxValue, Error> {
+        slot: Rc<Cell<Slot>>,
+    ) -> Result<LoxValue, LoxError> {
         self.resolve_expr(right)?;
-        self.resolve_local(distance, left);
+        self.resolve_local(slot, left);
         Ok(LoxValue::Nil)
     }
 
-    fn visit_binary(&mut self, left: &Expr, _op: &Token, right: &Expr) -> Result<LoxValue, Error> {
+    fn visit_binary(
+        &mut self,
+        left: &Expr,
+        _op: &Token,
+        right: &Expr,
+    ) -> Result<LoxValue, LoxError> {
         self.resolve_expr(left)?;
         self.resolve_expr(right)?;
         Ok(LoxValue::Nil)
     }
 
-    fn visit_grouping(&mut self, expr: &Expr) -> Result<LoxValue, Error> {
+    fn visit_grouping(&mut self, expr: &Expr) -> Result<LoxValue, LoxError> {
         self.resolve_expr(expr)?;
         Ok(LoxValue::Nil)
     }
 
-    fn visit_literal(&mut self, _lit: &crate::token::Literal) -> Result<LoxValue, Error> {
+    fn visit_literal(&mut self, _lit: &crate::token::Literal) -> Result<LoxValue, LoxError> {
         Ok(LoxValue::Nil)
     }
 
-    fn visit_logical(&mut self, left: &Expr, _op: &Token, right: &Expr) -> Result<LoxValue, Error> {
+    fn visit_logical(
+        &mut self,
+        left: &Expr,
+        _op: &Token,
+        right: &Expr,
+    ) -> Result<LoxValue, LoxError> {
         self.resolve_expr(left)?;
         self.resolve_expr(right)?;
         Ok(LoxValue::Nil)
     }
 
-    fn visit_unary(&mut self, _token: &Token, expr: &Expr) -> Result<LoxValue, Error> {
+    fn visit_unary(&mut self, _token: &Token, expr: &Expr) -> Result<LoxValue, LoxError> {
         self.resolve_expr(expr)?;
         Ok(LoxValue::Nil)
     }
@@ -279,7 +318,7 @@ impl Visitor for Resolver {
         callee: &Expr,
         _paren: &Token,
         args: Vec<Expr>,
-    ) -> Result<LoxValue, Error> {
+    ) -> Result<LoxValue, LoxError> {
         self.resolve_expr(callee)?;
         for arg in args.iter() {
             self.resolve_expr(arg)?;
@@ -287,14 +326,14 @@ impl Visitor for Resolver {
         Ok(LoxValue::Nil)
     }
 
-    fn visit_expr_stmt(&mut self, expr: &Expr) -> Result<LoxValue, Error> {
+    fn visit_expr_stmt(&mut self, expr: &Expr) -> Result<Flow, LoxError> {
         self.resolve_expr(expr)?;
-        Ok(LoxValue::Nil)
+        Ok(Flow::Normal)
     }
 
-    fn visit_print(&mut self, expr: &Expr) -> Result<LoxValue, Error> {
+    fn visit_print(&mut self, expr: &Expr) -> Result<Flow, LoxError> {
         self.resolve_expr(expr)?;
-        Ok(LoxValue::Nil)
+        Ok(Flow::Normal)
     }
 
     fn visit_func(
@@ -302,11 +341,11 @@ impl Visitor for Resolver {
         name: &Token,
         args: Vec<Token>,
         body: &Stmt,
-    ) -> Result<LoxValue, Error> {
+    ) -> Result<Flow, LoxError> {
         self.declare(name)?;
         self.define(name);
         self.resolve_function(args, body, FunctionType::Function)?;
-        Ok(LoxValue::Nil)
+        Ok(Flow::Normal)
     }
 
     fn visit_if(
@@ -314,74 +353,171 @@ impl Visitor for Resolver {
         cond: &Expr,
         then_branch: &Stmt,
         else_branch: Option<&Stmt>,
-    ) -> Result<LoxValue, Error> {
+    ) -> Result<Flow, LoxError> {
         self.resolve_expr(cond)?;
         self.resolve_stmt(then_branch)?;
         if let Some(else_branch) = else_branch {
             self.resolve_stmt(else_branch)?;
         }
-        Ok(LoxValue::Nil)
+        Ok(Flow::Normal)
     }
 
-    fn visit_return(&mut self, keyword: &Token, value: Option<&Expr>) -> Result<LoxValue, Error> {
+    fn visit_return(&mut self, keyword: &Token, value: Option<&Expr>) -> Result<Flow, LoxError> {
         if let FunctionType::None = self.functoin_type {
-            return Err(Error {
-                kind: "resolving error".to_string(),
-                msg: format!("can't return from top-level code\nline: {}", keyword.line),
-            });
+            return Err(error(keyword, ResolveErrorKind::ReturnAtTopLevel));
         }
 
         if let Some(value) = value {
-            match self.functoin_type {
-                FunctionType::Initializer => {
-                    return Err(Error {
-                        kind: "resolving error".to_string(),
-                        msg: "can't return a value from an initializer".to_string(),
-                    })
-                }
-                _ => {}
+            if let FunctionType::Initializer = self.functoin_type {
+                return Err(error(keyword, ResolveErrorKind::ReturnFromInitializer));
             }
             self.resolve_expr(value)?;
         }
-        Ok(LoxValue::Nil)
+        Ok(Flow::Normal)
     }
 
-    fn visit_while(&mut self, cond: &Expr, body: &Stmt) -> Result<LoxValue, Error> {
+    fn visit_while(
+        &mut self,
+        cond: &Expr,
+        body: &Stmt,
+        inc: Option<&Expr>,
+    ) -> Result<Flow, LoxError> {
         self.resolve_expr(cond)?;
+        self.loop_depth += 1;
+        let resolved = self.resolve_stmt(body);
+        self.loop_depth -= 1;
+        resolved?;
+        if let Some(inc) = inc {
+            self.resolve_expr(inc)?;
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn visit_import(&mut self, _path: &Token, _names: Option<&[Token]>) -> Result<Flow, LoxError> {
+        Ok(Flow::Normal)
+    }
+
+    fn visit_throw(&mut self, _keyword: &Token, value: &Expr) -> Result<Flow, LoxError> {
+        self.resolve_expr(value)?;
+        Ok(Flow::Normal)
+    }
+
+    fn visit_try(
+        &mut self,
+        body: &Stmt,
+        catch: Option<(&Token, &Stmt)>,
+        finally: Option<&Stmt>,
+    ) -> Result<Flow, LoxError> {
         self.resolve_stmt(body)?;
-        Ok(LoxValue::Nil)
+        if let Some((name, handler)) = catch {
+            self.begin_scope();
+            self.declare(name)?;
+            self.define(name);
+            self.resolve_stmt(handler)?;
+            self.end_scope();
+        }
+        if let Some(finally) = finally {
+            self.resolve_stmt(finally)?;
+        }
+        Ok(Flow::Normal)
     }
 
-    fn visit_expr(&mut self, expr: &Expr) -> Result<LoxValue, Error> {
+    fn visit_break(&mut self, keyword: &Token) -> Result<Flow, LoxError> {
+        if self.loop_depth == 0 {
+            return Err(error(
+                keyword,
+                ResolveErrorKind::OutsideLoop(keyword.lexeme.clone()),
+            ));
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn visit_continue(&mut self, keyword: &Token) -> Result<Flow, LoxError> {
+        self.visit_break(keyword)
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) -> Result<LoxValue, LoxError> {
         walk_expr(self, expr)
     }
 
-    fn visit_stmt(&mut self, stmt: &Stmt) -> Result<LoxValue, Error> {
+    fn visit_stmt(&mut self, stmt: &Stmt) -> Result<Flow, LoxError> {
         walk_stmt(self, stmt)
     }
 
-    fn visit_get(&mut self, expr: &Expr, _name: &Token) -> Result<LoxValue, Error> {
+    fn visit_get(&mut self, expr: &Expr, _name: &Token) -> Result<LoxValue, LoxError> {
+        self.resolve_expr(expr)?;
+        Ok(LoxValue::Nil)
+    }
+
+    fn visit_set(
+        &mut self,
+        expr: &Expr,
+        _name: &Token,
+        value: &Expr,
+    ) -> Result<LoxValue, LoxError> {
+        self.resolve_expr(expr)?;
+        self.resolve_expr(value)?;
+        Ok(LoxValue::Nil)
+    }
+
+    fn visit_lambda(
+        &mut self,
+        _name: &Token,
+        args: &[Token],
+        body: &Stmt,
+    ) -> Result<LoxValue, LoxError> {
+        self.resolve_function(args.to_vec(), body, FunctionType::Function)
+    }
+
+    fn visit_list(&mut self, _bracket: &Token, items: &[Expr]) -> Result<LoxValue, LoxError> {
+        for item in items.iter() {
+            self.resolve_expr(item)?;
+        }
+        Ok(LoxValue::Nil)
+    }
+
+    fn visit_map(
+        &mut self,
+        _brace: &Token,
+        entries: &[(Expr, Expr)],
+    ) -> Result<LoxValue, LoxError> {
+        for (key, value) in entries.iter() {
+            self.resolve_expr(key)?;
+            self.resolve_expr(value)?;
+        }
+        Ok(LoxValue::Nil)
+    }
+
+    fn visit_index(
+        &mut self,
+        expr: &Expr,
+        _bracket: &Token,
+        index: &Expr,
+    ) -> Result<LoxValue, LoxError> {
         self.resolve_expr(expr)?;
+        self.resolve_expr(index)?;
         Ok(LoxValue::Nil)
     }
 
-    fn visit_set(&mut self, expr: &Expr, _name: &Token, value: &Expr) -> Result<LoxValue, Error> {
+    fn visit_set_index(
+        &mut self,
+        expr: &Expr,
+        _bracket: &Token,
+        index: &Expr,
+        value: &Expr,
+    ) -> Result<LoxValue, LoxError> {
         self.resolve_expr(expr)?;
+        self.resolve_expr(index)?;
         self.resolve_expr(value)?;
         Ok(LoxValue::Nil)
     }
 
-    fn visit_this(&mut self, token: &Token, distance: Rc<Cell<i32>>) -> Result<LoxValue, Error> {
+    fn visit_this(&mut self, token: &Token, slot: Rc<Cell<Slot>>) -> Result<LoxValue, LoxError> {
         match self.class_type {
             ClassType::Class | ClassType::Subclass => {}
-            ClassType::None => {
-                return Err(Error {
-                    kind: "resolving error".to_string(),
-                    msg: "can't use 'this' outside of a class".to_string(),
-                })
-            }
+            ClassType::None => return Err(error(token, ResolveErrorKind::ThisOutsideClass)),
         }
-        self.resolve_local(distance, token);
+        self.resolve_local(slot, token);
         Ok(LoxValue::Nil)
     }
 }
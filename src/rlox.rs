@@ -1,58 +1,30 @@
 use anyhow::{Context, Result};
 use std::fs::read_to_string;
-use std::io::stdin;
 
-use crate::interpreter::Interpreter;
-use crate::parser::Parser;
-use crate::resolver::Resolver;
-use crate::scanner::Scanner;
+use crate::lox::{Backend, Lox};
+use crate::repl;
 
-pub fn run_file(path: String) -> Result<()> {
-    let mut interpreter = Interpreter::new();
+pub fn run_file(path: String, backend: Backend, search_path: &[String]) -> Result<()> {
+    let mut lox = Lox::with_backend(backend);
+    for dir in search_path {
+        lox.add_search_path(dir);
+    }
     let s =
         read_to_string(path.clone()).with_context(|| format!("couldn't read file `{}`", path))?;
-    run(&mut interpreter, s);
-
-    Ok(())
-}
-
-pub fn run_prompt() -> Result<()> {
-    let mut interpreter = Interpreter::new();
-    loop {
-        let mut s = String::new();
-        match stdin().read_line(&mut s).ok() {
-            Some(_) => {}
-            None => break,
-        }
-        run(&mut interpreter, s);
+    if let Err(err) = lox.run_source(&path, &s) {
+        lox.report(&err);
+        return Err(err.into());
     }
 
     Ok(())
 }
 
-fn run(interpreter: &mut Interpreter, s: String) {
-    let mut scanner = Scanner {
-        source: s.chars().collect(),
-        ..Default::default()
-    };
-    let tokens = scanner.scan_tokens();
-    let mut parser = Parser {
-        tokens: tokens,
-        current: 0,
-    };
-    let stmts = parser.parse();
-
-    let mut resolver = Resolver::new();
-    match resolver.resolve_stmts(stmts.clone()) {
-        Ok(_) => {}
-        Err(err) => {
-            println!("{:?}", err);
-            return;
-        }
+pub fn run_prompt(backend: Backend, search_path: &[String]) -> Result<()> {
+    let mut lox = Lox::with_backend(backend);
+    for dir in search_path {
+        lox.add_search_path(dir);
     }
+    repl::run(&mut lox)?;
 
-    match interpreter.interpret(stmts) {
-        Ok(_) => {}
-        Err(err) => println!("{:?}", err),
-    }
+    Ok(())
 }
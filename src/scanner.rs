@@ -1,25 +1,33 @@
+use crate::error::{LexError, LexErrorKind};
 use crate::token::TokenType::*;
-use crate::token::{Literal, Token, TokenType};
+use crate::token::{Literal, Span, Token, TokenType};
 use lazy_static::lazy_static;
 use std::collections::HashMap;
 
 lazy_static! {
-    static ref KEYWORDS: HashMap<&'static str, TokenType> = {
+    pub static ref KEYWORDS: HashMap<&'static str, TokenType> = {
         let mut map = HashMap::new();
         map.insert("and", And);
+        map.insert("break", Break);
+        map.insert("catch", Catch);
         map.insert("class", Class);
+        map.insert("continue", Continue);
         map.insert("else", Else);
         map.insert("false", False);
+        map.insert("finally", Finally);
         map.insert("for", For);
         map.insert("fun", Fun);
         map.insert("if", If);
+        map.insert("import", Import);
         map.insert("nil", Nil);
         map.insert("or", Or);
         map.insert("print", Print);
         map.insert("return", Return);
         map.insert("super", Super);
         map.insert("this", This);
+        map.insert("throw", Throw);
         map.insert("true", True);
+        map.insert("try", Try);
         map.insert("var", Var);
         map.insert("while", While);
         map
@@ -28,37 +36,57 @@ lazy_static! {
 
 pub struct Scanner {
     pub source: Vec<char>,
+    pub file_id: usize,
     pub tokens: Vec<Token>,
     pub start: usize,
     pub current: usize,
+    pub start_byte: usize,
+    pub current_byte: usize,
+    pub start_line: usize,
+    pub start_column: usize,
     pub line: usize,
+    pub line_start: usize,
+    pub errors: Vec<LexError>,
 }
 
 impl Default for Scanner {
     fn default() -> Scanner {
         Scanner {
             source: Vec::new(),
+            file_id: 0,
             tokens: Vec::new(),
             start: 0,
             current: 0,
+            start_byte: 0,
+            current_byte: 0,
+            start_line: 1,
+            start_column: 1,
             line: 1,
+            line_start: 0,
+            errors: Vec::new(),
         }
     }
 }
 
 impl Scanner {
-    pub fn scan_tokens(&mut self) -> Vec<Token> {
+    pub fn scan_tokens(&mut self) -> Result<Vec<Token>, Vec<LexError>> {
         while !self.is_at_end() {
-            self.start = self.current;
+            self.begin_token();
             self.scan_token();
         }
+        self.begin_token();
         self.tokens.push(Token {
             token_type: TokenType::Eof,
             lexeme: "".to_string(),
             lit: None,
-            line: self.line,
+            span: self.span(),
         });
-        self.tokens.clone()
+
+        if self.errors.is_empty() {
+            Ok(self.tokens.clone())
+        } else {
+            Err(self.errors.clone())
+        }
     }
 
     fn scan_token(&mut self) {
@@ -68,6 +96,9 @@ impl Scanner {
             ')' => self.add_token(RightParen, None),
             '{' => self.add_token(LeftBrace, None),
             '}' => self.add_token(RightBrace, None),
+            '[' => self.add_token(LeftBracket, None),
+            ']' => self.add_token(RightBracket, None),
+            ':' => self.add_token(Colon, None),
             ',' => self.add_token(Comma, None),
             '.' => self.add_token(Dot, None),
             '-' => self.add_token(Minus, None),
@@ -81,6 +112,8 @@ impl Scanner {
             '=' => {
                 let token_type = if self.is_match('=') {
                     EqualEqual
+                } else if self.is_match('>') {
+                    Arrow
                 } else {
                     Equal
                 };
@@ -108,7 +141,7 @@ impl Scanner {
                 }
             }
             ' ' | '\r' | '\t' => {}
-            '\n' => self.line += 1,
+            '\n' => self.newline(),
             '"' => self.string(),
             _ => {
                 if is_digit(c) {
@@ -116,38 +149,65 @@ impl Scanner {
                 } else if is_alpha(c) {
                     self.identifier();
                 } else {
-                    panic!("unexpected character");
+                    self.error(LexErrorKind::UnexpectedCharacter(c));
                 }
             }
         }
     }
 
     fn advance(&mut self) -> char {
+        let c = self.source[self.current];
         self.current += 1;
-        self.source[self.current - 1]
+        self.current_byte += c.len_utf8();
+        c
     }
 
     fn add_token(&mut self, token_type: TokenType, lit: Option<Literal>) {
         let token = Token {
-            token_type: token_type,
+            token_type,
             lexeme: self.source[self.start..self.current].iter().collect(),
-            lit: lit,
-            line: self.line,
+            lit,
+            span: self.span(),
         };
         self.tokens.push(token);
     }
 
+    fn begin_token(&mut self) {
+        self.start = self.current;
+        self.start_byte = self.current_byte;
+        self.start_line = self.line;
+        self.start_column = self.current - self.line_start + 1;
+    }
+
+    fn span(&self) -> Span {
+        Span {
+            file_id: self.file_id,
+            start: self.start_byte,
+            end: self.current_byte,
+            line: self.start_line,
+            column: self.start_column,
+        }
+    }
+
+    fn error(&mut self, kind: LexErrorKind) {
+        let span = self.span();
+        self.errors.push(LexError { kind, span });
+    }
+
+    fn newline(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
+    }
+
     fn is_at_end(&self) -> bool {
         self.current >= self.source.len()
     }
 
     fn is_match(&mut self, expected: char) -> bool {
-        if self.is_at_end() {
-            false
-        } else if self.source[self.current] != expected {
+        if self.is_at_end() || self.source[self.current] != expected {
             false
         } else {
-            self.current += 1;
+            self.advance();
             true
         }
     }
@@ -170,14 +230,14 @@ impl Scanner {
 
     fn string(&mut self) {
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
+            if self.advance() == '\n' {
+                self.newline();
             }
-            self.advance();
         }
 
         if self.is_at_end() {
-            panic!("unterminated string")
+            self.error(LexErrorKind::UnterminatedString);
+            return;
         }
 
         // The closing "
@@ -203,12 +263,11 @@ impl Scanner {
             }
         }
 
-        let n: f64 = self.source[self.start..self.current]
-            .iter()
-            .collect::<String>()
-            .parse()
-            .unwrap();
-        self.add_token(Number, Some(Literal::Number(n)));
+        let text: String = self.source[self.start..self.current].iter().collect();
+        match text.parse() {
+            Ok(n) => self.add_token(Number, Some(Literal::Number(n))),
+            Err(_) => self.error(LexErrorKind::InvalidNumber(text)),
+        }
     }
 
     fn identifier(&mut self) {
@@ -222,11 +281,11 @@ impl Scanner {
 }
 
 fn is_digit(c: char) -> bool {
-    c >= '0' && c <= '9'
+    c.is_ascii_digit()
 }
 
 fn is_alpha(c: char) -> bool {
-    (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z') || c == '_'
+    c.is_ascii_alphabetic() || c == '_'
 }
 
 fn is_alpha_number(c: char) -> bool {
@@ -234,6 +293,7 @@ fn is_alpha_number(c: char) -> bool {
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::redundant_field_names)]
 mod tests {
     use super::*;
 
@@ -352,7 +412,7 @@ mod tests {
             source: s.chars().collect(),
             ..Default::default()
         };
-        let tokens = scanner.scan_tokens();
+        let tokens = scanner.scan_tokens().unwrap();
         assert_eq!(tokens[0].token_type, TokenType::Number);
         assert_eq!(tokens[1].token_type, TokenType::Plus);
         assert_eq!(tokens[2].token_type, TokenType::Number);
@@ -365,7 +425,7 @@ mod tests {
             source: s.chars().collect(),
             ..Default::default()
         };
-        let tokens = scanner.scan_tokens();
+        let tokens = scanner.scan_tokens().unwrap();
         assert_eq!(tokens[0].token_type, TokenType::Print);
         assert_eq!(tokens[1].token_type, TokenType::Str);
         assert_eq!(tokens[2].token_type, TokenType::Semicolon);
@@ -378,7 +438,7 @@ mod tests {
             source: s.chars().collect(),
             ..Default::default()
         };
-        let tokens = scanner.scan_tokens();
+        let tokens = scanner.scan_tokens().unwrap();
         assert_eq!(tokens[0].token_type, TokenType::Print);
         assert_eq!(tokens[1].token_type, TokenType::True);
         assert_eq!(tokens[2].token_type, TokenType::Semicolon);
@@ -391,11 +451,104 @@ mod tests {
             source: s.chars().collect(),
             ..Default::default()
         };
-        let tokens = scanner.scan_tokens();
+        let tokens = scanner.scan_tokens().unwrap();
         assert_eq!(tokens[0].token_type, TokenType::Print);
         assert_eq!(tokens[1].token_type, TokenType::Number);
         assert_eq!(tokens[2].token_type, TokenType::Plus);
         assert_eq!(tokens[3].token_type, TokenType::Number);
         assert_eq!(tokens[4].token_type, TokenType::Semicolon);
     }
+
+    #[test]
+    fn test_unexpected_character() {
+        let s = "var a = 1;\n  @ # print a;";
+        let mut scanner = Scanner {
+            source: s.chars().collect(),
+            ..Default::default()
+        };
+        let errors = scanner.scan_tokens().unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                LexError {
+                    span: Span {
+                        file_id: 0,
+                        start: 13,
+                        end: 14,
+                        line: 2,
+                        column: 3,
+                    },
+                    kind: LexErrorKind::UnexpectedCharacter('@'),
+                },
+                LexError {
+                    span: Span {
+                        file_id: 0,
+                        start: 15,
+                        end: 16,
+                        line: 2,
+                        column: 5,
+                    },
+                    kind: LexErrorKind::UnexpectedCharacter('#'),
+                },
+            ]
+        );
+        assert_eq!(scanner.tokens.last().unwrap().token_type, TokenType::Eof);
+        assert_eq!(scanner.tokens[5].token_type, TokenType::Print);
+    }
+
+    #[test]
+    fn test_unterminated_string() {
+        let s = "print 1;\nprint \"oops\n;";
+        let mut scanner = Scanner {
+            source: s.chars().collect(),
+            ..Default::default()
+        };
+        let errors = scanner.scan_tokens().unwrap_err();
+        assert_eq!(
+            errors,
+            vec![LexError {
+                span: Span {
+                    file_id: 0,
+                    start: 15,
+                    end: 22,
+                    line: 2,
+                    column: 7,
+                },
+                kind: LexErrorKind::UnterminatedString,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_span() {
+        let s = "var a = \"ü\";\n  x";
+        let mut scanner = Scanner {
+            source: s.chars().collect(),
+            file_id: 3,
+            ..Default::default()
+        };
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(
+            tokens[3].span,
+            Span {
+                file_id: 3,
+                start: 8,
+                end: 12,
+                line: 1,
+                column: 9,
+            }
+        );
+        assert_eq!(&s[tokens[3].span.start..tokens[3].span.end], "\"ü\"");
+        assert_eq!(tokens[4].span.column, 12);
+        assert_eq!(
+            tokens[5].span,
+            Span {
+                file_id: 3,
+                start: 16,
+                end: 17,
+                line: 2,
+                column: 3,
+            }
+        );
+    }
 }
@@ -0,0 +1,26 @@
+use super::{argument_type, Function};
+use crate::error::LoxError;
+use crate::gc::Heap;
+use crate::lox_value::LoxValue;
+
+pub const NATIVES: &[(&str, usize, Function)] =
+    &[("str", 1, to_str), ("num", 1, to_num), ("type", 1, type_of)];
+
+// The value as `print` writes it.
+fn to_str(_heap: &mut Heap, args: &[LoxValue]) -> Result<LoxValue, LoxError> {
+    Ok(LoxValue::Str(args[0].to_string()))
+}
+
+// Parses a string, ignoring surrounding whitespace. Strings that aren't
+// numbers give nil.
+fn to_num(_heap: &mut Heap, args: &[LoxValue]) -> Result<LoxValue, LoxError> {
+    match &args[0] {
+        LoxValue::Number(n) => Ok(LoxValue::Number(*n)),
+        LoxValue::Str(s) => Ok(s.trim().parse().map_or(LoxValue::Nil, LoxValue::Number)),
+        arg => Err(argument_type("num", 0, "a number or string", arg)),
+    }
+}
+
+fn type_of(_heap: &mut Heap, args: &[LoxValue]) -> Result<LoxValue, LoxError> {
+    Ok(LoxValue::Str(args[0].type_name().to_string()))
+}
@@ -0,0 +1,81 @@
+use std::io::{stdin, Write};
+use std::rc::Rc;
+
+use super::string;
+use crate::error::{LoxError, RuntimeErrorKind};
+use crate::gc::Heap;
+use crate::lox_value::LoxValue;
+use crate::native_fn::{NativeFn, Streams};
+
+// `input` and `readLine`, which read from the streams of the engine that
+// defines them rather than from the process's.
+pub fn natives(streams: &Streams) -> Vec<NativeFn> {
+    let (prompted, plain) = (streams.clone(), streams.clone());
+    vec![
+        NativeFn {
+            name: "input".to_string(),
+            arity: 1,
+            function: Rc::new(move |_heap: &mut Heap, args: &[LoxValue]| input(&prompted, args)),
+        },
+        NativeFn {
+            name: "readLine".to_string(),
+            arity: 0,
+            function: Rc::new(move |_heap: &mut Heap, _args: &[LoxValue]| read_line(&plain)),
+        },
+    ]
+}
+
+// Writes the prompt, then reads a line like `readLine`.
+fn input(streams: &Streams, args: &[LoxValue]) -> Result<LoxValue, LoxError> {
+    let prompt = string("input", args, 0)?;
+    let mut output = streams.output.borrow_mut();
+    write!(output, "{}", prompt)
+        .and_then(|_| output.flush())
+        .map_err(|err| LoxError::runtime(RuntimeErrorKind::Output(err.to_string())))?;
+    drop(output);
+    read_line(streams)
+}
+
+// A line of input without its line ending, or nil at the end of the input.
+fn read_line(streams: &Streams) -> Result<LoxValue, LoxError> {
+    let mut line = String::new();
+    let read = match &mut *streams.input.borrow_mut() {
+        Some(input) => input.read_line(&mut line),
+        None => stdin().read_line(&mut line),
+    };
+    match read {
+        Ok(0) | Err(_) => Ok(LoxValue::Nil),
+        Ok(_) => {
+            let len = line.trim_end_matches(&['\n', '\r'][..]).len();
+            line.truncate(len);
+            Ok(LoxValue::Str(line))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lox_value::LoxValue;
+    use crate::test_support::{on_each_backend, Captured, Closed};
+
+    #[test]
+    fn test_input_uses_streams() {
+        on_each_backend(|mut lox, backend| {
+            let output = Captured::default();
+            lox.set_output(Box::new(output.clone()));
+            lox.set_input(Box::new(std::io::Cursor::new("Ada\r\nrest\n")));
+
+            let value = lox
+                .run("var name = input(\"name? \"); print \"hi \" + name; readLine();")
+                .unwrap();
+            assert_eq!(value, LoxValue::Str("rest".to_string()));
+            assert_eq!(output.contents(), "name? hi Ada\n");
+            assert_eq!(lox.run("readLine();").unwrap(), LoxValue::Nil);
+
+            // A prompt that can't be written fails like `print`.
+            lox.set_output(Box::new(Closed));
+            let err = lox.run("input(\"> \");").unwrap_err();
+            assert_eq!(err.code(), "E0418", "{:?}", backend);
+        });
+    }
+}
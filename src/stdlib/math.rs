@@ -0,0 +1,40 @@
+use super::{number, Function};
+use crate::error::LoxError;
+use crate::gc::Heap;
+use crate::lox_value::LoxValue;
+
+pub const NATIVES: &[(&str, usize, Function)] = &[
+    ("sqrt", 1, sqrt),
+    ("floor", 1, floor),
+    ("pow", 2, pow),
+    ("abs", 1, abs),
+    ("min", 2, min),
+    ("max", 2, max),
+];
+
+fn sqrt(_heap: &mut Heap, args: &[LoxValue]) -> Result<LoxValue, LoxError> {
+    Ok(LoxValue::Number(number("sqrt", args, 0)?.sqrt()))
+}
+
+fn floor(_heap: &mut Heap, args: &[LoxValue]) -> Result<LoxValue, LoxError> {
+    Ok(LoxValue::Number(number("floor", args, 0)?.floor()))
+}
+
+fn pow(_heap: &mut Heap, args: &[LoxValue]) -> Result<LoxValue, LoxError> {
+    let base = number("pow", args, 0)?;
+    Ok(LoxValue::Number(base.powf(number("pow", args, 1)?)))
+}
+
+fn abs(_heap: &mut Heap, args: &[LoxValue]) -> Result<LoxValue, LoxError> {
+    Ok(LoxValue::Number(number("abs", args, 0)?.abs()))
+}
+
+fn min(_heap: &mut Heap, args: &[LoxValue]) -> Result<LoxValue, LoxError> {
+    let a = number("min", args, 0)?;
+    Ok(LoxValue::Number(a.min(number("min", args, 1)?)))
+}
+
+fn max(_heap: &mut Heap, args: &[LoxValue]) -> Result<LoxValue, LoxError> {
+    let a = number("max", args, 0)?;
+    Ok(LoxValue::Number(a.max(number("max", args, 1)?)))
+}
@@ -0,0 +1,149 @@
+// Native functions every module starts out with, one group per file.
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::error::{LoxError, RuntimeErrorKind};
+use crate::gc::Heap;
+use crate::lox_value::LoxValue;
+use crate::native_fn::{NativeFn, Streams};
+
+mod convert;
+mod io;
+mod math;
+mod string;
+mod time;
+
+type Function = fn(&mut Heap, &[LoxValue]) -> Result<LoxValue, LoxError>;
+
+// Defines every native of the standard library in `globals`. The I/O
+// natives use `streams`.
+pub fn register_stdlib(globals: &mut HashMap<String, LoxValue>, streams: &Streams) {
+    let groups: [&[(&str, usize, Function)]; 4] = [
+        math::NATIVES,
+        string::NATIVES,
+        convert::NATIVES,
+        time::NATIVES,
+    ];
+    for &(name, arity, function) in groups.iter().flat_map(|group| group.iter()) {
+        let native = NativeFn {
+            name: name.to_string(),
+            arity,
+            function: Rc::new(function),
+        };
+        globals.insert(name.to_string(), LoxValue::Native(Rc::new(native)));
+    }
+    for native in io::natives(streams) {
+        globals.insert(native.name.clone(), LoxValue::Native(Rc::new(native)));
+    }
+}
+
+fn argument_type(callee: &str, index: usize, expected: &str, actual: &LoxValue) -> LoxError {
+    LoxError::runtime(RuntimeErrorKind::ArgumentType {
+        callee: callee.to_string(),
+        index: index + 1,
+        expected: expected.to_string(),
+        actual: actual.type_name(),
+    })
+}
+
+// Argument `index` of `callee`, which must be a number.
+fn number(callee: &str, args: &[LoxValue], index: usize) -> Result<f64, LoxError> {
+    match &args[index] {
+        LoxValue::Number(n) => Ok(*n),
+        arg => Err(argument_type(callee, index, "a number", arg)),
+    }
+}
+
+// Argument `index` of `callee`, which must be a whole number.
+fn integer(callee: &str, args: &[LoxValue], index: usize) -> Result<i64, LoxError> {
+    match &args[index] {
+        LoxValue::Number(n) if n.fract() == 0.0 => Ok(*n as i64),
+        arg => Err(argument_type(callee, index, "an integer", arg)),
+    }
+}
+
+// Argument `index` of `callee`, which must be a string.
+fn string<'a>(callee: &str, args: &'a [LoxValue], index: usize) -> Result<&'a str, LoxError> {
+    match &args[index] {
+        LoxValue::Str(s) => Ok(s),
+        arg => Err(argument_type(callee, index, "a string", arg)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::on_each_backend;
+
+    #[test]
+    fn test_stdlib() {
+        let programs = [
+            ("sqrt(16) + floor(-1.5) + pow(2, 10) + abs(-3);", "1029"),
+            ("min(3, -1) * max(3, -1);", "-3"),
+            ("len(\"héllo\") + len([1, 2]) + len({});", "7"),
+            (
+                "substr(\"héllo\", 1, 3) + substr(\"abc\", -5, 99) + substr(\"abc\", 2, 1);",
+                "élabc",
+            ),
+            ("upper(\"abc\") + lower(\"DEF\");", "ABCdef"),
+            ("split(\"a,b,,c\", \",\");", "[\"a\", \"b\", \"\", \"c\"]"),
+            ("split(\"ab\", \"\");", "[\"a\", \"b\"]"),
+            (
+                "[indexOf(\"héllo\", \"l\"), indexOf(\"abc\", \"z\")];",
+                "[2, -1]",
+            ),
+            ("str(1.5) + str(nil) + str([true]);", "1.5nil[true]"),
+            ("[num(\" 42 \"), num(\"4x\"), num(7)];", "[42, nil, 7]"),
+            (
+                "[type(1), type(\"\"), type(clock), type(Error), type({})];",
+                "[\"number\", \"string\", \"function\", \"class\", \"map\"]",
+            ),
+        ];
+        for (program, expected) in programs.iter() {
+            on_each_backend(|mut lox, backend| {
+                let value = lox.run(program).unwrap();
+                assert_eq!(value.to_string(), *expected, "{} ({:?})", program, backend);
+            });
+        }
+    }
+
+    #[test]
+    fn test_stdlib_argument_errors() {
+        let programs = [
+            (
+                "sqrt(\"4\");",
+                "argument 1 of `sqrt` must be a number, got string",
+            ),
+            (
+                "pow(2, nil);",
+                "argument 2 of `pow` must be a number, got nil",
+            ),
+            (
+                "len(1);",
+                "argument 1 of `len` must be a string, list or map, got number",
+            ),
+            (
+                "substr(\"abc\", 0.5, 1);",
+                "argument 2 of `substr` must be an integer, got number",
+            ),
+            (
+                "split(\"a\", 1);",
+                "argument 2 of `split` must be a string, got number",
+            ),
+            (
+                "num(true);",
+                "argument 1 of `num` must be a number or string, got bool",
+            ),
+        ];
+        for (program, message) in programs.iter() {
+            on_each_backend(|mut lox, backend| {
+                let err = lox.run(program).unwrap_err();
+                assert_eq!(err.code(), "E0415", "{} ({:?})", program, backend);
+                let err = err.to_string();
+                assert_eq!(
+                    err.lines().next().unwrap(),
+                    format!("[line 1] error: {}", message)
+                );
+            });
+        }
+    }
+}
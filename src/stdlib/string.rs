@@ -0,0 +1,73 @@
+use super::{argument_type, integer, string, Function};
+use crate::error::LoxError;
+use crate::gc::Heap;
+use crate::lox_value::LoxValue;
+
+// Positions and lengths count characters, not bytes.
+pub const NATIVES: &[(&str, usize, Function)] = &[
+    ("len", 1, len),
+    ("substr", 3, substr),
+    ("upper", 1, upper),
+    ("lower", 1, lower),
+    ("split", 2, split),
+    ("indexOf", 2, index_of),
+];
+
+// Also takes lists and maps, like their `len` methods.
+fn len(_heap: &mut Heap, args: &[LoxValue]) -> Result<LoxValue, LoxError> {
+    let len = match &args[0] {
+        LoxValue::Str(s) => s.chars().count(),
+        LoxValue::List(list) => list.borrow().len(),
+        LoxValue::Map(map) => map.borrow().len(),
+        arg => return Err(argument_type("len", 0, "a string, list or map", arg)),
+    };
+    Ok(LoxValue::Number(len as f64))
+}
+
+// The characters from `start` up to but not including `end`. Both are
+// clamped to the string, so the result may be shorter or empty.
+fn substr(_heap: &mut Heap, args: &[LoxValue]) -> Result<LoxValue, LoxError> {
+    let s = string("substr", args, 0)?;
+    let len = s.chars().count() as i64;
+    let start = integer("substr", args, 1)?.clamp(0, len);
+    let end = integer("substr", args, 2)?.clamp(start, len);
+    Ok(LoxValue::Str(
+        s.chars()
+            .skip(start as usize)
+            .take((end - start) as usize)
+            .collect(),
+    ))
+}
+
+fn upper(_heap: &mut Heap, args: &[LoxValue]) -> Result<LoxValue, LoxError> {
+    Ok(LoxValue::Str(string("upper", args, 0)?.to_uppercase()))
+}
+
+fn lower(_heap: &mut Heap, args: &[LoxValue]) -> Result<LoxValue, LoxError> {
+    Ok(LoxValue::Str(string("lower", args, 0)?.to_lowercase()))
+}
+
+// Splitting on "" gives the characters.
+fn split(heap: &mut Heap, args: &[LoxValue]) -> Result<LoxValue, LoxError> {
+    let s = string("split", args, 0)?;
+    let separator = string("split", args, 1)?;
+    let parts = if separator.is_empty() {
+        s.chars().map(|c| LoxValue::Str(c.to_string())).collect()
+    } else {
+        s.split(separator)
+            .map(|part| LoxValue::Str(part.to_string()))
+            .collect()
+    };
+    Ok(LoxValue::List(heap.list(parts)))
+}
+
+// Where `needle` first occurs in the string, or -1.
+fn index_of(_heap: &mut Heap, args: &[LoxValue]) -> Result<LoxValue, LoxError> {
+    let s = string("indexOf", args, 0)?;
+    let needle = string("indexOf", args, 1)?;
+    let index = match s.find(needle) {
+        Some(byte) => s[..byte].chars().count() as f64,
+        None => -1.0,
+    };
+    Ok(LoxValue::Number(index))
+}
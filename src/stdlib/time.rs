@@ -0,0 +1,12 @@
+use std::time::SystemTime;
+
+use super::Function;
+use crate::error::LoxError;
+use crate::gc::Heap;
+use crate::lox_value::LoxValue;
+
+pub const NATIVES: &[(&str, usize, Function)] = &[("clock", 0, clock)];
+
+fn clock(_heap: &mut Heap, _args: &[LoxValue]) -> Result<LoxValue, LoxError> {
+    Ok(LoxValue::Time(SystemTime::now()))
+}
@@ -1,45 +1,70 @@
-use crate::error::Error;
+use crate::error::LoxError;
 use crate::expr::Expr;
 use crate::lox_value::LoxValue;
 use crate::token::Token;
 use crate::visitor::Visitor;
 
 #[allow(dead_code)]
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Stmt {
     Block(Vec<Stmt>),
-    Class(Token, Option<Expr>, Box<Vec<Stmt>>),
+    Break(Token),
+    Class(Token, Option<Expr>, Vec<Stmt>),
+    Continue(Token),
     Expr(Expr),
     Func(Token, Vec<Token>, Box<Stmt>),
     If(Expr, Box<Stmt>, Option<Box<Stmt>>),
+    // The path string, then the names to import if the module's other
+    // exports are left out.
+    Import(Token, Option<Vec<Token>>),
     Print(Expr),
     Return(Token, Option<Expr>),
+    Throw(Token, Expr),
+    // The body, then the `catch` clause's variable and block, then the
+    // `finally` block. At least one of the clauses is present.
+    Try(Box<Stmt>, Option<(Token, Box<Stmt>)>, Option<Box<Stmt>>),
     Var(Token, Option<Expr>),
-    While(Expr, Box<Stmt>),
+    // The expression is the increment of a desugared `for`, which runs after
+    // the body even when the body is left with `continue`.
+    While(Expr, Box<Stmt>, Option<Expr>),
 }
 
-pub fn walk_stmt<V: Visitor + ?Sized>(visitor: &mut V, stmt: &Stmt) -> Result<LoxValue, Error> {
+// How a statement finished: by running off its end or by unwinding to an
+// enclosing construct. Kept apart from `LoxValue` so control flow can never
+// escape into a user-visible value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Flow {
+    Normal,
+    Return(LoxValue),
+    Break,
+    Continue,
+}
+
+pub fn walk_stmt<V: Visitor + ?Sized>(visitor: &mut V, stmt: &Stmt) -> Result<Flow, LoxError> {
     match stmt {
         Stmt::Block(stmts) => visitor.visit_block(stmts.to_vec()),
+        Stmt::Break(keyword) => visitor.visit_break(keyword),
         Stmt::Class(name, superclass, methods) => {
-            let superclass = match superclass {
-                Some(v) => Some(v.clone()),
-                None => None,
-            };
-            visitor.visit_class(name, superclass, methods.to_vec())
+            visitor.visit_class(name, superclass.clone(), methods.to_vec())
         }
+        Stmt::Continue(keyword) => visitor.visit_continue(keyword),
         Stmt::Expr(expr) => visitor.visit_expr_stmt(expr),
         Stmt::Func(name, args, body) => visitor.visit_func(name, args.to_vec(), body),
         Stmt::If(cond, then_branch, else_branch) => {
-            let else_branch_converted = match else_branch {
-                Some(b) => Some(&**b),
-                None => None,
-            };
-            visitor.visit_if(cond, then_branch, else_branch_converted)
+            visitor.visit_if(cond, then_branch, else_branch.as_deref())
         }
-        Stmt::While(cond, body) => visitor.visit_while(cond, body),
+        Stmt::Import(path, names) => visitor.visit_import(path, names.as_deref()),
+        Stmt::While(cond, body, inc) => visitor.visit_while(cond, body, inc.as_ref()),
         Stmt::Print(expr) => visitor.visit_print(expr),
         Stmt::Return(keyword, value) => visitor.visit_return(keyword, value.as_ref()),
+        Stmt::Throw(keyword, value) => visitor.visit_throw(keyword, value),
+        Stmt::Try(body, catch, finally) => visitor.visit_try(
+            body,
+            catch
+                .as_ref()
+                .map(|(name, handler)| (name, handler.as_ref())),
+            finally.as_deref(),
+        ),
         Stmt::Var(name, init) => visitor.visit_var_stmt(name, init.as_ref()),
     }
 }
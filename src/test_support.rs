@@ -0,0 +1,53 @@
+// Helpers shared by the tests of several modules.
+
+use crate::lox::{Backend, Lox};
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+// Runs `f` against a fresh `Lox` on each backend.
+pub fn on_each_backend(f: impl Fn(Lox, Backend)) {
+    for backend in [Backend::TreeWalk, Backend::Bytecode].iter() {
+        f(Lox::with_backend(*backend), *backend);
+    }
+}
+
+// Runs `f` on a thread with a main thread's stack. Test threads get 2MB,
+// which deep recursion overflows in a debug build.
+pub fn on_main_stack(f: impl FnOnce() + Send + 'static) {
+    let run = std::thread::Builder::new().stack_size(8 << 20).spawn(f);
+    run.unwrap().join().unwrap();
+}
+
+// A sink whose contents stay readable after it is handed to `Lox`.
+#[derive(Clone, Default)]
+pub struct Captured(Rc<RefCell<Vec<u8>>>);
+
+impl Captured {
+    pub fn contents(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// A sink that fails every write, like a closed pipe.
+pub struct Closed;
+
+impl Write for Closed {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::BrokenPipe, "pipe closed"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -10,6 +10,9 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
+    Colon,
     Comma,
     Dot,
     Minus,
@@ -22,6 +25,7 @@ pub enum TokenType {
     BangEqual,
     Equal,
     EqualEqual,
+    Arrow,
     Greater,
     GreaterEqual,
     Less,
@@ -32,19 +36,26 @@ pub enum TokenType {
     Number,
     // Keywords
     And,
+    Break,
+    Catch,
     Class,
+    Continue,
     Else,
     False,
+    Finally,
     Fun,
     For,
     If,
+    Import,
     Nil,
     Or,
     Print,
     Return,
     Super,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
     Eof,
@@ -55,7 +66,18 @@ pub struct Token {
     pub token_type: TokenType,
     pub lexeme: String,
     pub lit: Option<Literal>,
+    pub span: Span,
+}
+
+// Location of a token in the source. `start` and `end` are byte offsets,
+// `line` and `column` are 1-based and count characters.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Span {
+    pub file_id: usize,
+    pub start: usize,
+    pub end: usize,
     pub line: usize,
+    pub column: usize,
 }
 
 #[derive(Clone, Debug, PartialEq)]
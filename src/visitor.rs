@@ -1,14 +1,14 @@
-use crate::error::Error;
-use crate::expr::{walk_expr, Expr};
+use crate::error::LoxError;
+use crate::expr::{walk_expr, Expr, Slot};
 use crate::lox_value::LoxValue;
-use crate::stmt::{walk_stmt, Stmt};
+use crate::stmt::{walk_stmt, Flow, Stmt};
 use crate::token::{Literal, Token};
 
 use std::cell::Cell;
 use std::rc::Rc;
 
 pub trait Visitor {
-    fn visit_expr(&mut self, expr: &Expr) -> Result<LoxValue, Error> {
+    fn visit_expr(&mut self, expr: &Expr) -> Result<LoxValue, LoxError> {
         walk_expr(self, expr)
     }
 
@@ -16,32 +16,59 @@ pub trait Visitor {
         &mut self,
         left: &Token,
         right: &Expr,
-        distance: Rc<Cell<i32>>,
-    ) -> Result<LoxValue, Error>;
-    fn visit_binary(&mut self, left: &Expr, op: &Token, right: &Expr) -> Result<LoxValue, Error>;
-    fn visit_grouping(&mut self, expr: &Expr) -> Result<LoxValue, Error>;
-    fn visit_literal(&mut self, lit: &Literal) -> Result<LoxValue, Error>;
-    fn visit_logical(&mut self, left: &Expr, op: &Token, right: &Expr) -> Result<LoxValue, Error>;
-    fn visit_unary(&mut self, token: &Token, expr: &Expr) -> Result<LoxValue, Error>;
-    fn visit_var_expr(&mut self, token: &Token, distance: Rc<Cell<i32>>)
-        -> Result<LoxValue, Error>;
+        slot: Rc<Cell<Slot>>,
+    ) -> Result<LoxValue, LoxError>;
+    fn visit_binary(&mut self, left: &Expr, op: &Token, right: &Expr)
+        -> Result<LoxValue, LoxError>;
+    fn visit_grouping(&mut self, expr: &Expr) -> Result<LoxValue, LoxError>;
+    fn visit_literal(&mut self, lit: &Literal) -> Result<LoxValue, LoxError>;
+    fn visit_logical(
+        &mut self,
+        left: &Expr,
+        op: &Token,
+        right: &Expr,
+    ) -> Result<LoxValue, LoxError>;
+    fn visit_unary(&mut self, token: &Token, expr: &Expr) -> Result<LoxValue, LoxError>;
+    fn visit_var_expr(&mut self, token: &Token, slot: Rc<Cell<Slot>>)
+        -> Result<LoxValue, LoxError>;
     fn visit_call(
         &mut self,
         callee: &Expr,
         paren: &Token,
         args: Vec<Expr>,
-    ) -> Result<LoxValue, Error>;
-    fn visit_get(&mut self, expr: &Expr, name: &Token) -> Result<LoxValue, Error>;
-    fn visit_set(&mut self, expr: &Expr, name: &Token, value: &Expr) -> Result<LoxValue, Error>;
-    fn visit_this(&mut self, token: &Token, distance: Rc<Cell<i32>>) -> Result<LoxValue, Error>;
+    ) -> Result<LoxValue, LoxError>;
+    fn visit_get(&mut self, expr: &Expr, name: &Token) -> Result<LoxValue, LoxError>;
+    fn visit_set(&mut self, expr: &Expr, name: &Token, value: &Expr) -> Result<LoxValue, LoxError>;
+    fn visit_lambda(
+        &mut self,
+        name: &Token,
+        args: &[Token],
+        body: &Stmt,
+    ) -> Result<LoxValue, LoxError>;
+    fn visit_list(&mut self, bracket: &Token, items: &[Expr]) -> Result<LoxValue, LoxError>;
+    fn visit_map(&mut self, brace: &Token, entries: &[(Expr, Expr)]) -> Result<LoxValue, LoxError>;
+    fn visit_index(
+        &mut self,
+        expr: &Expr,
+        bracket: &Token,
+        index: &Expr,
+    ) -> Result<LoxValue, LoxError>;
+    fn visit_set_index(
+        &mut self,
+        expr: &Expr,
+        bracket: &Token,
+        index: &Expr,
+        value: &Expr,
+    ) -> Result<LoxValue, LoxError>;
+    fn visit_this(&mut self, token: &Token, slot: Rc<Cell<Slot>>) -> Result<LoxValue, LoxError>;
     fn visit_super(
         &mut self,
         keyword: &Token,
         method: &Token,
-        distance: Rc<Cell<i32>>,
-    ) -> Result<LoxValue, Error>;
+        slot: Rc<Cell<Slot>>,
+    ) -> Result<LoxValue, LoxError>;
 
-    fn visit_stmt(&mut self, stmt: &Stmt) -> Result<LoxValue, Error> {
+    fn visit_stmt(&mut self, stmt: &Stmt) -> Result<Flow, LoxError> {
         walk_stmt(self, stmt)
     }
 
@@ -50,23 +77,34 @@ pub trait Visitor {
         name: &Token,
         superclass: Option<Expr>,
         methods: Vec<Stmt>,
-    ) -> Result<LoxValue, Error>;
-    fn visit_expr_stmt(&mut self, expr: &Expr) -> Result<LoxValue, Error>;
-    fn visit_print(&mut self, expr: &Expr) -> Result<LoxValue, Error>;
-    fn visit_block(&mut self, stmts: Vec<Stmt>) -> Result<LoxValue, Error>;
-    fn visit_func(
-        &mut self,
-        name: &Token,
-        args: Vec<Token>,
-        body: &Stmt,
-    ) -> Result<LoxValue, Error>;
+    ) -> Result<Flow, LoxError>;
+    fn visit_expr_stmt(&mut self, expr: &Expr) -> Result<Flow, LoxError>;
+    fn visit_print(&mut self, expr: &Expr) -> Result<Flow, LoxError>;
+    fn visit_block(&mut self, stmts: Vec<Stmt>) -> Result<Flow, LoxError>;
+    fn visit_func(&mut self, name: &Token, args: Vec<Token>, body: &Stmt)
+        -> Result<Flow, LoxError>;
     fn visit_if(
         &mut self,
         cond: &Expr,
         then_branch: &Stmt,
         else_branch: Option<&Stmt>,
-    ) -> Result<LoxValue, Error>;
-    fn visit_return(&mut self, keyword: &Token, value: Option<&Expr>) -> Result<LoxValue, Error>;
-    fn visit_while(&mut self, cond: &Expr, body: &Stmt) -> Result<LoxValue, Error>;
-    fn visit_var_stmt(&mut self, token: &Token, expr: Option<&Expr>) -> Result<LoxValue, Error>;
+    ) -> Result<Flow, LoxError>;
+    fn visit_import(&mut self, path: &Token, names: Option<&[Token]>) -> Result<Flow, LoxError>;
+    fn visit_return(&mut self, keyword: &Token, value: Option<&Expr>) -> Result<Flow, LoxError>;
+    fn visit_throw(&mut self, keyword: &Token, value: &Expr) -> Result<Flow, LoxError>;
+    fn visit_try(
+        &mut self,
+        body: &Stmt,
+        catch: Option<(&Token, &Stmt)>,
+        finally: Option<&Stmt>,
+    ) -> Result<Flow, LoxError>;
+    fn visit_while(
+        &mut self,
+        cond: &Expr,
+        body: &Stmt,
+        inc: Option<&Expr>,
+    ) -> Result<Flow, LoxError>;
+    fn visit_break(&mut self, keyword: &Token) -> Result<Flow, LoxError>;
+    fn visit_continue(&mut self, keyword: &Token) -> Result<Flow, LoxError>;
+    fn visit_var_stmt(&mut self, token: &Token, expr: Option<&Expr>) -> Result<Flow, LoxError>;
 }
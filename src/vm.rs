@@ -0,0 +1,728 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Write;
+use std::rc::Rc;
+
+use crate::chunk::{FunctionProto, OpCode};
+use crate::diagnostic::Label;
+use crate::error::{LoxError, RuntimeErrorKind};
+use crate::gc::Heap;
+use crate::limits::{Budget, Limit};
+use crate::lox_class::{error_class, LoxClass, Method};
+use crate::lox_closure::{LoxClosure, Upvalue};
+use crate::lox_map::LoxMap;
+use crate::lox_value::LoxValue;
+use crate::native_fn::{builtins, Streams};
+use crate::token::Span;
+
+// Calls that may nest however the limits are set. Frames live on the heap,
+// so this can be far above the tree-walker's safe depth.
+const FRAMES_MAX: usize = 1 << 16;
+
+// Stack-based virtual machine running the compiler's bytecode. Values, error
+// kinds and backtraces are the same as the tree-walker's.
+pub struct Vm {
+    stack: Vec<LoxValue>,
+    frames: Vec<Frame>,
+    // Globals of each module, by id. The main program is module 0.
+    pub modules: Vec<HashMap<String, LoxValue>>,
+    // Upvalues still pointing into the stack, closed when their slot is
+    // popped.
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    pub heap: Heap,
+    // Where `print` writes and the natives read and write.
+    pub streams: Streams,
+    pub budget: Budget,
+    handlers: Vec<Handler>,
+    // Errors set aside while a `finally` block runs, with the value of the
+    // `throw` behind each, if any. A `TryFinally` handler pushes the index
+    // of its entry for `Rethrow`.
+    unwinding: Vec<(LoxError, Option<LoxValue>)>,
+    // Value of the `throw` being unwound, if any.
+    thrown: Option<LoxValue>,
+    // The global `Error` as the VM started, kept in case a script rebinds
+    // the name.
+    error_class: Rc<LoxClass>,
+}
+
+// An active `try`, entered with `Try` or `TryFinally`.
+struct Handler {
+    // Number of frames when it was entered; the last one holds the `try`.
+    frames: usize,
+    stack: usize,
+    // Instruction to resume at in that frame.
+    target: usize,
+    finally: bool,
+}
+
+struct Frame {
+    closure: Rc<LoxClosure>,
+    ip: usize,
+    // Stack index of slot 0 of the frame.
+    base: usize,
+}
+
+impl Default for Vm {
+    fn default() -> Vm {
+        Vm::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Vm {
+        let error_class = Rc::new(error_class());
+        let streams = Streams::default();
+        Vm {
+            stack: Vec::new(),
+            frames: Vec::new(),
+            modules: vec![builtins(&error_class, &streams)],
+            open_upvalues: Vec::new(),
+            heap: Heap::new(),
+            streams,
+            budget: Budget::default(),
+            handlers: Vec::new(),
+            unwinding: Vec::new(),
+            thrown: None,
+            error_class,
+        }
+    }
+
+    // Adds a module with nothing but the builtins in its globals and returns
+    // its id.
+    pub fn add_module(&mut self) -> usize {
+        self.modules
+            .push(builtins(&self.error_class, &self.streams));
+        self.modules.len() - 1
+    }
+
+    // Runs a compiled script and returns the value it returns. The module
+    // it runs in is the one it was compiled for.
+    pub fn interpret(&mut self, script: Rc<FunctionProto>) -> Result<LoxValue, LoxError> {
+        let closure = Rc::new(LoxClosure {
+            proto: script,
+            upvalues: Vec::new(),
+        });
+        self.stack.push(LoxValue::Closure(closure.clone()));
+        self.frames.push(Frame {
+            closure,
+            ip: 0,
+            base: self.stack.len() - 1,
+        });
+
+        let value = self.run();
+        if value.is_err() {
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+            self.handlers.clear();
+            self.thrown = None;
+        }
+        self.unwinding.clear();
+        value
+    }
+
+    fn run(&mut self) -> Result<LoxValue, LoxError> {
+        loop {
+            let (op, span) = {
+                let frame = self.frame_mut();
+                let chunk = &frame.closure.proto.chunk;
+                let op = chunk.code[frame.ip];
+                let span = chunk.spans[frame.ip];
+                frame.ip += 1;
+                (op, span)
+            };
+            match self.budget.step().and_then(|()| self.execute(op, span)) {
+                Ok(Some(value)) => return Ok(value),
+                Ok(None) => {}
+                Err(err) if err.is_uncatchable() => return Err(self.backtrace(err.at(span), None)),
+                Err(err) => match self.handlers.pop() {
+                    Some(handler) => self.catch(handler, err.at(span)),
+                    None => return Err(self.backtrace(err.at(span), None)),
+                },
+            }
+        }
+    }
+
+    // Executes one instruction. Returns the script's value once its frame
+    // returns.
+    fn execute(&mut self, op: OpCode, span: Span) -> Result<Option<LoxValue>, LoxError> {
+        match op {
+            OpCode::Constant(index) => {
+                let value = self.constant(index);
+                self.stack.push(value);
+            }
+            OpCode::Nil => self.stack.push(LoxValue::Nil),
+            OpCode::True => self.stack.push(LoxValue::Bool(true)),
+            OpCode::False => self.stack.push(LoxValue::Bool(false)),
+            OpCode::Pop => {
+                self.pop();
+            }
+            OpCode::GetLocal(slot) => {
+                let value = self.stack[self.frame().base + slot as usize].clone();
+                self.stack.push(value);
+            }
+            OpCode::SetLocal(slot) => {
+                let index = self.frame().base + slot as usize;
+                self.stack[index] = self.peek(0).clone();
+            }
+            OpCode::GetGlobal(index) => {
+                let name = self.name(index);
+                match self.globals().get(&name) {
+                    Some(value) => {
+                        let value = value.clone();
+                        self.stack.push(value);
+                    }
+                    None => {
+                        return Err(LoxError::runtime(RuntimeErrorKind::UndefinedVariable(name)))
+                    }
+                }
+            }
+            OpCode::DefineGlobal(index) => {
+                let name = self.name(index);
+                let value = self.pop();
+                self.globals().insert(name, value);
+            }
+            OpCode::SetGlobal(index) => {
+                let name = self.name(index);
+                let value = self.peek(0).clone();
+                match self.globals().get_mut(&name) {
+                    Some(global) => *global = value,
+                    None => {
+                        return Err(LoxError::runtime(RuntimeErrorKind::UndefinedVariable(name)))
+                    }
+                }
+            }
+            OpCode::GetUpvalue(index) => {
+                let upvalue = self.frame().closure.upvalues[index as usize].clone();
+                let value = match &*upvalue.borrow() {
+                    Upvalue::Open(slot) => self.stack[*slot].clone(),
+                    Upvalue::Closed(value) => value.clone(),
+                };
+                self.stack.push(value);
+            }
+            OpCode::SetUpvalue(index) => {
+                let upvalue = self.frame().closure.upvalues[index as usize].clone();
+                let value = self.peek(0).clone();
+                let mut upvalue = upvalue.borrow_mut();
+                match &mut *upvalue {
+                    Upvalue::Open(slot) => self.stack[*slot] = value,
+                    Upvalue::Closed(closed) => *closed = value,
+                }
+            }
+            OpCode::GetProperty(index) => {
+                let name = self.name(index);
+                let value = match self.pop() {
+                    LoxValue::Instance(instance) => {
+                        let field = instance.borrow().field(&name);
+                        match field {
+                            Some(value) => value,
+                            None => {
+                                let method = instance.borrow().class().find_method(&name);
+                                match method {
+                                    Some(method) => {
+                                        method.get(LoxValue::Instance(instance), &mut self.heap)?
+                                    }
+                                    None => {
+                                        return Err(LoxError::runtime(
+                                            RuntimeErrorKind::UndefinedProperty(name),
+                                        ))
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    value @ (LoxValue::List(_) | LoxValue::Map(_)) => {
+                        match value.builtin_method(&name) {
+                            Some(method) => method,
+                            None => {
+                                return Err(LoxError::runtime(RuntimeErrorKind::UndefinedProperty(
+                                    name,
+                                )))
+                            }
+                        }
+                    }
+                    value => {
+                        return Err(LoxError::runtime(RuntimeErrorKind::NotAnInstance {
+                            actual: value.type_name(),
+                        }))
+                    }
+                };
+                self.stack.push(value);
+            }
+            OpCode::SetProperty(index) => {
+                let name = self.name(index);
+                let value = self.pop();
+                match self.pop() {
+                    LoxValue::Instance(instance) => {
+                        instance.borrow_mut().set_field(name, value.clone());
+                    }
+                    receiver => {
+                        return Err(LoxError::runtime(RuntimeErrorKind::NotAnInstance {
+                            actual: receiver.type_name(),
+                        }))
+                    }
+                }
+                self.stack.push(value);
+            }
+            OpCode::GetIndex => {
+                let index = self.pop();
+                let value = self.pop().get_index(&index)?;
+                self.stack.push(value);
+            }
+            OpCode::SetIndex => {
+                let value = self.pop();
+                let index = self.pop();
+                self.pop().set_index(&index, value.clone())?;
+                self.stack.push(value);
+            }
+            OpCode::List(count) => {
+                let items = self.stack.split_off(self.stack.len() - count as usize);
+                let list = self.heap.list(items);
+                self.stack.push(LoxValue::List(list));
+            }
+            OpCode::Map(count) => {
+                let items = self.stack.split_off(self.stack.len() - 2 * count as usize);
+                let mut map = LoxMap::new();
+                for pair in items.chunks(2) {
+                    map.insert(pair[0].clone(), pair[1].clone())?;
+                }
+                let map = self.heap.map(map);
+                self.stack.push(LoxValue::Map(map));
+            }
+            OpCode::GetSuper(index) => {
+                let name = self.name(index);
+                let superclass = self.pop();
+                let this = self.pop();
+                let method = match superclass {
+                    LoxValue::Class(superclass) => superclass.inner.find_method(&name),
+                    _ => unreachable!("`super` is always bound to a class"),
+                };
+                match method {
+                    Some(method) => {
+                        let value = method.get(this, &mut self.heap)?;
+                        self.stack.push(value);
+                    }
+                    None => {
+                        return Err(LoxError::runtime(RuntimeErrorKind::UndefinedProperty(name)))
+                    }
+                }
+            }
+            OpCode::Equal => self.binary(LoxValue::equal_equal)?,
+            OpCode::NotEqual => self.binary(LoxValue::bang_equal)?,
+            OpCode::Greater => self.binary(LoxValue::greater)?,
+            OpCode::GreaterEqual => self.binary(LoxValue::greater_equal)?,
+            OpCode::Less => self.binary(LoxValue::less)?,
+            OpCode::LessEqual => self.binary(LoxValue::less_equal)?,
+            OpCode::Add => self.binary(LoxValue::plus)?,
+            OpCode::Subtract => self.binary(LoxValue::subtract)?,
+            OpCode::Multiply => self.binary(LoxValue::multiply)?,
+            OpCode::Divide => self.binary(LoxValue::divide)?,
+            OpCode::Not => {
+                let value = self.pop().negate()?;
+                self.stack.push(value);
+            }
+            OpCode::Negate => {
+                let value = self.pop().negate_number()?;
+                self.stack.push(value);
+            }
+            OpCode::Print => {
+                let value = self.pop();
+                writeln!(self.streams.output.borrow_mut(), "{}", value)
+                    .map_err(|err| LoxError::runtime(RuntimeErrorKind::Output(err.to_string())))?;
+            }
+            OpCode::Jump(offset) => self.frame_mut().ip += offset as usize,
+            OpCode::JumpIfFalse(offset) => {
+                if self.peek(0).truthy()? == LoxValue::Bool(false) {
+                    self.frame_mut().ip += offset as usize;
+                }
+            }
+            OpCode::Loop(offset) => self.frame_mut().ip -= offset as usize,
+            OpCode::Call(argc) => self.call_value(argc as usize, span)?,
+            OpCode::Closure(index) => {
+                let frame = self.frame();
+                let proto = frame.closure.proto.chunk.functions[index as usize].clone();
+                let base = frame.base;
+                let mut upvalues = Vec::with_capacity(proto.upvalues.len());
+                for upvalue in proto.upvalues.iter() {
+                    let upvalue = if upvalue.is_local {
+                        self.capture_upvalue(base + upvalue.index as usize)
+                    } else {
+                        self.frame().closure.upvalues[upvalue.index as usize].clone()
+                    };
+                    upvalues.push(upvalue);
+                }
+                self.stack
+                    .push(LoxValue::Closure(Rc::new(LoxClosure { proto, upvalues })));
+            }
+            OpCode::CloseUpvalue => {
+                self.close_upvalues(self.stack.len() - 1);
+                self.pop();
+            }
+            OpCode::Return => {
+                let value = self.pop();
+                let frame = self.frames.pop().expect("a frame is running");
+                while let Some(handler) = self.handlers.last() {
+                    if handler.frames <= self.frames.len() {
+                        break;
+                    }
+                    self.handlers.pop();
+                }
+                self.close_upvalues(frame.base);
+                self.stack.truncate(frame.base);
+                if self.frames.is_empty() {
+                    return Ok(Some(value));
+                }
+                self.stack.push(value);
+            }
+            OpCode::Try(offset) | OpCode::TryFinally(offset) => {
+                let handler = Handler {
+                    frames: self.frames.len(),
+                    stack: self.stack.len(),
+                    target: self.frame().ip + offset as usize,
+                    finally: matches!(op, OpCode::TryFinally(_)),
+                };
+                self.handlers.push(handler);
+            }
+            OpCode::EndTry => {
+                self.handlers.pop();
+            }
+            OpCode::Throw => {
+                let value = self.pop();
+                let kind = RuntimeErrorKind::thrown(&value);
+                self.thrown = Some(value);
+                return Err(LoxError::runtime(kind));
+            }
+            OpCode::Rethrow => {
+                let index = match self.pop() {
+                    LoxValue::Number(index) => index as usize,
+                    value => unreachable!("rethrowing {:?}", value),
+                };
+                // Entries past this one were left by `finally` blocks that
+                // jumped out instead of rethrowing.
+                self.unwinding.truncate(index + 1);
+                let (err, thrown) = self.unwinding.pop().expect("error was set aside");
+                self.thrown = thrown;
+                return Err(err);
+            }
+            OpCode::Class(index, count) => {
+                let methods = self.methods(count as usize);
+                let class = LoxClass::new(self.name(index), None, methods);
+                self.stack.push(LoxValue::Class(Rc::new(class)));
+            }
+            OpCode::Subclass(index, count) => {
+                let methods = self.methods(count as usize);
+                let superclass = match self.peek(0) {
+                    LoxValue::Class(superclass) => superclass.clone(),
+                    superclass => {
+                        return Err(LoxError::runtime(RuntimeErrorKind::SuperclassNotClass {
+                            actual: superclass.type_name(),
+                        }))
+                    }
+                };
+                let class = LoxClass::new(self.name(index), Some(superclass), methods);
+                self.stack.push(LoxValue::Class(Rc::new(class)));
+            }
+        }
+        Ok(None)
+    }
+
+    // Unwinds to `handler` and resumes there with what it expects on the
+    // stack: the value for `catch`, or the index of the set-aside error.
+    fn catch(&mut self, handler: Handler, err: LoxError) {
+        let value = if handler.finally {
+            // The backtrace is taken now, while the frames raising the error
+            // are still there.
+            let err = self.backtrace(err, None);
+            self.unwinding.push((err, self.thrown.take()));
+            LoxValue::Number((self.unwinding.len() - 1) as f64)
+        } else {
+            match err {
+                LoxError::Runtime(err) => {
+                    self.heap
+                        .error_value(&err, &mut self.thrown, &self.error_class)
+                }
+                err => unreachable!("{} raised at runtime", err),
+            }
+        };
+        self.frames.truncate(handler.frames);
+        self.close_upvalues(handler.stack);
+        self.stack.truncate(handler.stack);
+        self.stack.push(value);
+        self.frame_mut().ip = handler.target;
+    }
+
+    // Globals of the module of the running function.
+    fn globals(&mut self) -> &mut HashMap<String, LoxValue> {
+        let module = self.frame().closure.proto.module;
+        &mut self.modules[module]
+    }
+
+    fn frame(&self) -> &Frame {
+        self.frames.last().expect("a frame is running")
+    }
+
+    fn frame_mut(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("a frame is running")
+    }
+
+    fn constant(&self, index: u16) -> LoxValue {
+        self.frame().closure.proto.chunk.constants[index as usize].clone()
+    }
+
+    fn name(&self, index: u16) -> String {
+        match self.constant(index) {
+            LoxValue::Str(name) => name,
+            value => unreachable!("identifier constant {:?}", value),
+        }
+    }
+
+    fn pop(&mut self) -> LoxValue {
+        self.stack.pop().expect("stack underflow")
+    }
+
+    fn peek(&self, distance: usize) -> &LoxValue {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    fn binary(
+        &mut self,
+        op: fn(&LoxValue, LoxValue) -> Result<LoxValue, LoxError>,
+    ) -> Result<(), LoxError> {
+        let right = self.pop();
+        let left = self.pop();
+        self.stack.push(op(&left, right)?);
+        Ok(())
+    }
+
+    // Pops the method name and closure pairs pushed for a class declaration.
+    fn methods(&mut self, count: usize) -> HashMap<String, Method> {
+        let pairs = self.stack.split_off(self.stack.len() - 2 * count);
+        let mut methods = HashMap::new();
+        for pair in pairs.chunks(2) {
+            match pair {
+                [LoxValue::Str(name), LoxValue::Closure(closure)] => {
+                    methods.insert(name.clone(), Method::Closure(closure.clone()));
+                }
+                _ => unreachable!("class methods are name and closure pairs"),
+            }
+        }
+        methods
+    }
+
+    // Calls the value sitting below `argc` arguments on the stack.
+    fn call_value(&mut self, argc: usize, span: Span) -> Result<(), LoxError> {
+        let slot = self.stack.len() - 1 - argc;
+        match self.stack[slot].clone() {
+            LoxValue::Closure(closure) => self.call_closure(closure, argc),
+            LoxValue::BoundMethod(bound) => {
+                self.stack[slot] = bound.receiver.clone();
+                self.call_closure(bound.method.clone(), argc)
+            }
+            LoxValue::Class(class) => {
+                let initializer = class.inner.find_method(&"init".to_string());
+                let arity = initializer.as_ref().map_or(0, |init| init.arity());
+                if argc != arity {
+                    return Err(LoxError::runtime(RuntimeErrorKind::Arity {
+                        callee: class.inner.name.clone(),
+                        expected: arity,
+                        got: argc,
+                    }));
+                }
+                let instance = self.heap.instance(class.instantiate());
+                self.stack[slot] = LoxValue::Instance(instance);
+                match initializer {
+                    Some(Method::Closure(initializer)) => self.call_closure(initializer, argc),
+                    Some(Method::Function(_)) => {
+                        unreachable!("tree-walker initializer in the VM")
+                    }
+                    Some(Method::Foreign(initializer)) => {
+                        let name = format!("{}.init", class.inner.name);
+                        let args = self.stack.split_off(slot + 1);
+                        let instance = self.stack[slot].clone();
+                        (initializer.function)(&mut self.heap, &instance, &args).map_err(
+                            |err| self.backtrace(err.at(span), Some(Label::new(span, name))),
+                        )?;
+                        Ok(())
+                    }
+                    None => Ok(()),
+                }
+            }
+            LoxValue::NativeMethod(method) => {
+                let name = format!("{}.{}", method.receiver_name(), method.name);
+                if argc != method.arity {
+                    return Err(LoxError::runtime(RuntimeErrorKind::Arity {
+                        callee: method.name.clone(),
+                        expected: method.arity,
+                        got: argc,
+                    }));
+                }
+                let args = self.stack.split_off(slot + 1);
+                let value = (method.function)(&mut self.heap, &method.receiver, &args)
+                    .map_err(|err| self.backtrace(err.at(span), Some(Label::new(span, name))))?;
+                self.stack.truncate(slot);
+                self.stack.push(value);
+                Ok(())
+            }
+            LoxValue::Native(native) => {
+                if argc != native.arity {
+                    return Err(LoxError::runtime(RuntimeErrorKind::Arity {
+                        callee: native.name.clone(),
+                        expected: native.arity,
+                        got: argc,
+                    }));
+                }
+                let args = self.stack.split_off(slot + 1);
+                let value = (native.function)(&mut self.heap, &args).map_err(|err| {
+                    self.backtrace(err.at(span), Some(Label::new(span, native.name.clone())))
+                })?;
+                self.stack.truncate(slot);
+                self.stack.push(value);
+                Ok(())
+            }
+            callee => Err(LoxError::runtime(RuntimeErrorKind::NotCallable {
+                actual: callee.type_name(),
+            })),
+        }
+    }
+
+    fn call_closure(&mut self, closure: Rc<LoxClosure>, argc: usize) -> Result<(), LoxError> {
+        if argc != closure.proto.arity {
+            return Err(LoxError::runtime(RuntimeErrorKind::Arity {
+                callee: closure.proto.name.clone(),
+                expected: closure.proto.arity,
+                got: argc,
+            }));
+        }
+        // The script's own frame doesn't count, as in the tree-walker.
+        self.budget.call(self.frames.len() - 1)?;
+        // Even without a depth limit, runaway recursion mustn't grow the
+        // frames until the process runs out of memory.
+        if self.frames.len() > FRAMES_MAX {
+            return Err(LoxError::runtime(RuntimeErrorKind::LimitExceeded(
+                Limit::Depth(FRAMES_MAX),
+            )));
+        }
+        self.frames.push(Frame {
+            closure,
+            ip: 0,
+            base: self.stack.len() - 1 - argc,
+        });
+        Ok(())
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        for upvalue in self.open_upvalues.iter() {
+            if let Upvalue::Open(open) = *upvalue.borrow() {
+                if open == slot {
+                    return upvalue.clone();
+                }
+            }
+        }
+        let upvalue = self.heap.upvalue(Upvalue::Open(slot));
+        self.open_upvalues.push(upvalue.clone());
+        upvalue
+    }
+
+    // Moves the values of every open upvalue at or above `from` off the stack.
+    fn close_upvalues(&mut self, from: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let slot = match *upvalue.borrow() {
+                Upvalue::Open(slot) => slot,
+                Upvalue::Closed(_) => return false,
+            };
+            if slot < from {
+                return true;
+            }
+            *upvalue.borrow_mut() = Upvalue::Closed(stack[slot].clone());
+            false
+        });
+    }
+
+    // Same shape as the tree-walker's backtrace: innermost frame first, each
+    // at the instruction it was executing, ending with the script. `native`
+    // is the native function that raised the error, if any. The innermost
+    // frame is placed where the error was raised, which is not the current
+    // instruction when a `finally` block rethrows it.
+    fn backtrace(&self, mut err: LoxError, native: Option<Label>) -> LoxError {
+        if let LoxError::Runtime(ref mut err) = err {
+            if err.backtrace.is_empty() {
+                let mut backtrace: Vec<Label> = native.into_iter().collect();
+                let mut raised = err.span;
+                for (depth, frame) in self.frames.iter().enumerate().rev() {
+                    let span = raised
+                        .take()
+                        .unwrap_or(frame.closure.proto.chunk.spans[frame.ip - 1]);
+                    let name = if depth == 0 {
+                        "<script>".to_string()
+                    } else {
+                        frame.closure.name()
+                    };
+                    backtrace.push(Label::new(span, name));
+                }
+                if backtrace.len() > 1 {
+                    err.backtrace = backtrace;
+                }
+            }
+        }
+        err
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lox::{Backend, Lox};
+    use crate::lox_value::LoxValue;
+
+    #[test]
+    fn test_backends_agree() {
+        let programs = [
+            "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }\nfib(15);",
+            "fun counter() { var i = 0; fun inc() { i = i + 1; return i; } return inc; }
+var c = counter(); c(); c();",
+            "var fs = nil;
+{ var x = 1; fun get() { return x; } fs = get; x = 2; }
+fs();",
+            "class A { init(x) { this.x = x; } name() { return \"A\" + this.x; } }
+class B < A { name() { return \"B\" + super.name(); } }
+B(\"!\").name();",
+            "var s = \"\"; for (var i = 0; i < 3; i = i + 1) { if (i) s = s + \"y\"; else s = s + \"n\"; } s;",
+            "var a; (a = 3) + 1;",
+            "nil or \"default\";",
+            "class Point {} var p = Point(); p.x = 1;",
+        ];
+        for program in programs.iter() {
+            let mut tree = Lox::with_backend(Backend::TreeWalk);
+            let mut vm = Lox::with_backend(Backend::Bytecode);
+            let expected = tree.run(program).unwrap();
+            assert_eq!(vm.run(program).unwrap(), expected, "{}", program);
+        }
+    }
+
+    #[test]
+    fn test_bytecode_runtime_error() {
+        let source = "fun inner() {\n  return nil + 1;\n}\nfun outer() {\n  inner();\n}\nouter();";
+        let mut tree = Lox::with_backend(Backend::TreeWalk);
+        let mut vm = Lox::with_backend(Backend::Bytecode);
+        let expected = tree.run_source("main.lox", source).unwrap_err();
+        let err = vm.run_source("main.lox", source).unwrap_err();
+        assert_eq!(err, expected);
+        assert_eq!(
+            vm.render(&err),
+            "error[E0401]: operands of `+` must be two numbers or two strings, got nil and number
+ --> main.lox:2:14
+  |
+2 |   return nil + 1;
+  |              ^
+  = backtrace:
+      at inner (main.lox:2)
+      at outer (main.lox:5)
+      at <script> (main.lox:7)"
+        );
+
+        // The VM unwinds its stack on errors and keeps its globals.
+        vm.run("var kept = 1;").unwrap();
+        assert!(vm.run("outer();").is_err());
+        assert_eq!(vm.run("kept + 1;").unwrap(), LoxValue::Number(2f64));
+    }
+}